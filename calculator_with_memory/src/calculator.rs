@@ -0,0 +1,424 @@
+use crate::error::CalcError;
+use crate::expression::{Expr, Statement};
+use crate::format::Format;
+use crate::history::History;
+use crate::memory::{Memory, MemoryOp, UserFunction};
+use crate::number::NumericMode;
+use crate::parser::parse_statement;
+use crate::solver::SolverSettings;
+use crate::token::{tokenize, Span};
+use crate::value::Value;
+
+/// How a [`Calculator`] represents and prints numbers.
+///
+/// ```
+/// use calculator_with_memory::{Calculator, Config};
+/// use calculator_with_memory::number::NumericMode;
+///
+/// let mut calc = Calculator::with_config(Config {
+///     mode: NumericMode::Rational,
+///     ..Config::default()
+/// });
+/// assert_eq!(calc.eval("1/3 + 1/4").unwrap().to_string(), "7/12");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Config {
+    /// The number representation used for literals and results.
+    pub mode: NumericMode,
+    /// How [`Calculator::format`] writes results.
+    pub format: Format,
+    /// The tolerance and iteration cap of `solve`.
+    pub solver: SolverSettings,
+}
+
+/// What running one line did, for the caller to report.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// An expression or a recalled slot produced a value, which is now the
+    /// latest result in the history.
+    Value(Value),
+    /// `let` stored a value, which is also the latest result.
+    Assigned { name: String, value: Value },
+    /// `fn` defined a function.
+    Defined {
+        name: String,
+        function: UserFunction,
+    },
+    /// A memory operation left a slot holding this value.
+    Updated { name: String, value: Value },
+    /// `memX!` removed a slot.
+    Cleared(String),
+    /// `memX<>Y` exchanged two slots; each name comes with its new value.
+    Swapped {
+        first: (String, Value),
+        second: (String, Value),
+    },
+}
+
+impl Outcome {
+    /// Whether the line changed memory, so that a saved copy is stale.
+    pub fn changes_memory(&self) -> bool {
+        !matches!(self, Outcome::Value(_))
+    }
+}
+
+/// A calculator session: memory slots, user functions and numbered results,
+/// driven one line of input at a time.
+///
+/// ```
+/// use calculator_with_memory::Calculator;
+///
+/// let mut calc = Calculator::new();
+/// assert_eq!(calc.eval("2 + 3 * 4").unwrap().to_string(), "14");
+/// calc.eval("let rate = 1.5").unwrap();
+/// assert_eq!(calc.eval("rate * ans").unwrap().to_string(), "2.25");
+/// assert_eq!(calc.eval("$1 + 1").unwrap().to_string(), "15");
+///
+/// let error = calc.eval("1 / (rate - 1.5)").unwrap_err();
+/// assert_eq!(error.to_string(), "Division by zero");
+/// ```
+#[derive(Debug, Default)]
+pub struct Calculator {
+    memory: Memory,
+    history: History,
+    config: Config,
+}
+
+impl Calculator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let mut memory = Memory::with_mode(config.mode);
+        memory.set_solver(config.solver);
+        Self {
+            memory,
+            history: History::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Changes the radix results are formatted in; the value is one of 2,
+    /// 8, 10 and 16.
+    pub fn set_base(&mut self, base: u32) {
+        self.config.format.base = base;
+    }
+
+    pub fn set_format(&mut self, format: Format) {
+        self.config.format = format;
+    }
+
+    pub fn set_solver(&mut self, settings: SolverSettings) {
+        self.config.solver = settings;
+        self.memory.set_solver(settings);
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Writes a value the way the calculator prints results, in the
+    /// configured [`Format`].
+    ///
+    /// ```
+    /// use calculator_with_memory::Calculator;
+    ///
+    /// let mut calc = Calculator::new();
+    /// let value = calc.eval("0xf0 | 0x0f").unwrap();
+    /// calc.set_base(16);
+    /// assert_eq!(calc.format(&value), "0xff");
+    /// ```
+    pub fn format(&self, value: &Value) -> String {
+        self.config.format.value(value)
+    }
+
+    /// Evaluates an expression or a `let` assignment and returns its value,
+    /// which becomes the latest result. Function definitions and memory
+    /// operations are rejected without taking effect; use
+    /// [`Calculator::execute`] for those.
+    ///
+    /// ```
+    /// use calculator_with_memory::Calculator;
+    ///
+    /// let mut calc = Calculator::new();
+    /// calc.eval("let limit = 5 km").unwrap();
+    /// assert_eq!(calc.eval("4800 m < limit").unwrap().to_string(), "true");
+    /// assert!(calc.eval("fn f(x) = x").is_err());
+    /// ```
+    pub fn eval(&mut self, input: &str) -> Result<Value, CalcError> {
+        let statement = parse_statement(&tokenize(input)?)?;
+        if !matches!(statement, Statement::Expr(_) | Statement::Let { .. }) {
+            return Err(CalcError::ExpectedExpression {
+                span: Span::new(0, input.len()),
+            });
+        }
+        match self.run(statement)? {
+            Outcome::Value(value) | Outcome::Assigned { value, .. } => Ok(value),
+            _ => unreachable!("only expressions and assignments are run"),
+        }
+    }
+
+    /// Runs any line other than a `:command`: an expression, an assignment,
+    /// a function definition or a memory operation.
+    ///
+    /// ```
+    /// use calculator_with_memory::{Calculator, Outcome};
+    ///
+    /// let mut calc = Calculator::new();
+    /// calc.execute("fn area(r) = pi * r^2").unwrap();
+    /// calc.execute("area(2)").unwrap();
+    /// let outcome = calc.execute("memA+").unwrap();
+    /// assert!(matches!(outcome, Outcome::Updated { ref name, .. } if name == "A"));
+    /// ```
+    pub fn execute(&mut self, input: &str) -> Result<Outcome, CalcError> {
+        let statement = parse_statement(&tokenize(input)?)?;
+        self.run(statement)
+    }
+
+    fn run(&mut self, statement: Statement) -> Result<Outcome, CalcError> {
+        match statement {
+            Statement::Expr(expr) => {
+                let value = expr.eval_with_history(&self.memory, &self.history)?;
+                self.history.push(value.clone());
+                Ok(Outcome::Value(value))
+            }
+            Statement::Let { name, value } => {
+                let value = value.eval_with_history(&self.memory, &self.history)?;
+                self.memory.set(name.clone(), value.clone());
+                self.history.push(value.clone());
+                Ok(Outcome::Assigned { name, value })
+            }
+            Statement::FnDef { name, function } => {
+                self.memory.define(name.clone(), function.clone());
+                Ok(Outcome::Defined { name, function })
+            }
+            Statement::Memory {
+                name,
+                op,
+                operand,
+                span,
+            } => self.memory_op(name, op, operand, span),
+        }
+    }
+
+    // Without an operand, a memory operation applies the latest result, or
+    // zero before there is one.
+    fn memory_op(
+        &mut self,
+        name: String,
+        op: MemoryOp,
+        operand: Option<Expr>,
+        span: Span,
+    ) -> Result<Outcome, CalcError> {
+        let missing = |name: &str| CalcError::UnknownMemory {
+            name: name.to_string(),
+            span,
+        };
+        let percent = operand.as_ref().is_some_and(Expr::is_percent);
+        let value = match operand {
+            Some(expr) => expr.eval_with_history(&self.memory, &self.history)?,
+            None => match self.history.last() {
+                Some(value) => value.clone(),
+                None => Value::Number(self.memory.mode().from_f64(0.0)),
+            },
+        };
+        let incompatible = |e| CalcError::from_value_error(&name, e, span);
+        // Like `a + 15%`, `memA+ 15%` adds 15% of what the slot holds.
+        let value = match op {
+            MemoryOp::Add | MemoryOp::Subtract if percent => {
+                let zero = Value::Number(self.memory.mode().from_f64(0.0));
+                let held = self.memory.get(&name).unwrap_or(zero);
+                held.mul(&value).map_err(incompatible)?
+            }
+            _ => value,
+        };
+        match op {
+            MemoryOp::Add => self
+                .memory
+                .update(name.clone(), value)
+                .map_err(incompatible)?,
+            MemoryOp::Subtract => self
+                .memory
+                .update(name.clone(), value.neg().map_err(incompatible)?)
+                .map_err(incompatible)?,
+            MemoryOp::Multiply => self
+                .memory
+                .multiply(name.clone(), value)
+                .map_err(incompatible)?,
+            MemoryOp::Store => self.memory.set(name.clone(), value),
+            MemoryOp::Recall => {
+                let value = self.memory.get(&name).ok_or_else(|| missing(&name))?;
+                self.history.push(value.clone());
+                return Ok(Outcome::Value(value));
+            }
+            MemoryOp::Clear => {
+                self.memory.take(&name).ok_or_else(|| missing(&name))?;
+                return Ok(Outcome::Cleared(name));
+            }
+            MemoryOp::Swap(other) => {
+                if !self.memory.swap(&name, &other) {
+                    let absent = if self.memory.get(&name).is_none() {
+                        &name
+                    } else {
+                        &other
+                    };
+                    return Err(missing(absent));
+                }
+                let first = self.memory.get(&name).unwrap();
+                let second = self.memory.get(&other).unwrap();
+                return Ok(Outcome::Swapped {
+                    first: (name, first),
+                    second: (other, second),
+                });
+            }
+        }
+        let value = self.memory.get(&name).unwrap();
+        Ok(Outcome::Updated { name, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Notation;
+
+    #[test]
+    fn test_results_feed_history_and_memory() {
+        let mut calc = Calculator::new();
+        assert_eq!(calc.eval("let x = 4"), Ok(Value::from(4.0)));
+        assert_eq!(calc.eval("x * 2"), Ok(Value::from(8.0)));
+        assert_eq!(calc.history().len(), 2);
+        assert_eq!(
+            calc.execute("memA+ $1 + ans"),
+            Ok(Outcome::Updated {
+                name: "A".to_string(),
+                value: Value::from(12.0)
+            })
+        );
+        assert_eq!(calc.memory().get("A"), Some(Value::from(12.0)));
+        assert_eq!(calc.execute("memA?"), Ok(Outcome::Value(Value::from(12.0))));
+        assert_eq!(calc.history().len(), 3);
+    }
+
+    #[test]
+    fn test_bare_assignment_stores_a_dataset() {
+        let mut calc = Calculator::new();
+        calc.execute("x = 4").unwrap();
+        let data = calc.eval("[12, 4, 8]").unwrap();
+        assert_eq!(
+            calc.execute("data = [3, 1, 2] * x"),
+            Ok(Outcome::Assigned {
+                name: "data".to_string(),
+                value: data.clone(),
+            })
+        );
+        assert_eq!(calc.memory().get("data"), Some(data));
+        assert_eq!(calc.eval("median(data)"), Ok(Value::from(8.0)));
+    }
+
+    #[test]
+    fn test_percent_memory_operations() {
+        let mut calc = Calculator::new();
+        calc.execute("memA= 200").unwrap();
+        calc.execute("memA+ 15%").unwrap();
+        assert_eq!(calc.memory().get("A"), Some(Value::from(230.0)));
+        calc.execute("memA- 10%").unwrap();
+        assert_eq!(calc.memory().get("A"), Some(Value::from(207.0)));
+        calc.execute("memB+ 10%").unwrap();
+        assert_eq!(calc.memory().get("B"), Some(Value::from(0.0)));
+    }
+
+    #[test]
+    fn test_memory_operations() {
+        let mut calc = Calculator::new();
+        calc.execute("memA= 2").unwrap();
+        calc.execute("memB= 3").unwrap();
+        assert_eq!(
+            calc.execute("memA<>B"),
+            Ok(Outcome::Swapped {
+                first: ("A".to_string(), Value::from(3.0)),
+                second: ("B".to_string(), Value::from(2.0)),
+            })
+        );
+        assert_eq!(calc.execute("memA!"), Ok(Outcome::Cleared("A".to_string())));
+        assert_eq!(
+            calc.execute("memA?"),
+            Err(CalcError::UnknownMemory {
+                name: "A".to_string(),
+                span: Span::new(0, 5)
+            })
+        );
+        assert!(!Outcome::Value(Value::from(1.0)).changes_memory());
+        assert!(Outcome::Cleared("A".to_string()).changes_memory());
+    }
+
+    #[test]
+    fn test_memory_slots_read_back_from_a_save() {
+        let mut calc = Calculator::new();
+        calc.execute("memA1= 5").unwrap();
+        calc.execute("mem_b+ 2").unwrap();
+        assert!(calc.execute("mem1+ 5").is_err());
+        assert!(calc.execute("memA1<>2").is_err());
+        let mut loaded = Memory::new();
+        loaded.load_text(&calc.memory().to_text()).unwrap();
+        assert_eq!(loaded.slots(), calc.memory().slots());
+        assert_eq!(loaded.slots().len(), 2);
+    }
+
+    #[test]
+    fn test_eval_rejects_statements_without_a_value() {
+        let mut calc = Calculator::new();
+        assert_eq!(
+            calc.eval("memA= 1"),
+            Err(CalcError::ExpectedExpression {
+                span: Span::new(0, 7)
+            })
+        );
+        assert!(calc.memory().is_empty());
+        assert!(matches!(
+            calc.eval("1 +"),
+            Err(CalcError::UnexpectedEnd { .. })
+        ));
+    }
+
+    #[test]
+    fn test_config() {
+        let mut calc = Calculator::with_config(Config {
+            mode: NumericMode::Integer,
+            format: Format {
+                base: 2,
+                ..Format::default()
+            },
+            ..Config::default()
+        });
+        let value = calc.eval("7 / 2").unwrap();
+        assert_eq!(calc.format(&value), "0b11");
+        calc.set_format(Format {
+            notation: Notation::Fixed(2),
+            grouping: Some(','),
+            ..Format::default()
+        });
+        let value = calc.eval("[1234567, 3 km]").unwrap();
+        assert_eq!(calc.format(&value), "[1,234,567.00, 3.00 km]");
+        assert_eq!(calc.memory().mode(), NumericMode::Integer);
+        calc.set_solver(SolverSettings {
+            tolerance: 1e-3,
+            max_iterations: 2,
+        });
+        assert_eq!(calc.memory().solver(), calc.config().solver);
+        assert!(calc.eval("solve(cos(x) - x, x, 0)").is_err());
+    }
+}
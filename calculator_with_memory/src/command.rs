@@ -0,0 +1,238 @@
+use crate::format::{parse_base, parse_decimal_point, parse_separator, Notation};
+use std::path::PathBuf;
+
+pub const HELP: &str = "\
+Expressions:
+  1 + 2 * 3        evaluate an expression
+  let x = 3 * 4    store a value in slot x (or just x = 3 * 4)
+  fn f(a, b) = ... define a function
+  memX+ / memX-    add / subtract the previous result to slot X
+  memX* / memX=    multiply slot X by / store the previous result
+  memX+ 3 * 4      use an expression instead of the previous result
+  memX? / memX!    recall / clear slot X
+  memX<>Y          swap slots X and Y
+  ans, $1, $2      the previous result, or the first, second, ... one
+  3 GiB / 200 MB/s quantities with units (length, time, data)
+  90 min to h      convert to another unit (also: in)
+  0xff 0o17 0b101  hexadecimal, octal and binary literals
+  & | xor ~ << >>  bitwise and, or, exclusive or, not and shifts
+                   (`^` is the power operator, so exclusive or is `xor`)
+  == != < <= > >=  comparisons, giving true or false
+  and or not       combine true/false values
+  if(c, a, b)      a when c is true, otherwise b (only one is evaluated)
+  diff(x^2, x)     derivative with respect to x, as an expression
+  simplify(x + x)  algebraic cleanup of an expression
+  solve(x^2 - 2, x, 1)
+                   a root of the expression near the guess 1
+  integrate(sin(x), x, 0, pi)
+                   definite integral over x from 0 to pi
+  sum(i^2, i, 1, 10), product(i, i, 1, 5)
+                   sum or product over whole numbers i from 1 to 10
+  [1, 2, 3]        a list; lists combine element by element
+  [[1, 2], [3, 4]] a matrix, written as a list of rows
+  dot cross transpose det inverse
+                   products of lists and matrices (dot multiplies matrices)
+  sum mean stddev  add up, average or spread of a list's elements
+  median variance  middle value and spread (sample variance) of a list
+  percentile(v, p) the value p percent of the way up a list
+  linreg(xs, ys)   least-squares line through points, as [slope, intercept]
+  \"text\"           a string, such as a file name
+  load(\"f.csv\", c) column c of a CSV file, by heading or number from 1
+  200 + 15%        percentages; + and - add or take away a share (230 here)
+  pmt(r, n, pv)    payment per period on a loan of pv at rate r for n periods
+  fv(r, n, pmt)    value of n payments of pmt at rate r by the last one
+  compound(pv, r, n)
+                   pv grown by rate r over n periods
+  npv(r, flows), irr(flows)
+                   net present value of flows (first one now), and the rate
+                   that makes it zero; money paid out is negative
+
+Commands:
+  :mem             list memory slots and functions
+  :clear <name>    delete a slot or function
+  :reset           delete every slot and function
+  :history         list numbered results
+  :base <radix>    print whole numbers in base 2, 8, 10 or 16
+  :format          show how results are printed
+  :format <style>  auto, fixed <digits>, sig <digits>, sci <digits> or
+                   eng <digits> (scientific with powers of a thousand)
+  :format group <sep>
+                   group digits by , . _ ' or space; off to stop
+  :format decimal <sep>
+                   write the decimal point as . or ,
+  :tolerance <eps> how precisely solve() pins down a root
+  :iterations <n>  how many steps solve() may take
+  :save <path>     write memory to a file
+  :load <path>     read memory from a file
+  :help            show this help
+  :quit            exit";
+
+pub const COMMAND_NAMES: &[&str] = &[
+    "mem",
+    "clear",
+    "reset",
+    "history",
+    "base",
+    "format",
+    "tolerance",
+    "iterations",
+    "save",
+    "load",
+    "help",
+    "quit",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Save(PathBuf),
+    Load(PathBuf),
+    ListMemory,
+    Clear(String),
+    Reset,
+    History,
+    Base(u32),
+    /// `:format` shows the current format when no notation is given.
+    Format(Option<Notation>),
+    Grouping(Option<char>),
+    DecimalPoint(char),
+    Tolerance(f64),
+    Iterations(u32),
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn is_command(line: &str) -> bool {
+        line.trim_start().starts_with(':')
+    }
+
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let body = line
+            .strip_prefix(':')
+            .ok_or_else(|| format!("Not a command: {}", line))?;
+        let (name, arg) = match body.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (body, ""),
+        };
+        match name {
+            "save" => Ok(Command::Save(path_arg(name, arg)?)),
+            "load" => Ok(Command::Load(path_arg(name, arg)?)),
+            "clear" if arg.is_empty() => Err("Usage: :clear <name>".to_string()),
+            "clear" => Ok(Command::Clear(arg.to_string())),
+            "base" => parse_base(arg)
+                .map(Command::Base)
+                .map_err(|_| "Usage: :base <2|8|10|16>".to_string()),
+            "format" => format_arg(arg),
+            "tolerance" => match arg.parse::<f64>() {
+                Ok(tolerance) if tolerance > 0.0 && tolerance.is_finite() => {
+                    Ok(Command::Tolerance(tolerance))
+                }
+                _ => Err("Usage: :tolerance <positive number>".to_string()),
+            },
+            "iterations" => match arg.parse() {
+                Ok(count) if count > 0 => Ok(Command::Iterations(count)),
+                _ => Err("Usage: :iterations <positive whole number>".to_string()),
+            },
+            _ if !arg.is_empty() && COMMAND_NAMES.contains(&name) => {
+                Err(format!("Command ':{}' takes no argument", name))
+            }
+            "mem" => Ok(Command::ListMemory),
+            "reset" => Ok(Command::Reset),
+            "history" => Ok(Command::History),
+            "help" => Ok(Command::Help),
+            "quit" => Ok(Command::Quit),
+            _ => Err(format!("Unknown command ':{}'", name)),
+        }
+    }
+}
+
+fn format_arg(arg: &str) -> Result<Command, String> {
+    let words: Vec<&str> = arg.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Ok(Command::Format(None)),
+        ["group", "off"] => Ok(Command::Grouping(None)),
+        ["group", separator] => parse_separator(separator).map(|c| Command::Grouping(Some(c))),
+        ["decimal", separator] => parse_decimal_point(separator).map(Command::DecimalPoint),
+        ["group" | "decimal", ..] => {
+            Err("Usage: :format group <sep|off> or :format decimal <sep>".to_string())
+        }
+        words => words.join(":").parse().map(|n| Command::Format(Some(n))),
+    }
+}
+
+fn path_arg(name: &str, arg: &str) -> Result<PathBuf, String> {
+    if arg.is_empty() {
+        Err(format!("Usage: :{} <path>", name))
+    } else {
+        Ok(PathBuf::from(arg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Command::parse(":save totals.calc"),
+            Ok(Command::Save(PathBuf::from("totals.calc")))
+        );
+        assert_eq!(
+            Command::parse("  :load  my totals.calc "),
+            Ok(Command::Load(PathBuf::from("my totals.calc")))
+        );
+        assert!(Command::parse(":save").is_err());
+        assert!(Command::parse(":frobnicate").is_err());
+        assert_eq!(Command::parse(":mem"), Ok(Command::ListMemory));
+        assert_eq!(
+            Command::parse(":clear A"),
+            Ok(Command::Clear("A".to_string()))
+        );
+        assert!(Command::parse(":clear").is_err());
+        assert!(Command::parse(":reset now").is_err());
+        assert_eq!(Command::parse(":history"), Ok(Command::History));
+        assert_eq!(Command::parse(":base 16"), Ok(Command::Base(16)));
+        assert!(Command::parse(":base 7").is_err());
+        assert!(Command::parse(":base").is_err());
+        assert_eq!(
+            Command::parse(":tolerance 1e-6"),
+            Ok(Command::Tolerance(1e-6))
+        );
+        assert!(Command::parse(":tolerance -1").is_err());
+        assert_eq!(
+            Command::parse(":iterations 50"),
+            Ok(Command::Iterations(50))
+        );
+        assert!(Command::parse(":iterations 0").is_err());
+        assert_eq!(Command::parse(":format"), Ok(Command::Format(None)));
+        assert_eq!(
+            Command::parse(":format fixed 2"),
+            Ok(Command::Format(Some(Notation::Fixed(2))))
+        );
+        assert_eq!(
+            Command::parse(":format sig:4"),
+            Ok(Command::Format(Some(Notation::Significant(4))))
+        );
+        assert!(Command::parse(":format fixed two").is_err());
+        assert!(Command::parse(":format fixed 100000").is_err());
+        assert_eq!(
+            Command::parse(":format group space"),
+            Ok(Command::Grouping(Some(' ')))
+        );
+        assert_eq!(
+            Command::parse(":format group off"),
+            Ok(Command::Grouping(None))
+        );
+        assert!(Command::parse(":format group").is_err());
+        assert_eq!(
+            Command::parse(":format decimal ,"),
+            Ok(Command::DecimalPoint(','))
+        );
+        assert!(Command::parse(":format decimal ;").is_err());
+        assert_eq!(Command::parse(":quit"), Ok(Command::Quit));
+        assert!(Command::is_command(" :save x"));
+        assert!(!Command::is_command("1 + 2"));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::command::COMMAND_NAMES;
+use crate::finance::FINANCE;
+use crate::functions::{BUILTINS, CONSTANTS};
+use crate::history::ANS;
+use crate::matrix::LIST_FUNCTIONS;
+use crate::statistics::STATISTICS;
+
+const KEYWORDS: &[&str] = &["let", "fn"];
+
+/// Returns where the word under the cursor starts and every name it could
+/// be completed to. Commands complete after a leading `:`, the argument of
+/// `:clear` completes to `memory_names`, and anything else completes to
+/// those names plus built-ins, constants, keywords and `ans`.
+pub fn complete(line: &str, pos: usize, memory_names: &[String]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .map_or(0, |at| at + 1);
+    let prefix = &before[start..];
+
+    let candidates: Vec<String> = match before.trim_start().strip_prefix(':') {
+        Some(command) if !command.contains(char::is_whitespace) => {
+            COMMAND_NAMES.iter().map(|name| name.to_string()).collect()
+        }
+        Some(command) if command.starts_with("clear") => memory_names.to_vec(),
+        Some(_) => Vec::new(),
+        None => {
+            let mut names = memory_names.to_vec();
+            names.extend(BUILTINS.iter().map(|builtin| builtin.name.to_string()));
+            names.extend(
+                LIST_FUNCTIONS
+                    .iter()
+                    .chain(STATISTICS)
+                    .chain(FINANCE)
+                    .map(|function| function.name.to_string()),
+            );
+            names.extend(CONSTANTS.iter().map(|(name, _)| name.to_string()));
+            names.extend(KEYWORDS.iter().map(|name| name.to_string()));
+            names.push(ANS.to_string());
+            names
+        }
+    };
+
+    let mut matches: Vec<String> = candidates
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    matches.sort();
+    matches.dedup();
+    (start, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        vec!["tax".to_string(), "tip".to_string(), "total".to_string()]
+    }
+
+    #[test]
+    fn test_complete_names() {
+        let memory = names();
+        assert_eq!(
+            complete("1 + ta", 6, &memory),
+            (4, vec!["tan".to_string(), "tax".to_string()])
+        );
+        assert_eq!(
+            complete("sqrt(to", 7, &memory),
+            (5, vec!["total".to_string()])
+        );
+        assert_eq!(complete("ti + 1", 2, &memory), (0, vec!["tip".to_string()]));
+        assert_eq!(
+            complete("tr", 2, &memory),
+            (0, vec!["transpose".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_complete_commands() {
+        let memory = names();
+        assert_eq!(complete(":re", 3, &memory), (1, vec!["reset".to_string()]));
+        assert_eq!(
+            complete(":clear t", 8, &memory),
+            (
+                7,
+                vec!["tax".to_string(), "tip".to_string(), "total".to_string()]
+            )
+        );
+        assert_eq!(complete(":save t", 7, &memory), (6, vec![]));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::functions::Arity;
+use crate::token::Span;
+use crate::units::Dimension;
+use crate::value::{Shape, ValueError};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    UnknownCharacter {
+        ch: char,
+        span: Span,
+    },
+    InvalidNumber {
+        text: String,
+        span: Span,
+    },
+    UnexpectedToken {
+        found: String,
+        span: Span,
+    },
+    UnexpectedEnd {
+        span: Span,
+    },
+    UnbalancedParen {
+        span: Span,
+    },
+    UnterminatedText {
+        span: Span,
+    },
+    TooDeep {
+        span: Span,
+    },
+    TooLong {
+        span: Span,
+    },
+    UnknownMemory {
+        name: String,
+        span: Span,
+    },
+    UnknownResult {
+        reference: String,
+        span: Span,
+    },
+    DivisionByZero {
+        span: Span,
+    },
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+    ArityMismatch {
+        name: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
+    Domain {
+        name: String,
+        message: String,
+        span: Span,
+    },
+    RecursionLimit {
+        name: String,
+        span: Span,
+    },
+    UnknownUnit {
+        name: String,
+        span: Span,
+    },
+    IncompatibleUnits {
+        left: Dimension,
+        right: Dimension,
+        span: Span,
+    },
+    Overflow {
+        span: Span,
+    },
+    TypeMismatch {
+        name: String,
+        expected: &'static str,
+        found: &'static str,
+        span: Span,
+    },
+    ExpectedExpression {
+        span: Span,
+    },
+    ShapeMismatch {
+        name: String,
+        left: Shape,
+        right: Shape,
+        span: Span,
+    },
+    Load {
+        path: String,
+        message: String,
+        span: Span,
+    },
+}
+
+impl CalcError {
+    pub fn span(&self) -> Span {
+        match self {
+            CalcError::UnknownCharacter { span, .. }
+            | CalcError::InvalidNumber { span, .. }
+            | CalcError::UnexpectedToken { span, .. }
+            | CalcError::UnexpectedEnd { span }
+            | CalcError::UnbalancedParen { span }
+            | CalcError::UnterminatedText { span }
+            | CalcError::TooDeep { span }
+            | CalcError::TooLong { span }
+            | CalcError::UnknownMemory { span, .. }
+            | CalcError::UnknownResult { span, .. }
+            | CalcError::DivisionByZero { span }
+            | CalcError::UnknownFunction { span, .. }
+            | CalcError::ArityMismatch { span, .. }
+            | CalcError::Domain { span, .. }
+            | CalcError::RecursionLimit { span, .. }
+            | CalcError::UnknownUnit { span, .. }
+            | CalcError::IncompatibleUnits { span, .. }
+            | CalcError::Overflow { span }
+            | CalcError::TypeMismatch { span, .. }
+            | CalcError::ExpectedExpression { span }
+            | CalcError::ShapeMismatch { span, .. }
+            | CalcError::Load { span, .. } => *span,
+        }
+    }
+
+    pub fn with_span(mut self, new_span: Span) -> Self {
+        match &mut self {
+            CalcError::UnknownCharacter { span, .. }
+            | CalcError::InvalidNumber { span, .. }
+            | CalcError::UnexpectedToken { span, .. }
+            | CalcError::UnexpectedEnd { span }
+            | CalcError::UnbalancedParen { span }
+            | CalcError::UnterminatedText { span }
+            | CalcError::TooDeep { span }
+            | CalcError::TooLong { span }
+            | CalcError::UnknownMemory { span, .. }
+            | CalcError::UnknownResult { span, .. }
+            | CalcError::DivisionByZero { span }
+            | CalcError::UnknownFunction { span, .. }
+            | CalcError::ArityMismatch { span, .. }
+            | CalcError::Domain { span, .. }
+            | CalcError::RecursionLimit { span, .. }
+            | CalcError::UnknownUnit { span, .. }
+            | CalcError::IncompatibleUnits { span, .. }
+            | CalcError::Overflow { span }
+            | CalcError::TypeMismatch { span, .. }
+            | CalcError::ExpectedExpression { span }
+            | CalcError::ShapeMismatch { span, .. }
+            | CalcError::Load { span, .. } => *span = new_span,
+        }
+        self
+    }
+
+    /// Attaches a span to a failed operation on values; `name` is the
+    /// operator or function reported for domain errors.
+    pub fn from_value_error(name: &str, error: ValueError, span: Span) -> Self {
+        match error {
+            ValueError::Incompatible(left, right) => {
+                CalcError::IncompatibleUnits { left, right, span }
+            }
+            ValueError::Domain(message) => CalcError::Domain {
+                name: name.to_string(),
+                message: message.to_string(),
+                span,
+            },
+            ValueError::Overflow => CalcError::Overflow { span },
+            ValueError::Mismatch(expected, found) => CalcError::TypeMismatch {
+                name: name.to_string(),
+                expected,
+                found,
+                span,
+            },
+            ValueError::Shape(left, right) => CalcError::ShapeMismatch {
+                name: name.to_string(),
+                left,
+                right,
+                span,
+            },
+            ValueError::DivisionByZero => CalcError::DivisionByZero { span },
+        }
+    }
+
+    /// Renders the error under the offending input line, e.g.
+    ///
+    /// ```text
+    /// Error: Unknown memory slot 'B'
+    ///   A * B
+    ///       ^
+    /// ```
+    pub fn render(&self, input: &str) -> String {
+        let span = self.span();
+        let start = input[..span.start.min(input.len())].chars().count();
+        let width = input
+            .get(span.start..span.end)
+            .map_or(0, |text| text.chars().count())
+            .max(1);
+        format!(
+            "Error: {}\n  {}\n  {}{}",
+            self,
+            input,
+            " ".repeat(start),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::UnknownCharacter { ch, .. } => write!(f, "Unknown character '{}'", ch),
+            CalcError::InvalidNumber { text, .. } => write!(f, "Invalid number '{}'", text),
+            CalcError::UnexpectedToken { found, .. } => write!(f, "Unexpected token '{}'", found),
+            CalcError::UnexpectedEnd { .. } => write!(f, "Unexpected end of input"),
+            CalcError::UnbalancedParen { .. } => write!(f, "Unbalanced parenthesis"),
+            CalcError::UnterminatedText { .. } => write!(f, "Missing closing quote"),
+            CalcError::TooDeep { .. } => write!(f, "Expression is nested too deeply"),
+            CalcError::TooLong { .. } => write!(f, "Expression has too many operators"),
+            CalcError::UnknownMemory { name, .. } => write!(f, "Unknown memory slot '{}'", name),
+            CalcError::UnknownResult { reference, .. } => {
+                write!(f, "No result '{}' in history", reference)
+            }
+            CalcError::DivisionByZero { .. } => write!(f, "Division by zero"),
+            CalcError::UnknownFunction { name, .. } => write!(f, "Unknown function '{}'", name),
+            CalcError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Function '{}' expects {} argument(s) but got {}",
+                name, expected, found
+            ),
+            CalcError::Domain { name, message, .. } => {
+                write!(f, "Domain error in '{}': {}", name, message)
+            }
+            CalcError::RecursionLimit { name, .. } => {
+                write!(f, "Recursion limit exceeded in '{}'", name)
+            }
+            CalcError::UnknownUnit { name, .. } => write!(f, "Unknown unit '{}'", name),
+            CalcError::IncompatibleUnits { left, right, .. } => {
+                write!(f, "Incompatible dimensions: {} and {}", left, right)
+            }
+            CalcError::Overflow { .. } => write!(f, "Integer overflow"),
+            CalcError::TypeMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Type mismatch in '{}': expected a {} but found a {}",
+                name, expected, found
+            ),
+            CalcError::ExpectedExpression { .. } => {
+                write!(f, "Expected an expression or assignment")
+            }
+            CalcError::ShapeMismatch {
+                name, left, right, ..
+            } => write!(f, "Shape mismatch in '{}': {} and {}", name, left, right),
+            CalcError::Load { path, message, .. } => {
+                write!(f, "Cannot load '{}': {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_points_at_span() {
+        let error = CalcError::UnknownMemory {
+            name: "B".to_string(),
+            span: Span::new(4, 5),
+        };
+        assert_eq!(
+            error.render("A * B"),
+            "Error: Unknown memory slot 'B'\n  A * B\n      ^"
+        );
+    }
+
+    #[test]
+    fn test_render_at_end_of_input() {
+        let error = CalcError::UnexpectedEnd {
+            span: Span::new(3, 3),
+        };
+        assert_eq!(
+            error.render("1 +"),
+            "Error: Unexpected end of input\n  1 +\n     ^"
+        );
+    }
+}
@@ -1,78 +1,1704 @@
-use crate::memory::Memory;
-use crate::token::Token;
+use crate::error::CalcError;
+use crate::functions::{self, Arity, Builtin};
+use crate::history::{History, ANS};
+use crate::matrix::{self, divisible};
+use crate::memory::{Memory, MemoryOp, UserFunction};
+use crate::number::{Number, NumberError};
+use crate::parser::parse;
+use crate::quadrature;
+use crate::solver;
+use crate::statistics::{self, Column};
+use crate::symbolic;
+use crate::token::{Span, Token};
+use crate::units::Unit;
+use crate::value::Value;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::thread;
 
-pub fn eval_expression(tokens: &[Token], memory: &Memory) -> Result<f64, String> {
-    let (result, _) = eval_additive_expression(tokens, 0, memory)?;
-    Ok(result)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
 }
 
-fn eval_additive_expression(
-    tokens: &[Token],
-    index: usize,
-    memory: &Memory,
-) -> Result<(f64, usize), String> {
-    let (mut result, mut index) = eval_multiplicative_expression(tokens, index, memory)?;
+pub(crate) const NOT_PRECEDENCE: u8 = 3;
+pub(crate) const CONVERT_PRECEDENCE: u8 = 5;
+const UNARY_PRECEDENCE: u8 = 12;
+const PERCENT_PRECEDENCE: u8 = 14;
 
-    while index < tokens.len() {
-        match &tokens[index] {
-            Token::Plus => {
-                let (value, next) = eval_multiplicative_expression(tokens, index + 1, memory)?;
-                result += value;
-                index = next;
+impl BinaryOp {
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 4,
+            BinaryOp::BitOr => 6,
+            BinaryOp::BitXor => 7,
+            BinaryOp::BitAnd => 8,
+            BinaryOp::Shl | BinaryOp::Shr => 9,
+            BinaryOp::Add | BinaryOp::Sub => 10,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 11,
+            BinaryOp::Pow => 13,
+        }
+    }
+
+    pub fn is_right_associative(self) -> bool {
+        self == BinaryOp::Pow
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "or",
+            BinaryOp::And => "and",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "xor",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Pow => "^",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    BitNot,
+    Not,
+    /// The postfix `%`, which divides by 100.
+    Percent,
+}
+
+impl UnaryOp {
+    pub fn precedence(self) -> u8 {
+        match self {
+            UnaryOp::Not => NOT_PRECEDENCE,
+            UnaryOp::Percent => PERCENT_PRECEDENCE,
+            _ => UNARY_PRECEDENCE,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::BitNot => "~",
+            UnaryOp::Not => "not",
+            UnaryOp::Percent => "%",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(Number),
+    Variable(String),
+    History(usize),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Quantity {
+        value: Box<Expr>,
+        unit: Unit,
+    },
+    Convert {
+        expr: Box<Expr>,
+        unit: Unit,
+    },
+    List(Vec<Expr>),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+// Spans only locate an expression in its source line, so two expressions
+// with the same structure are equal wherever they were typed.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn unary(op: UnaryOp, operand: Expr, op_span: Span) -> Self {
+        let span = op_span.to(operand.span);
+        Expr::new(
+            ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+            span,
+        )
+    }
+
+    /// `15%` and the like, which `+` and `-` take as a share of their
+    /// left-hand side.
+    pub fn is_percent(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::Unary {
+                op: UnaryOp::Percent,
+                ..
             }
-            Token::Minus => {
-                let (value, next) = eval_multiplicative_expression(tokens, index + 1, memory)?;
-                result -= value;
-                index = next;
+        )
+    }
+
+    // Whether the expression prints starting with `-` or `+`.
+    fn is_signed(&self) -> bool {
+        match &self.kind {
+            ExprKind::Number(value) => value.is_negative(),
+            ExprKind::Unary { op, .. } => matches!(op, UnaryOp::Neg | UnaryOp::Plus),
+            _ => false,
+        }
+    }
+
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        let span = lhs.span.to(rhs.span);
+        Expr::new(
+            ExprKind::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            span,
+        )
+    }
+
+    pub fn eval(&self, memory: &Memory) -> Result<Value, CalcError> {
+        self.eval_with_history(memory, &History::new())
+    }
+
+    /// Evaluates with `ans` and `$N` referring to the results in `history`.
+    pub fn eval_with_history(
+        &self,
+        memory: &Memory,
+        history: &History,
+    ) -> Result<Value, CalcError> {
+        on_eval_stack(|| self.eval_in(&Scope::global(memory, history, &Budget::new())))
+    }
+
+    fn eval_in(&self, scope: &Scope) -> Result<Value, CalcError> {
+        scope.budget.nested(self.span, || self.eval_node(scope))
+    }
+
+    fn eval_node(&self, scope: &Scope) -> Result<Value, CalcError> {
+        match &self.kind {
+            ExprKind::Number(value) => Ok(Value::Number(scope.memory.mode().convert(value))),
+            ExprKind::Text(text) => Ok(Value::Text(text.clone())),
+            ExprKind::Variable(name) => scope.lookup(name).ok_or_else(|| {
+                if name == ANS {
+                    CalcError::UnknownResult {
+                        reference: name.clone(),
+                        span: self.span,
+                    }
+                } else {
+                    CalcError::UnknownMemory {
+                        name: name.clone(),
+                        span: self.span,
+                    }
+                }
+            }),
+            ExprKind::History(number) => {
+                scope
+                    .history
+                    .get(*number)
+                    .cloned()
+                    .ok_or_else(|| CalcError::UnknownResult {
+                        reference: format!("${}", number),
+                        span: self.span,
+                    })
+            }
+            ExprKind::Unary { op, operand } => {
+                let value = operand.eval_in(scope)?;
+                let result = match op {
+                    UnaryOp::Neg => value.neg(),
+                    UnaryOp::Plus => Ok(value),
+                    UnaryOp::BitNot => value.bit_not(),
+                    UnaryOp::Not => value.as_bool().map(|value| Value::Bool(!value)),
+                    UnaryOp::Percent => {
+                        let mode = scope.memory.mode();
+                        let hundred = Value::Number(mode.from_f64(100.0));
+                        value.map_elements(&mut |value| divisible(value, mode).div(&hundred))
+                    }
+                };
+                result.map_err(|e| CalcError::from_value_error(op.symbol(), e, self.span))
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                if matches!(op, BinaryOp::And | BinaryOp::Or) {
+                    return self.eval_logical(*op, lhs, rhs, scope);
+                }
+                let (lhs_value, mut rhs_value) = (lhs.eval_in(scope)?, rhs.eval_in(scope)?);
+                // `200 + 15%` adds 15% of 200, and `200 - 15%` takes it away.
+                if matches!(op, BinaryOp::Add | BinaryOp::Sub) && rhs.is_percent() {
+                    rhs_value = lhs_value
+                        .mul(&rhs_value)
+                        .map_err(|e| CalcError::from_value_error("%", e, rhs.span))?;
+                }
+                let compare = |test: fn(Ordering) -> bool| {
+                    lhs_value
+                        .compare(&rhs_value)
+                        .map(|ordering| Value::Bool(ordering.is_some_and(test)))
+                };
+                let result = match op {
+                    BinaryOp::And | BinaryOp::Or => unreachable!("evaluated lazily above"),
+                    BinaryOp::Eq => lhs_value.equals(&rhs_value).map(Value::Bool),
+                    BinaryOp::Ne => lhs_value
+                        .equals(&rhs_value)
+                        .map(|equal| Value::Bool(!equal)),
+                    BinaryOp::Lt => compare(Ordering::is_lt),
+                    BinaryOp::Le => compare(Ordering::is_le),
+                    BinaryOp::Gt => compare(Ordering::is_gt),
+                    BinaryOp::Ge => compare(Ordering::is_ge),
+                    BinaryOp::BitOr => lhs_value.bitwise(&rhs_value, |a, b| Ok(a | b)),
+                    BinaryOp::BitXor => lhs_value.bitwise(&rhs_value, |a, b| Ok(a ^ b)),
+                    BinaryOp::BitAnd => lhs_value.bitwise(&rhs_value, |a, b| Ok(a & b)),
+                    BinaryOp::Shl => lhs_value.bitwise(&rhs_value, shift_left),
+                    BinaryOp::Shr => lhs_value.bitwise(&rhs_value, |a, b| Ok(a >> shift(b)?)),
+                    BinaryOp::Add => lhs_value.add(&rhs_value),
+                    BinaryOp::Sub => lhs_value.sub(&rhs_value),
+                    BinaryOp::Mul => lhs_value.mul(&rhs_value),
+                    BinaryOp::Div | BinaryOp::Rem if rhs_value.is_zero() => {
+                        return Err(CalcError::DivisionByZero { span: rhs.span });
+                    }
+                    BinaryOp::Div => lhs_value.div(&rhs_value),
+                    BinaryOp::Rem => lhs_value.rem(&rhs_value),
+                    BinaryOp::Pow => lhs_value.pow(&rhs_value),
+                };
+                result.map_err(|e| CalcError::from_value_error(op.symbol(), e, self.span))
+            }
+            ExprKind::Quantity { value, unit } => {
+                let one = scope.memory.mode().from_f64(1.0);
+                value
+                    .eval_in(scope)?
+                    .mul(&Value::quantity(one, unit.clone()))
+                    .map_err(|e| CalcError::from_value_error("*", e, self.span))
+            }
+            ExprKind::Convert { expr, unit } => expr
+                .eval_in(scope)?
+                .convert(unit)
+                .map_err(|e| CalcError::from_value_error("to", e, self.span)),
+            ExprKind::Call { name, args } if name == "if" => self.eval_if(args, scope),
+            ExprKind::Call { name, args } if name == "diff" || name == "simplify" => {
+                self.eval_symbolic(name, args, scope)
+            }
+            ExprKind::Call { name, args } if name == "solve" => self.eval_solve(args, scope),
+            ExprKind::Call { name, args } if name == "integrate" => self.eval_integral(args, scope),
+            ExprKind::Call { name, args } if name == "load" => self.eval_load(args, scope),
+            // With a single argument, `sum` adds up a list instead.
+            ExprKind::Call { name, args }
+                if (name == "sum" && args.len() != 1) || name == "product" =>
+            {
+                self.eval_series(name, args, scope)
+            }
+            ExprKind::Call { name, args } => {
+                if let Some(function) = scope.memory.function(name) {
+                    return self.call_user_function(name, function, args, scope);
+                }
+                if let Some(function) = matrix::list_function(name) {
+                    self.check_arity(name, function.arity, args)?;
+                    return function
+                        .call(&eval_args(args, scope)?, scope.memory.mode())
+                        .map_err(|e| CalcError::from_value_error(name, e, self.span));
+                }
+                let builtin =
+                    functions::builtin(name).ok_or_else(|| CalcError::UnknownFunction {
+                        name: name.clone(),
+                        span: self.span,
+                    })?;
+                self.check_arity(name, builtin.arity, args)?;
+                let values = eval_args(args, scope)?;
+                // A function of one number applies to each element of a list.
+                if let [list @ Value::List(_)] = values.as_slice() {
+                    if builtin.arity == Arity::Exact(1) {
+                        return list.map_elements(&mut |value| {
+                            self.call_builtin(builtin, std::slice::from_ref(value), args)
+                        });
+                    }
+                }
+                self.call_builtin(builtin, &values, args)
+            }
+            ExprKind::List(items) => {
+                let values = eval_args(items, scope)?;
+                if let Some(first) = values.first() {
+                    for (value, item) in values.iter().zip(items) {
+                        if value.shape() != first.shape() {
+                            return Err(CalcError::ShapeMismatch {
+                                name: "[]".to_string(),
+                                left: first.shape(),
+                                right: value.shape(),
+                                span: item.span,
+                            });
+                        }
+                    }
+                }
+                Ok(Value::List(values))
             }
-            _ => break,
         }
     }
-    Ok((result, index))
-}
 
-fn eval_multiplicative_expression(
-    tokens: &[Token],
-    index: usize,
-    memory: &Memory,
-) -> Result<(f64, usize), String> {
-    let (mut result, mut index) = eval_primary_expression(tokens, index, memory)?;
+    fn call_builtin(
+        &self,
+        builtin: &Builtin,
+        values: &[Value],
+        args: &[Expr],
+    ) -> Result<Value, CalcError> {
+        let numbers = values
+            .iter()
+            .zip(args)
+            .map(|(value, arg)| eval_plain(builtin.name, value, arg))
+            .collect::<Result<Vec<Number>, CalcError>>()?;
+        builtin
+            .call(&numbers)
+            .map(Value::Number)
+            .map_err(|message| CalcError::Domain {
+                name: builtin.name.to_string(),
+                message: message.to_string(),
+                span: self.span,
+            })
+    }
+
+    // `and` and `or` only look at their right-hand side when it decides the
+    // result, so `x != 0 and 1 / x > 2` is safe.
+    fn eval_logical(
+        &self,
+        op: BinaryOp,
+        lhs: &Expr,
+        rhs: &Expr,
+        scope: &Scope,
+    ) -> Result<Value, CalcError> {
+        let truth = |expr: &Expr| {
+            expr.eval_in(scope)?
+                .as_bool()
+                .map_err(|e| CalcError::from_value_error(op.symbol(), e, expr.span))
+        };
+        let lhs = truth(lhs)?;
+        let result = if op == BinaryOp::And {
+            lhs && truth(rhs)?
+        } else {
+            lhs || truth(rhs)?
+        };
+        Ok(Value::Bool(result))
+    }
 
-    while index < tokens.len() {
-        match &tokens[index] {
-            Token::Asterisk => {
-                let (value, next) = eval_primary_expression(tokens, index + 1, memory)?;
-                result *= value;
-                index = next;
+    // `if` is not a function: only the chosen branch is evaluated, which is
+    // what lets a recursive definition stop.
+    fn eval_if(&self, args: &[Expr], scope: &Scope) -> Result<Value, CalcError> {
+        self.check_arity("if", Arity::Exact(3), args)?;
+        let condition = args[0]
+            .eval_in(scope)?
+            .as_bool()
+            .map_err(|e| CalcError::from_value_error("if", e, args[0].span))?;
+        if condition {
+            args[1].eval_in(scope)
+        } else {
+            args[2].eval_in(scope)
+        }
+    }
+
+    // `diff(expr, x)` and `simplify(expr)` work on their first argument as
+    // written rather than on its value.
+    fn eval_symbolic(&self, name: &str, args: &[Expr], scope: &Scope) -> Result<Value, CalcError> {
+        let arity = if name == "diff" { 2 } else { 1 };
+        self.check_arity(name, Arity::Exact(arity), args)?;
+        let var = match args.get(1) {
+            Some(arg) => Some(bound_variable(name, arg)?),
+            None => None,
+        };
+        let expr = expand_stored(&args[0], var, scope);
+        let result = match var {
+            Some(var) => symbolic::derivative_in(&expr, var, scope.memory, scope.budget)?,
+            None => expr,
+        };
+        let is_constant = |name: &str| scope.is_builtin_constant(name);
+        Ok(Value::Expr(Box::new(symbolic::simplify(
+            &result,
+            &is_constant,
+        ))))
+    }
+
+    // `solve(expr, x, guess)` evaluates `expr` again and again with `x`
+    // bound to trial values, so it too takes the expression as written. An
+    // error at the guess is reported as it is; anywhere else it only marks
+    // a point where the expression is undefined.
+    fn eval_solve(&self, args: &[Expr], scope: &Scope) -> Result<Value, CalcError> {
+        self.check_arity("solve", Arity::Exact(3), args)?;
+        let var = bound_variable("solve", &args[1])?;
+        let guess = eval_number("solve", &args[2], scope)?.to_f64();
+        let expr = expand_stored(&args[0], Some(var), scope);
+        let residual = |expr: &Expr, x: f64| {
+            expr.eval_in(&scope.bind(var, Value::Number(Number::Float(x))))?
+                .magnitude()
+                .map(Number::to_f64)
+                .map_err(|e| CalcError::from_value_error("solve", e, expr.span))
+        };
+        residual(&expr, guess)?;
+        let mut f = |x| residual(&expr, x).ok().filter(|y| !y.is_nan());
+        let derivative = symbolic::derivative_in(&expr, var, scope.memory, scope.budget).ok();
+        let mut slope = derivative
+            .as_ref()
+            .map(|derivative| move |x| residual(derivative, x).ok());
+        let slope = slope
+            .as_mut()
+            .map(|slope| slope as &mut dyn FnMut(f64) -> Option<f64>);
+        let root =
+            solver::find_root(&mut f, slope, guess, &scope.memory.solver()).map_err(|e| {
+                CalcError::Domain {
+                    name: "solve".to_string(),
+                    message: e.message(),
+                    span: self.span,
+                }
+            })?;
+        Ok(Value::Number(scope.memory.mode().from_f64(root)))
+    }
+
+    // `integrate(expr, x, a, b)` samples `expr` with `x` bound to points
+    // between the bounds. Unlike `solve`, an error at any of them is the
+    // answer, since the integrand has to be defined all the way across.
+    fn eval_integral(&self, args: &[Expr], scope: &Scope) -> Result<Value, CalcError> {
+        self.check_arity("integrate", Arity::Exact(4), args)?;
+        let var = bound_variable("integrate", &args[1])?;
+        let mut bounds = [0.0; 2];
+        for (bound, arg) in bounds.iter_mut().zip(&args[2..]) {
+            *bound = eval_number("integrate", arg, scope)?.to_f64();
+            if !bound.is_finite() {
+                return Err(CalcError::Domain {
+                    name: "integrate".to_string(),
+                    message: "the bounds must be finite".to_string(),
+                    span: arg.span,
+                });
             }
-            Token::Slash => {
-                let (value, next) = eval_primary_expression(tokens, index, memory)?;
-                result /= value;
-                index = next;
+        }
+        let expr = expand_stored(&args[0], Some(var), scope);
+        let mut failure = None;
+        let mut f = |x: f64| {
+            let value = expr.eval_in(&scope.bind(var, Value::Number(Number::Float(x))));
+            match value.and_then(|value| eval_plain("integrate", &value, &expr)) {
+                Ok(number) => Some(number.to_f64()),
+                Err(e) => {
+                    failure.get_or_insert(e);
+                    None
+                }
+            }
+        };
+        let result = quadrature::integrate(&mut f, bounds[0], bounds[1]);
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        let value = result.map_err(|e| CalcError::Domain {
+            name: "integrate".to_string(),
+            message: e.message(),
+            span: self.span,
+        })?;
+        Ok(Value::Number(scope.memory.mode().from_f64(value)))
+    }
+
+    // `load(path, column)` reads one column of a CSV file into a list. The
+    // column is picked by its heading, or by its position counting from 1.
+    fn eval_load(&self, args: &[Expr], scope: &Scope) -> Result<Value, CalcError> {
+        self.check_arity("load", Arity::Exact(2), args)?;
+        let values = eval_args(args, scope)?;
+        let path = values[0]
+            .as_text()
+            .map_err(|e| CalcError::from_value_error("load", e, args[0].span))?;
+        let column = match &values[1] {
+            Value::Text(name) => Column::Name(name.clone()),
+            value => {
+                let number = eval_plain("load", value, &args[1])?;
+                let position = number
+                    .to_i64()
+                    .filter(|&position| position >= 1)
+                    .ok_or_else(|| CalcError::Domain {
+                        name: "load".to_string(),
+                        message: "the column number must be a whole number from 1".to_string(),
+                        span: args[1].span,
+                    })?;
+                Column::Number(position as usize)
+            }
+        };
+        statistics::load_column(Path::new(path), &column, scope.memory.mode())
+            .map(Value::List)
+            .map_err(|e| CalcError::Load {
+                path: path.to_string(),
+                message: e.message(),
+                span: self.span,
+            })
+    }
+
+    // `sum(expr, i, from, to)` and `product(expr, i, from, to)` evaluate
+    // `expr` once for each whole number from `from` to `to` in turn, with
+    // `i` bound to it. The terms may carry units; an empty range gives 0
+    // or 1. The terms of a series nested in another count against the
+    // same budget, so `sum(sum(...))` cannot run to a million squared.
+    fn eval_series(&self, name: &str, args: &[Expr], scope: &Scope) -> Result<Value, CalcError> {
+        self.check_arity(name, Arity::Exact(4), args)?;
+        let var = bound_variable(name, &args[1])?;
+        let domain = |message: String, span| CalcError::Domain {
+            name: name.to_string(),
+            message,
+            span,
+        };
+        let bound = |arg: &Expr| {
+            eval_number(name, arg, scope)?
+                .to_i64()
+                .ok_or_else(|| domain("the bounds must be whole numbers".to_string(), arg.span))
+        };
+        let (from, to) = (bound(&args[2])?, bound(&args[3])?);
+        if !scope
+            .budget
+            .take_terms(i128::from(to) - i128::from(from) + 1)
+        {
+            let message = format!("at most {} terms are allowed", MAX_TERMS);
+            return Err(domain(message, self.span));
+        }
+        let expr = expand_stored(&args[0], Some(var), scope);
+        let mode = scope.memory.mode();
+        let mut total: Option<Value> = None;
+        for i in from..=to {
+            let term =
+                expr.eval_in(&scope.bind(var, Value::Number(mode.convert(&Number::Integer(i)))))?;
+            total = Some(match total {
+                None => term,
+                Some(total) if name == "sum" => total
+                    .add(&term)
+                    .map_err(|e| CalcError::from_value_error("+", e, self.span))?,
+                Some(total) => total
+                    .mul(&term)
+                    .map_err(|e| CalcError::from_value_error("*", e, self.span))?,
+            });
+        }
+        let empty = if name == "sum" { 0.0 } else { 1.0 };
+        Ok(total.unwrap_or_else(|| Value::Number(mode.from_f64(empty))))
+    }
+
+    fn check_arity(&self, name: &str, arity: Arity, args: &[Expr]) -> Result<(), CalcError> {
+        if arity.accepts(args.len()) {
+            Ok(())
+        } else {
+            Err(CalcError::ArityMismatch {
+                name: name.to_string(),
+                expected: arity,
+                found: args.len(),
+                span: self.span,
+            })
+        }
+    }
+
+    // The body was parsed from the definition line, so any error raised
+    // inside it is moved to the call site the user is looking at.
+    fn call_user_function(
+        &self,
+        name: &str,
+        function: &UserFunction,
+        args: &[Expr],
+        scope: &Scope,
+    ) -> Result<Value, CalcError> {
+        self.check_arity(name, Arity::Exact(function.params.len()), args)?;
+        if scope.depth >= MAX_CALL_DEPTH {
+            return Err(CalcError::RecursionLimit {
+                name: name.to_string(),
+                span: self.span,
+            });
+        }
+        let values = eval_args(args, scope)?;
+        let inner = Scope {
+            locals: function.params.iter().cloned().zip(values).collect(),
+            depth: scope.depth + 1,
+            ..*scope
+        };
+        function
+            .body
+            .eval_in(&inner)
+            .map_err(|e| e.with_span(self.span))
+    }
+
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            // A literal prints as `-2` or `1/3`, which binds like the
+            // operator it contains.
+            ExprKind::Number(value) if value.to_string().contains('/') => {
+                BinaryOp::Div.precedence()
             }
-            _ => break,
+            ExprKind::Number(value) if value.is_negative() => UNARY_PRECEDENCE,
+            ExprKind::Unary { op, .. } => op.precedence(),
+            ExprKind::Binary { op, .. } => op.precedence(),
+            ExprKind::Convert { .. } => CONVERT_PRECEDENCE,
+            _ => u8::MAX,
         }
     }
-    Ok((result, index))
 }
 
-fn eval_primary_expression(
-    tokens: &[Token],
-    index: usize,
-    memory: &Memory,
-) -> Result<(f64, usize), String> {
-    let first_token = &tokens[index];
-    match first_token {
-        Token::LParen => {
-            let (result, next) = eval_additive_expression(tokens, index + 1, memory)?;
-            if next < tokens.len() && matches!(tokens[next], Token::RParen) {
-                Ok((result, next + 1))
-            } else {
-                Err("Missing closing parenthesis".to_string())
+fn shift(amount: i64) -> Result<u32, NumberError> {
+    u32::try_from(amount)
+        .ok()
+        .filter(|&amount| amount < i64::BITS)
+        .ok_or(NumberError::Domain("shift amount must be between 0 and 63"))
+}
+
+// Shifting bits out of the top is an overflow, like any other integer result
+// that does not fit.
+fn shift_left(value: i64, amount: i64) -> Result<i64, NumberError> {
+    let shifted = value << shift(amount)?;
+    if shifted >> shift(amount)? == value {
+        Ok(shifted)
+    } else {
+        Err(NumberError::Overflow)
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter, operand: &Expr, parenthesize: bool) -> fmt::Result {
+    if parenthesize {
+        write!(f, "({})", operand)
+    } else {
+        write!(f, "{}", operand)
+    }
+}
+
+// An operand needs parentheses when it binds looser than its operator, or
+// equally tight on the side the operator does not associate towards. A unary
+// sign may stand unparenthesized as an exponent (`2 ^ -1`) but not as a base,
+// nor after `%`, where `a % -b` would read as `a% - b`.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Number(value) => write!(f, "{}", value),
+            ExprKind::Variable(name) => write!(f, "{}", name),
+            ExprKind::Text(text) => write!(f, "\"{}\"", text),
+            ExprKind::History(number) => write!(f, "${}", number),
+            ExprKind::Call { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Quantity { value, unit } => {
+                let parenthesize = !matches!(value.kind, ExprKind::Number(_) | ExprKind::List(_));
+                write_operand(f, value, parenthesize)?;
+                write!(f, " {}", unit)
+            }
+            ExprKind::Convert { expr, unit } => {
+                write_operand(f, expr, expr.precedence() < CONVERT_PRECEDENCE)?;
+                write!(f, " to {}", unit)
             }
+            ExprKind::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            ExprKind::Unary {
+                op: UnaryOp::Percent,
+                operand,
+            } => {
+                // The parser reads one `%` after an operand, so a percent
+                // of a percent needs parentheses: `(5%)%`.
+                write_operand(f, operand, operand.precedence() <= PERCENT_PRECEDENCE)?;
+                write!(f, "%")
+            }
+            ExprKind::Unary { op, operand } => {
+                write!(f, "{}", op.symbol())?;
+                if *op == UnaryOp::Not {
+                    write!(f, " ")?;
+                }
+                write_operand(f, operand, operand.precedence() < op.precedence())
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let precedence = op.precedence();
+                let (lhs_parens, rhs_parens) = if op.is_right_associative() {
+                    (
+                        lhs.precedence() <= precedence,
+                        rhs.precedence() < precedence.min(UNARY_PRECEDENCE),
+                    )
+                } else {
+                    (
+                        lhs.precedence() < precedence,
+                        rhs.precedence() <= precedence || (*op == BinaryOp::Rem && rhs.is_signed()),
+                    )
+                };
+                write_operand(f, lhs, lhs_parens)?;
+                write!(f, " {} ", op.symbol())?;
+                write_operand(f, rhs, rhs_parens)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Expr(Expr),
+    Let {
+        name: String,
+        value: Expr,
+    },
+    FnDef {
+        name: String,
+        function: UserFunction,
+    },
+    Memory {
+        name: String,
+        op: MemoryOp,
+        operand: Option<Expr>,
+        span: Span,
+    },
+}
+
+pub const MAX_CALL_DEPTH: usize = 100;
+
+/// How deeply one evaluation may recurse, counting each subexpression,
+/// function body and derivative step on the way down. Calls nest inside
+/// each other's arguments and bodies, so this is what bounds the stack.
+pub const MAX_EVAL_DEPTH: usize = 5000;
+
+// Evaluation runs on a thread of its own with this much stack, which holds
+// `MAX_EVAL_DEPTH` levels of the largest frames with room to spare.
+const EVAL_STACK_SIZE: usize = 256 << 20;
+
+/// Runs `f` on a thread with `EVAL_STACK_SIZE` of stack, so that the depth
+/// limit holds whichever thread the caller is on.
+pub(crate) fn on_eval_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(EVAL_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("failed to start the evaluation thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// What one evaluation has used of its limits, shared by every scope and
+/// step within it.
+pub(crate) struct Budget {
+    depth: Cell<usize>,
+    terms_left: Cell<i64>,
+}
+
+impl Budget {
+    pub(crate) fn new() -> Self {
+        Self {
+            depth: Cell::new(0),
+            terms_left: Cell::new(MAX_TERMS),
+        }
+    }
+
+    /// Sets aside `count` terms of a series, or returns false if that
+    /// would take the evaluation past `MAX_TERMS` in all.
+    fn take_terms(&self, count: i128) -> bool {
+        let left = self.terms_left.get();
+        if count > i128::from(left) {
+            return false;
+        }
+        self.terms_left.set(left - count.max(0) as i64);
+        true
+    }
+
+    /// Runs `f` one level deeper, failing at `span` past `MAX_EVAL_DEPTH`.
+    pub(crate) fn nested<T>(
+        &self,
+        span: Span,
+        f: impl FnOnce() -> Result<T, CalcError>,
+    ) -> Result<T, CalcError> {
+        let depth = self.depth.get();
+        if depth >= MAX_EVAL_DEPTH {
+            return Err(CalcError::TooDeep { span });
+        }
+        self.depth.set(depth + 1);
+        let result = f();
+        self.depth.set(depth);
+        result
+    }
+}
+
+/// The most terms `sum` and `product` will evaluate in one evaluation,
+/// counting those of nested series too.
+pub const MAX_TERMS: i64 = 1_000_000;
+
+// Function parameters live in `locals` and shadow memory slots, which in
+// turn shadow `ans` and the built-in constants. A function body only sees
+// its own parameters, never the locals of its caller. Unit names are not
+// variables: they only count after a number, as in `2 km`.
+struct Scope<'a> {
+    memory: &'a Memory,
+    history: &'a History,
+    budget: &'a Budget,
+    locals: HashMap<String, Value>,
+    depth: usize,
+}
+
+impl<'a> Scope<'a> {
+    fn global(memory: &'a Memory, history: &'a History, budget: &'a Budget) -> Self {
+        Self {
+            memory,
+            history,
+            budget,
+            locals: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    // The variable of `solve`, `integrate`, `sum` and `product` is bound
+    // the way a parameter is, on top of whatever locals are in scope.
+    fn bind(&self, name: &str, value: Value) -> Scope<'a> {
+        let mut locals = self.locals.clone();
+        locals.insert(name.to_string(), value);
+        Scope { locals, ..*self }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
+        let mode = self.memory.mode();
+        self.locals
+            .get(name)
+            .cloned()
+            .or_else(|| self.memory.get(name))
+            .or_else(|| self.history.last().filter(|_| name == ANS).cloned())
+            .or_else(|| functions::constant(name).map(|value| mode.from_f64(value).into()))
+            .or_else(|| boolean(name))
+    }
+
+    // Whether `name` still means a built-in constant such as `e`, rather
+    // than a local or a slot of the same name.
+    fn is_builtin_constant(&self, name: &str) -> bool {
+        functions::constant(name).is_some()
+            && !self.locals.contains_key(name)
+            && self.memory.get(name).is_none()
+    }
+}
+
+// The variable of `diff` and `solve` names the unknown; it is never
+// evaluated, so it has to be written as a plain name.
+fn bound_variable<'e>(name: &str, arg: &'e Expr) -> Result<&'e str, CalcError> {
+    match &arg.kind {
+        ExprKind::Variable(var) => Ok(var),
+        _ => Err(CalcError::Domain {
+            name: name.to_string(),
+            message: "the variable must be a plain name".to_string(),
+            span: arg.span,
+        }),
+    }
+}
+
+fn eval_number(name: &str, arg: &Expr, scope: &Scope) -> Result<Number, CalcError> {
+    eval_plain(name, &arg.eval_in(scope)?, arg)
+}
+
+fn eval_plain(name: &str, value: &Value, arg: &Expr) -> Result<Number, CalcError> {
+    value
+        .plain_number()
+        .cloned()
+        .map_err(|e| CalcError::from_value_error(name, e, arg.span))
+}
+
+// Names holding an expression, such as an earlier derivative, are expanded
+// in place; other names, and `var` itself, stay symbols.
+fn expand_stored(expr: &Expr, var: Option<&str>, scope: &Scope) -> Expr {
+    let stored = |symbol: &str| match scope.lookup(symbol) {
+        Some(Value::Expr(expr)) if Some(symbol) != var => Some(*expr),
+        _ => None,
+    };
+    symbolic::substitute(expr, &stored)
+}
+
+fn boolean(name: &str) -> Option<Value> {
+    match name {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => None,
+    }
+}
+
+fn eval_args(args: &[Expr], scope: &Scope) -> Result<Vec<Value>, CalcError> {
+    args.iter().map(|arg| arg.eval_in(scope)).collect()
+}
+
+pub fn eval_expression(tokens: &[Token], memory: &Memory) -> Result<Value, CalcError> {
+    parse(tokens)?.eval(memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::NumericMode;
+    use crate::parser::{MAX_NESTING, MAX_OPERATORS};
+    use crate::solver::SolverSettings;
+    use crate::token::tokenize;
+    use crate::units::Dimension;
+    use crate::value::Shape;
+
+    fn parse_str(input: &str) -> Expr {
+        parse(&tokenize(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut memory = Memory::new();
+        memory.set("A".to_string(), Value::from(4.0));
+        assert_eq!(parse_str("(1+2)*3").eval(&memory), Ok(Value::from(9.0)));
+        assert_eq!(parse_str("10 - 4 - 3").eval(&memory), Ok(Value::from(3.0)));
+        assert_eq!(parse_str("8 / 2 / 2").eval(&memory), Ok(Value::from(2.0)));
+        assert_eq!(parse_str("A * 2 + 1").eval(&memory), Ok(Value::from(9.0)));
+    }
+
+    #[test]
+    fn test_unary_power_and_remainder() {
+        let memory = Memory::new();
+        let eval = |input: &str| parse_str(input).eval(&memory);
+        assert_eq!(eval("-3 * 2"), Ok(Value::from(-6.0)));
+        assert_eq!(eval("3 * -2"), Ok(Value::from(-6.0)));
+        assert_eq!(eval("- -+2"), Ok(Value::from(2.0)));
+        assert_eq!(eval("2 ^ 10"), Ok(Value::from(1024.0)));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Ok(Value::from(512.0)));
+        assert_eq!(eval("(2 ^ 3) ^ 2"), Ok(Value::from(64.0)));
+        assert_eq!(eval("-2 ^ 2"), Ok(Value::from(-4.0)));
+        assert_eq!(eval("(-2) ^ 2"), Ok(Value::from(4.0)));
+        assert_eq!(eval("2 ^ -1"), Ok(Value::from(0.5)));
+        assert_eq!(eval("2 * 3 ^ 2"), Ok(Value::from(18.0)));
+        assert_eq!(eval("7 % 3"), Ok(Value::from(1.0)));
+        assert_eq!(eval("1 + 7 % 3 * 2"), Ok(Value::from(3.0)));
+        assert_eq!(eval("-7 % 3"), Ok(Value::from(-1.0)));
+        assert_eq!(
+            eval("7 % 0"),
+            Err(CalcError::DivisionByZero {
+                span: Span::new(4, 5)
+            })
+        );
+    }
+
+    #[test]
+    fn test_percent() {
+        let memory = Memory::new();
+        let eval = |input: &str| parse_str(input).eval(&memory).map(|v| v.to_string());
+        assert_eq!(eval("15%"), Ok("0.15".to_string()));
+        assert_eq!(eval("200 + 15%"), Ok("230".to_string()));
+        assert_eq!(eval("200 - 15%"), Ok("170".to_string()));
+        assert_eq!(eval("200 * 15%"), Ok("30".to_string()));
+        assert_eq!(eval("200 + 15% * 2"), Ok("200.3".to_string()));
+        assert_eq!(eval("(200 + 10%) + 10%"), Ok("242".to_string()));
+        assert_eq!(eval("50 m - 10%"), Ok("45 m".to_string()));
+        assert_eq!(eval("[100, 200] + 5%"), Ok("[105, 210]".to_string()));
+        assert_eq!(eval("10 % 4"), Ok("2".to_string()));
+        assert_eq!(eval("10 % (5 - 1)"), Ok("2".to_string()));
+        assert_eq!(eval("max(50%, 1%)"), Ok("0.5".to_string()));
+        assert_eq!(eval("15% - 1"), Ok("-0.85".to_string()));
+        assert_eq!(eval("15%-1"), Ok("-0.85".to_string()));
+        assert_eq!(eval("7 % -3"), Ok("1".to_string()));
+        assert_eq!(eval("7 %+3"), Ok("1".to_string()));
+        assert_eq!(eval("(5)% + 1"), Ok("1.05".to_string()));
+        let integer = Memory::with_mode(NumericMode::Integer);
+        assert_eq!(
+            parse_str("200 + 15%").eval(&integer).map(|v| v.to_string()),
+            Ok("230".to_string())
+        );
+        assert_eq!(
+            parse_str("15%").eval(&integer).map(|v| v.to_string()),
+            Ok("0.15".to_string())
+        );
+        assert_eq!(
+            parse_str("diff(x + 10%, x)")
+                .eval(&memory)
+                .map(|v| v.to_string()),
+            Ok("1.1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exact_modes() {
+        let rational = Memory::with_mode(NumericMode::Rational);
+        let eval =
+            |input: &str, memory: &Memory| parse_str(input).eval(memory).unwrap().to_string();
+        assert_eq!(eval("0.1 + 0.2", &rational), "0.3");
+        assert_eq!(eval("1 / 3 * 3", &rational), "1");
+        assert_eq!(eval("2 ^ -3 + 1 / 3", &rational), "11/24");
+        assert_eq!(eval("sqrt(2.25)", &rational), "1.5");
+
+        let decimal = Memory::with_mode(NumericMode::Decimal(4));
+        assert_eq!(eval("0.1 + 0.2", &decimal), "0.3");
+        assert_eq!(eval("2 / 3", &decimal), "0.6667");
+        assert_eq!(eval("max(1 / 3, 0.3)", &decimal), "0.3333");
+
+        let float = Memory::new();
+        assert_eq!(eval("0.1 + 0.2", &float), "0.30000000000000004");
+        assert_eq!(
+            parse_str("0 ^ -1").eval(&rational),
+            Err(CalcError::Domain {
+                name: "^".to_string(),
+                message: "zero cannot be raised to a negative power".to_string(),
+                span: Span::new(0, 6)
+            })
+        );
+    }
+
+    #[test]
+    fn test_integer_mode() {
+        let integer = Memory::with_mode(NumericMode::Integer);
+        let eval = |input: &str| parse_str(input).eval(&integer);
+        let value = |n: i64| Ok(Value::Number(Number::Integer(n)));
+        assert_eq!(eval("0xff & 0b1010 | 0o100"), value(74));
+        assert_eq!(eval("0xf0 xor 0xff"), value(0x0f));
+        assert_eq!(eval("1 << 4 + 1"), value(32));
+        assert_eq!(eval("-256 >> 4"), value(-16));
+        assert_eq!(eval("~0"), value(-1));
+        assert_eq!(eval("7 / 2"), value(3));
+        assert_eq!(eval("2 ^ 62"), value(1 << 62));
+        assert_eq!(
+            eval("2 ^ 63"),
+            Err(CalcError::Overflow {
+                span: Span::new(0, 6)
+            })
+        );
+        assert_eq!(
+            eval("0x7fffffffffffffff + 1"),
+            Err(CalcError::Overflow {
+                span: Span::new(0, 22)
+            })
+        );
+        assert_eq!(
+            eval("1 << 63"),
+            Err(CalcError::Overflow {
+                span: Span::new(0, 7)
+            })
+        );
+        assert!(matches!(eval("1 << 64"), Err(CalcError::Domain { .. })));
+        assert!(matches!(eval("1.5 & 1"), Err(CalcError::Domain { .. })));
+
+        let float = Memory::new();
+        assert_eq!(parse_str("0x10 | 1").eval(&float), Ok(Value::from(17.0)));
+        assert!(matches!(
+            parse_str("1 km & 1").eval(&float),
+            Err(CalcError::IncompatibleUnits { .. })
+        ));
+    }
+
+    #[test]
+    fn test_comparisons_and_conditionals() {
+        let mut memory = Memory::new();
+        let eval = |input: &str, memory: &Memory| parse_str(input).eval(memory);
+        assert_eq!(eval("1 + 1 == 2", &memory), Ok(Value::Bool(true)));
+        assert_eq!(eval("3 != 3", &memory), Ok(Value::Bool(false)));
+        assert_eq!(eval("1 km > 999 m", &memory), Ok(Value::Bool(true)));
+        assert_eq!(eval("not 2 <= 1 and true", &memory), Ok(Value::Bool(true)));
+        assert_eq!(eval("false or 1 >= 2", &memory), Ok(Value::Bool(false)));
+        assert_eq!(eval("if(2 > 1, 10, 20)", &memory), Ok(Value::from(10.0)));
+        assert_eq!(eval("if(false, 1 / 0, 3)", &memory), Ok(Value::from(3.0)));
+        assert_eq!(eval("false and 1 / 0 > 1", &memory), Ok(Value::Bool(false)));
+
+        let define = |memory: &mut Memory, line: &str| match crate::parser::parse_statement(
+            &tokenize(line).unwrap(),
+        ) {
+            Ok(Statement::FnDef { name, function }) => memory.define(name, function),
+            other => panic!("not a definition: {:?}", other),
+        };
+        define(&mut memory, "fn fact(n) = if(n <= 1, 1, n * fact(n - 1))");
+        define(&mut memory, "fn tier(x) = if(x < 10, 1, if(x < 100, 2, 3))");
+        assert_eq!(eval("fact(5)", &memory), Ok(Value::from(120.0)));
+        assert_eq!(eval("tier(50)", &memory), Ok(Value::from(2.0)));
+
+        assert_eq!(
+            eval("1 and true", &memory),
+            Err(CalcError::TypeMismatch {
+                name: "and".to_string(),
+                expected: "boolean",
+                found: "number",
+                span: Span::new(0, 1)
+            })
+        );
+        assert_eq!(
+            eval("(1 < 2) + 1", &memory),
+            Err(CalcError::TypeMismatch {
+                name: "+".to_string(),
+                expected: "number",
+                found: "boolean",
+                span: Span::new(0, 11)
+            })
+        );
+        assert!(matches!(
+            eval("if(1, 2, 3)", &memory),
+            Err(CalcError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            eval("if(true, 2)", &memory),
+            Err(CalcError::ArityMismatch { .. })
+        ));
+        assert!(matches!(
+            eval("1 m < 1 s", &memory),
+            Err(CalcError::IncompatibleUnits { .. })
+        ));
+        assert!(matches!(
+            eval("sqrt(true)", &memory),
+            Err(CalcError::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_symbolic() {
+        let mut memory = Memory::new();
+        memory.set("x".to_string(), Value::from(3.0));
+        let eval = |input: &str, memory: &Memory| {
+            parse_str(input).eval(memory).map(|value| value.to_string())
+        };
+        assert_eq!(
+            eval("diff(x ^ 2 * sin(x), x)", &memory).unwrap(),
+            "2 * x * sin(x) + x ^ 2 * cos(x)"
+        );
+        assert_eq!(eval("simplify(a * 1 + 0)", &memory).unwrap(), "a");
+        assert_eq!(eval("simplify(ln(e) * a)", &memory).unwrap(), "a");
+        memory.set("e".to_string(), Value::from(5.0));
+        assert_eq!(eval("simplify(ln(e) * a)", &memory).unwrap(), "ln(e) * a");
+
+        let derivative = parse_str("diff(x ^ 3, x)").eval(&memory).unwrap();
+        assert_eq!(derivative.kind(), "expression");
+        memory.set("d".to_string(), derivative);
+        assert_eq!(eval("diff(d, x)", &memory).unwrap(), "6 * x");
+        assert!(matches!(
+            parse_str("d + 1").eval(&memory),
+            Err(CalcError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            parse_str("diff(x, 2)").eval(&memory),
+            Err(CalcError::Domain { .. })
+        ));
+        assert!(matches!(
+            parse_str("simplify(x, y)").eval(&memory),
+            Err(CalcError::ArityMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut memory = Memory::new();
+        memory.set("x".to_string(), Value::from(10.0));
+        define(&mut memory, "f", &["t"], "t ^ 3 - 2 * t - 5");
+        let solve = |input: &str, memory: &Memory| {
+            let value = parse_str(input).eval(memory).unwrap();
+            value.magnitude().unwrap().to_f64()
+        };
+        // `x` is the unknown inside solve() even though a slot is named x.
+        assert!((solve("solve(x ^ 2 - 2, x, 1)", &memory) - 2f64.sqrt()).abs() < 1e-12);
+        assert!((solve("solve(x ^ 2 - 2, x, -1)", &memory) + 2f64.sqrt()).abs() < 1e-12);
+        assert!((solve("solve(f(t), t, 2)", &memory) - 2.094_551_481_542_326_5).abs() < 1e-12);
+        assert!((solve("solve(t * 1 m - 250 cm, t, x)", &memory) - 2.5).abs() < 1e-12);
+        // A double root has no sign change and falls back to Newton's method.
+        assert!((solve("solve((x - 3) ^ 2, x, 0)", &memory) - 3.0).abs() < 1e-6);
+        memory.set(
+            "d".to_string(),
+            parse_str("diff(x ^ 2, x)").eval(&memory).unwrap(),
+        );
+        assert!((solve("solve(d - 3, x, 0)", &memory) - 1.5).abs() < 1e-12);
+
+        let error = |input: &str| parse_str(input).eval(&memory).unwrap_err().to_string();
+        assert_eq!(
+            error("solve(exp(x), x, 0)"),
+            "Domain error in 'solve': no convergence after 200 iterations"
+        );
+        assert_eq!(
+            error("solve(x ^ 2 + 1, x, 0)"),
+            "Domain error in 'solve': no root found near the guess"
+        );
+        assert_eq!(
+            error("solve(sqrt(x), x, -1)"),
+            "Domain error in 'sqrt': argument must not be negative"
+        );
+        assert_eq!(error("solve(y - 1, x, 0)"), "Unknown memory slot 'y'");
+        assert_eq!(
+            error("solve(x > 1, x, 0)"),
+            "Type mismatch in 'solve': expected a number but found a boolean"
+        );
+        assert_eq!(
+            error("solve(x, 2, 0)"),
+            "Domain error in 'solve': the variable must be a plain name"
+        );
+        memory.set_solver(SolverSettings {
+            tolerance: 1e-12,
+            max_iterations: 3,
+        });
+        assert_eq!(
+            parse_str("solve(cos(x) - x, x, 0)").eval(&memory),
+            Err(CalcError::Domain {
+                name: "solve".to_string(),
+                message: "no convergence after 3 iterations".to_string(),
+                span: Span::new(0, 23)
+            })
+        );
+    }
+
+    #[test]
+    fn test_integrals_and_series() {
+        let mut memory = Memory::new();
+        memory.set("i".to_string(), Value::from(100.0));
+        memory.set("x".to_string(), Value::from(100.0));
+        let eval = |input: &str, memory: &Memory| parse_str(input).eval(memory);
+        let number = |input: &str, memory: &Memory| {
+            eval(input, memory).unwrap().magnitude().unwrap().to_f64()
+        };
+        assert!((number("integrate(x ^ 2, x, 0, 3)", &memory) - 9.0).abs() < 1e-12);
+        assert!((number("integrate(sin(x), x, 0, pi)", &memory) - 2.0).abs() < 1e-12);
+        assert!((number("integrate(1 / sqrt(x), x, 0, 1)", &memory) - 2.0).abs() < 1e-8);
+        // The bound variable shadows the slot, which is left alone.
+        assert_eq!(eval("sum(i, i, 1, 100)", &memory), Ok(Value::from(5050.0)));
+        assert_eq!(eval("product(i, i, 1, 5)", &memory), Ok(Value::from(120.0)));
+        assert_eq!(eval("sum(i, i, 5, 1)", &memory), Ok(Value::from(0.0)));
+        assert_eq!(eval("product(i, i, 5, 1)", &memory), Ok(Value::from(1.0)));
+        assert_eq!(memory.get("i"), Some(Value::from(100.0)));
+        assert_eq!(
+            eval("sum(sum(i * j, j, 1, i), i, 1, 3)", &memory),
+            Ok(Value::from(25.0))
+        );
+        assert_eq!(
+            eval("sum((i) m, i, 1, 3) to cm", &memory)
+                .unwrap()
+                .to_string(),
+            "600 cm"
+        );
+
+        let mut rational = Memory::with_mode(NumericMode::Rational);
+        define(&mut rational, "harmonic", &["n"], "sum(1 / k, k, 1, n)");
+        assert_eq!(eval("harmonic(4)", &rational).unwrap().to_string(), "25/12");
+        assert!(matches!(
+            eval(
+                "product(i, i, 1, 30)",
+                &Memory::with_mode(NumericMode::Integer)
+            ),
+            Err(CalcError::Overflow { .. })
+        ));
+
+        let error = |input: &str| eval(input, &memory).unwrap_err().to_string();
+        assert_eq!(
+            error("sum(i, i, 1, 1e7)"),
+            "Domain error in 'sum': at most 1000000 terms are allowed"
+        );
+        assert_eq!(
+            error("sum(sum(1, j, 1, 1e6), i, 1, 1e6)"),
+            "Domain error in 'sum': at most 1000000 terms are allowed"
+        );
+        assert_eq!(
+            error("product(sum(1, j, 1, 999999), i, 1, 2)"),
+            "Domain error in 'sum': at most 1000000 terms are allowed"
+        );
+        assert_eq!(
+            error("sum(i, i, 1, 2.5)"),
+            "Domain error in 'sum': the bounds must be whole numbers"
+        );
+        assert_eq!(
+            error("integrate(ln(x), x, -1, 1)"),
+            "Domain error in 'ln': argument must be positive"
+        );
+        assert_eq!(
+            error("integrate(1 / x, x, 0, 1)"),
+            "Domain error in 'integrate': the integral diverges"
+        );
+        assert_eq!(
+            error("integrate((x) m, x, 0, 1)"),
+            "Incompatible dimensions: length and dimensionless"
+        );
+        assert_eq!(
+            error("product(i, 2, 1, 3)"),
+            "Domain error in 'product': the variable must be a plain name"
+        );
+    }
+
+    #[test]
+    fn test_eval_errors_carry_spans() {
+        let memory = Memory::new();
+        assert_eq!(
+            parse_str("1 + B").eval(&memory),
+            Err(CalcError::UnknownMemory {
+                name: "B".to_string(),
+                span: Span::new(4, 5)
+            })
+        );
+        assert_eq!(
+            parse_str("1 / (2 - 2)").eval(&memory),
+            Err(CalcError::DivisionByZero {
+                span: Span::new(4, 11)
+            })
+        );
+    }
+
+    #[test]
+    fn test_functions_and_constants() {
+        let mut memory = Memory::new();
+        let eval = |input: &str, memory: &Memory| parse_str(input).eval(memory);
+        assert_eq!(eval("sqrt(16) + abs(-2)", &memory), Ok(Value::from(6.0)));
+        assert_eq!(
+            eval("max(1, 7, 3) - min(4, 2)", &memory),
+            Ok(Value::from(5.0))
+        );
+        assert_eq!(
+            eval("floor(2.7) + ceil(2.1) + round(-1.4)", &memory),
+            Ok(Value::from(4.0))
+        );
+        assert_eq!(eval("ln(e)", &memory), Ok(Value::from(1.0)));
+        assert_eq!(eval("cos(pi)", &memory), Ok(Value::from(-1.0)));
+        memory.set("e".to_string(), Value::from(2.0));
+        assert_eq!(eval("e ^ 2", &memory), Ok(Value::from(4.0)));
+    }
+
+    #[test]
+    fn test_function_errors() {
+        let memory = Memory::new();
+        let eval = |input: &str| parse_str(input).eval(&memory);
+        assert_eq!(
+            eval("1 + sqrt(-1)"),
+            Err(CalcError::Domain {
+                name: "sqrt".to_string(),
+                message: "argument must not be negative".to_string(),
+                span: Span::new(4, 12)
+            })
+        );
+        assert_eq!(
+            eval("sqrt(1, 2)"),
+            Err(CalcError::ArityMismatch {
+                name: "sqrt".to_string(),
+                expected: Arity::Exact(1),
+                found: 2,
+                span: Span::new(0, 10)
+            })
+        );
+        assert!(matches!(
+            eval("max()"),
+            Err(CalcError::ArityMismatch { .. })
+        ));
+        assert!(matches!(
+            eval("foo(1)"),
+            Err(CalcError::UnknownFunction { .. })
+        ));
+    }
+
+    fn define(memory: &mut Memory, name: &str, params: &[&str], body: &str) {
+        let function = UserFunction {
+            params: params.iter().map(|param| param.to_string()).collect(),
+            body: parse_str(body),
+        };
+        memory.define(name.to_string(), function);
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let mut memory = Memory::new();
+        memory.set("w".to_string(), Value::from(100.0));
+        define(&mut memory, "area", &["w", "h"], "w * h");
+        define(&mut memory, "square", &["x"], "area(x, x)");
+        let eval = |input: &str| parse_str(input).eval(&memory);
+        assert_eq!(eval("area(3, 4)"), Ok(Value::from(12.0)));
+        assert_eq!(eval("square(5) + w"), Ok(Value::from(125.0)));
+        assert!(matches!(
+            eval("area(1)"),
+            Err(CalcError::ArityMismatch { found: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_function_body_does_not_see_caller_locals() {
+        let mut memory = Memory::new();
+        define(&mut memory, "inner", &[], "x");
+        define(&mut memory, "outer", &["x"], "inner()");
+        assert_eq!(
+            parse_str("1 + outer(2)").eval(&memory),
+            Err(CalcError::UnknownMemory {
+                name: "x".to_string(),
+                span: Span::new(4, 12)
+            })
+        );
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let mut memory = Memory::new();
+        define(&mut memory, "forever", &["n"], "forever(n + 1)");
+        assert_eq!(
+            parse_str("forever(0)").eval(&memory),
+            Err(CalcError::RecursionLimit {
+                name: "forever".to_string(),
+                span: Span::new(0, 10)
+            })
+        );
+    }
+
+    #[test]
+    fn test_eval_depth_limit() {
+        let mut memory = Memory::new();
+        let wrapped =
+            |open: &str, close: &str| format!("{}g(n - 1){}", open.repeat(60), close.repeat(60));
+        define(
+            &mut memory,
+            "g",
+            &["n"],
+            &format!("if(n <= 0, 0, {})", wrapped("(", ")")),
+        );
+        assert_eq!(parse_str("g(99)").eval(&memory), Ok(Value::from(0.0)));
+        for (open, close) in [("max(", ")"), ("sqrt(", ")"), ("[", "]")] {
+            let body = format!("if(n <= 0, 0, {})", wrapped(open, close));
+            define(&mut memory, "g", &["n"], &body);
+            assert!(parse_str("g(3)").eval(&memory).is_ok(), "{}", open);
+            assert_eq!(
+                parse_str("g(99)").eval(&memory),
+                Err(CalcError::TooDeep {
+                    span: Span::new(0, 5)
+                }),
+                "{}",
+                open
+            );
         }
-        Token::Number(val) => Ok((*val, index + 1)),
-        Token::MemoryRef(memory_name) => Ok((memory.get(memory_name)?, index + 1)),
-        _ => unreachable!(),
+        let sum = format!("0{}", " + 1".repeat(MAX_OPERATORS));
+        assert_eq!(
+            parse_str(&sum).eval(&memory),
+            Ok(Value::from(MAX_OPERATORS as f64))
+        );
+        define(
+            &mut memory,
+            "h",
+            &["x"],
+            &format!("{}x{}", "sin(".repeat(60), ")".repeat(60)),
+        );
+        define(&mut memory, "k", &["x"], "h(h(h(h(h(h(h(h(h(h(x))))))))))");
+        assert!(matches!(
+            parse_str("diff(k(k(k(k(k(k(k(k(k(x))))))))), x)").eval(&memory),
+            Err(CalcError::TooDeep { .. })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_input_does_not_panic() {
+        let mut memory = Memory::new();
+        for input in [
+            "", "+", "1 +", "* 2", "()", "(1", "1)", "((", "1 / ", "memA+ 1", "1 memA-",
+        ] {
+            let result = tokenize(input).and_then(|tokens| eval_expression(&tokens, &memory));
+            assert!(result.is_err(), "{:?} should fail", input);
+        }
+        memory.set("x".to_string(), Value::from(1.0));
+        let nested = |n: usize| format!("{}x{}", "(-".repeat(n), ")".repeat(n));
+        let eval =
+            |input: &str| tokenize(input).and_then(|tokens| eval_expression(&tokens, &memory));
+        assert_eq!(eval(&nested(MAX_NESTING / 2)), Ok(Value::from(1.0)));
+        assert!(matches!(
+            eval(&nested(10_000)),
+            Err(CalcError::TooDeep { .. })
+        ));
+    }
+
+    #[test]
+    fn test_history_references() {
+        let mut memory = Memory::new();
+        let mut history = History::new();
+        let eval = |input: &str, memory: &Memory, history: &History| {
+            parse_str(input).eval_with_history(memory, history)
+        };
+        assert_eq!(
+            eval("ans + 1", &memory, &history),
+            Err(CalcError::UnknownResult {
+                reference: "ans".to_string(),
+                span: Span::new(0, 3)
+            })
+        );
+        history.push(Value::from(6.0));
+        history.push(Value::from(7.0));
+        assert_eq!(eval("ans * $1", &memory, &history), Ok(Value::from(42.0)));
+        assert_eq!(
+            eval("$1 + $3", &memory, &history),
+            Err(CalcError::UnknownResult {
+                reference: "$3".to_string(),
+                span: Span::new(5, 7)
+            })
+        );
+        memory.set("ans".to_string(), Value::from(1.0));
+        assert_eq!(eval("ans", &memory, &history), Ok(Value::from(1.0)));
+        assert_eq!(parse_str("$2 - ans").to_string(), "$2 - ans");
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut memory = Memory::new();
+        memory.set("x".to_string(), Value::from(2.0));
+        let eval = |input: &str, memory: &Memory| {
+            parse_str(input).eval(memory).map(|value| value.to_string())
+        };
+        assert_eq!(eval("[1, 2, 3] * x + 1", &memory).unwrap(), "[3, 5, 7]");
+        assert_eq!(eval("sqrt([4, 9, 16])", &memory).unwrap(), "[2, 3, 4]");
+        assert_eq!(eval("[1, 2] km to m", &memory).unwrap(), "[1000 m, 2000 m]");
+        assert_eq!(
+            eval("[[1, 2], [3, 4]] == [[1, 2], [3, 4]]", &memory).unwrap(),
+            "true"
+        );
+        assert_eq!(eval("sum([1, 2, 3])", &memory).unwrap(), "6");
+        assert_eq!(eval("sum(i, i, 1, 3)", &memory).unwrap(), "6");
+        assert_eq!(eval("diff([x, x ^ 2], x)", &memory).unwrap(), "[1, 2 * x]");
+        memory.set("v".to_string(), parse_str("[3, 4]").eval(&memory).unwrap());
+        assert_eq!(eval("sqrt(dot(v, v))", &memory).unwrap(), "5");
+
+        assert_eq!(
+            parse_str("[[1, 2], [3]]").eval(&memory),
+            Err(CalcError::ShapeMismatch {
+                name: "[]".to_string(),
+                left: Shape(vec![2]),
+                right: Shape(vec![1]),
+                span: Span::new(9, 12)
+            })
+        );
+        assert_eq!(
+            eval("[1, 2] + [1, 2, 3]", &memory).unwrap_err().to_string(),
+            "Shape mismatch in '+': list of 2 and list of 3"
+        );
+        assert_eq!(
+            parse_str("[1, 2] / [1, 0]").eval(&memory),
+            Err(CalcError::DivisionByZero {
+                span: Span::new(0, 15)
+            })
+        );
+        assert_eq!(
+            eval("[1, 2] < 3", &memory).unwrap_err().to_string(),
+            "Type mismatch in '<': expected a number but found a list"
+        );
+        assert_eq!(
+            eval("max([1, 2])", &memory).unwrap_err().to_string(),
+            "Type mismatch in 'max': expected a number but found a list"
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        let memory = Memory::with_mode(NumericMode::Rational);
+        let eval = |input: &str| {
+            parse_str(input)
+                .eval(&memory)
+                .map(|value| value.to_string())
+        };
+        assert_eq!(eval("3 GiB / 200 MB/s"), Ok("16.10612736 s".to_string()));
+        assert_eq!(eval("5 km + 300 m"), Ok("5.3 km".to_string()));
+        assert_eq!(eval("2 h + 30 min to min"), Ok("150 min".to_string()));
+        assert_eq!(eval("100 Mbit/s * 1 h in GB"), Ok("45 GB".to_string()));
+        assert_eq!(eval("(1/4) km to m"), Ok("250 m".to_string()));
+        assert_eq!(eval("1 km / 1 m"), Ok("1000".to_string()));
+        assert_eq!(
+            parse_str("5 km + 3 s").eval(&memory),
+            Err(CalcError::IncompatibleUnits {
+                left: Unit::lookup("km").unwrap().dimension(),
+                right: Unit::lookup("s").unwrap().dimension(),
+                span: Span::new(0, 10)
+            })
+        );
+        assert_eq!(
+            parse_str("sqrt(4 m)").eval(&memory),
+            Err(CalcError::IncompatibleUnits {
+                left: Unit::lookup("m").unwrap().dimension(),
+                right: Dimension::NONE,
+                span: Span::new(5, 8)
+            })
+        );
+        assert!(matches!(
+            parse_str("2 m to s").eval(&memory),
+            Err(CalcError::IncompatibleUnits { .. })
+        ));
+        assert!(matches!(
+            parse_str("10 m / s").eval(&memory),
+            Err(CalcError::UnknownMemory { .. })
+        ));
+    }
+
+    #[test]
+    fn test_display_minimal_parens() {
+        assert_eq!(parse_str("(1+2)*3").to_string(), "(1 + 2) * 3");
+        assert_eq!(parse_str("1 - (2 - 3)").to_string(), "1 - (2 - 3)");
+        assert_eq!(parse_str("(1 - 2) - 3").to_string(), "1 - 2 - 3");
+        assert_eq!(parse_str("1 + (2 * 3)").to_string(), "1 + 2 * 3");
+        assert_eq!(parse_str("2 ^ (3 ^ 2)").to_string(), "2 ^ 3 ^ 2");
+        assert_eq!(parse_str("(2 ^ 3) ^ 2").to_string(), "(2 ^ 3) ^ 2");
+        assert_eq!(parse_str("-(2 ^ 2)").to_string(), "-2 ^ 2");
+        assert_eq!(parse_str("(-2) ^ 2").to_string(), "(-2) ^ 2");
+        assert_eq!(parse_str("2 ^ (-1)").to_string(), "2 ^ -1");
+        assert_eq!(parse_str("-(1 + 2)").to_string(), "-(1 + 2)");
+        assert_eq!(parse_str("(1 | 2) & 3").to_string(), "(1 | 2) & 3");
+        assert_eq!(parse_str("1 xor (2 << 1)").to_string(), "1 xor 2 << 1");
+        assert_eq!(parse_str("~(1 + 2)").to_string(), "~(1 + 2)");
+        assert_eq!(parse_str("not (a < 1)").to_string(), "not a < 1");
+        assert_eq!(parse_str("-(not a)").to_string(), "-(not a)");
+        assert_eq!(parse_str("(a or b) and c").to_string(), "(a or b) and c");
+        assert_eq!(
+            parse_str("(1 m to cm) > 3 cm").to_string(),
+            "1 m to cm > 3 cm"
+        );
+        let literal = |value| Expr::new(ExprKind::Number(value), Span::new(0, 0));
+        let power = Expr::binary(BinaryOp::Pow, literal(Number::Integer(-2)), parse_str("x"));
+        assert_eq!(power.to_string(), "(-2) ^ x");
+        let third = parse_str("1 / 3").eval(&Memory::with_mode(NumericMode::Rational));
+        let third = literal(third.unwrap().magnitude().unwrap().clone());
+        let power = Expr::binary(BinaryOp::Pow, parse_str("x"), third);
+        assert_eq!(power.to_string(), "x ^ (1/3)");
+        assert_eq!(parse_str("max(1,(2+3))*2").to_string(), "max(1, 2 + 3) * 2");
+        assert_eq!(parse_str("(1/3) km").to_string(), "(1 / 3) km");
+        assert_eq!(parse_str("a + (15)%").to_string(), "a + 15%");
+        assert_eq!(parse_str("a % (-b)").to_string(), "a % (-b)");
+        assert_eq!(parse_str("a % -b"), parse_str("a % (-b)"));
+        assert_eq!(parse_str("a % ~b").to_string(), "a % ~b");
+        assert_eq!(parse_str("(5%)%").to_string(), "(5%)%");
+        assert_eq!(parse_str("(a + 15)%").to_string(), "(a + 15)%");
+        assert_eq!(parse_str("(-2)% % 3").to_string(), "(-2)% % 3");
+        assert_eq!(parse_str("2 * (3 m to cm)").to_string(), "2 * (3 m to cm)");
+        assert_eq!(
+            parse_str("10 m/s^2 in km/h^2").to_string(),
+            "10 m/s^2 to km/h^2"
+        );
     }
 }
@@ -0,0 +1,187 @@
+//! Time value of money. Rates are per period, so a yearly 6% paid monthly
+//! is `6% / 12`. As in spreadsheets, money paid out is negative and money
+//! received positive: borrowing 1000 now means repaying with negative
+//! payments, `pmt(1%, 12, 1000)` being about -88.85.
+
+use crate::matrix::{divisible, function, vector, ListFunction};
+use crate::number::NumericMode;
+use crate::solver::{find_root, SolverSettings};
+use crate::value::{Value, ValueError};
+
+pub const FINANCE: &[ListFunction] = &[
+    function("pmt", 3, |args, mode| {
+        pmt(
+            &rate(&args[0], mode)?,
+            &amount(&args[1], mode)?,
+            &amount(&args[2], mode)?,
+            mode,
+        )
+    }),
+    function("fv", 3, |args, mode| {
+        fv(
+            &rate(&args[0], mode)?,
+            &amount(&args[1], mode)?,
+            &amount(&args[2], mode)?,
+            mode,
+        )
+    }),
+    function("compound", 3, |args, mode| {
+        let growth = growth(&rate(&args[1], mode)?, &amount(&args[2], mode)?, mode)?;
+        amount(&args[0], mode)?.mul(&growth)
+    }),
+    function("npv", 2, |args, mode| {
+        npv(&rate(&args[0], mode)?, vector(&args[1])?, mode)
+    }),
+    function("irr", 1, |args, mode| irr(vector(&args[0])?, mode)),
+];
+
+fn one(mode: NumericMode) -> Value {
+    Value::Number(mode.from_f64(1.0))
+}
+
+// Money and periods are plain numbers; integer mode would truncate the
+// divisions below, so there they become floats.
+fn amount(value: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    value.plain_number()?;
+    Ok(divisible(value, mode))
+}
+
+fn rate(value: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    let rate = amount(value, mode)?;
+    if rate.plain_number()?.to_f64() <= -1.0 {
+        return Err(ValueError::Domain("the rate must be above -100%"));
+    }
+    Ok(rate)
+}
+
+/// What one unit grows to over `n` periods at `rate`: `(1 + rate)^n`.
+fn growth(rate: &Value, n: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    one(mode).add(rate)?.pow(n)
+}
+
+/// The payment each period that pays off `pv` over `n` periods.
+fn pmt(rate: &Value, n: &Value, pv: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    if rate.is_zero() {
+        return pv.div(n)?.neg();
+    }
+    let growth = growth(rate, n, mode)?;
+    let interest = pv.mul(rate)?.mul(&growth)?;
+    interest.div(&growth.sub(&one(mode))?)?.neg()
+}
+
+/// What `n` payments of `pmt` add up to, with interest, by the last one.
+fn fv(rate: &Value, n: &Value, pmt: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    if rate.is_zero() {
+        return pmt.mul(n)?.neg();
+    }
+    let growth = growth(rate, n, mode)?;
+    pmt.mul(&growth.sub(&one(mode))?)?.div(rate)?.neg()
+}
+
+/// The present value of cash flows one period apart, the first of them
+/// now. Spreadsheet `NPV` discounts the first flow too; here it is added
+/// as it is, which is what `irr` needs.
+fn npv(rate: &Value, flows: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    let factor = one(mode).add(rate)?;
+    let mut discount = one(mode);
+    let mut total = Value::Number(mode.from_f64(0.0));
+    for flow in flows {
+        total = total.add(&amount(flow, mode)?.div(&discount)?)?;
+        discount = discount.mul(&factor)?;
+    }
+    Ok(total)
+}
+
+/// The rate at which the cash flows' net present value is zero, found
+/// with the root finder from a guess of 10%.
+fn irr(flows: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    let flows = flows
+        .iter()
+        .map(|flow| Ok(flow.plain_number()?.to_f64()))
+        .collect::<Result<Vec<f64>, ValueError>>()?;
+    if !(flows.iter().any(|&flow| flow > 0.0) && flows.iter().any(|&flow| flow < 0.0)) {
+        return Err(ValueError::Domain(
+            "the cash flows must include both payments and receipts",
+        ));
+    }
+    let discounted = |rate: f64, flow: &dyn Fn(usize, f64) -> f64| {
+        (rate > -1.0).then(|| flows.iter().enumerate().map(|(i, &x)| flow(i, x)).sum())
+    };
+    let mut f = |rate: f64| discounted(rate, &|i, x| x * (1.0 + rate).powi(-(i as i32)));
+    let mut slope = |rate: f64| {
+        discounted(rate, &|i, x| {
+            -(i as f64) * x * (1.0 + rate).powi(-(i as i32) - 1)
+        })
+    };
+    let root = find_root(&mut f, Some(&mut slope), 0.1, &SolverSettings::default())
+        .map_err(|_| ValueError::Domain("no rate makes the net present value zero"))?;
+    Ok(Value::Number(mode.from_f64(root)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::eval_expression;
+    use crate::memory::Memory;
+    use crate::token::tokenize;
+
+    fn eval(input: &str, mode: NumericMode) -> Result<Value, crate::error::CalcError> {
+        eval_expression(&tokenize(input).unwrap(), &Memory::with_mode(mode))
+    }
+
+    fn number(input: &str) -> f64 {
+        let value = eval(input, NumericMode::Float).unwrap();
+        value.plain_number().unwrap().to_f64()
+    }
+
+    fn assert_close(input: &str, expected: f64) {
+        let value = number(input);
+        assert!((value - expected).abs() < 1e-6, "{} gave {}", input, value);
+    }
+
+    // Reference values from spreadsheet PMT, FV, NPV and IRR.
+    #[test]
+    fn test_reference_values() {
+        assert_close("pmt(5% / 12, 360, 200000)", -1_073.643_246_024_279_5);
+        assert_close("pmt(1%, 12, 1000)", -88.848_788_678_341_68);
+        assert_close("pmt(0, 12, 1200)", -100.0);
+        assert_close("fv(6% / 12, 10, -200)", 2_045.605_281_631_584);
+        assert_close("fv(0, 10, -200)", 2000.0);
+        assert_close("compound(1000, 5%, 10)", 1_628.894_626_777_442);
+        assert_close("npv(10%, [-1000, 300, 400, 500])", -21.036_814_425_244_3);
+        let irr = number("irr([-70000, 12000, 15000, 18000, 21000, 26000])");
+        assert!((irr - 0.086_630_948_036_531_6).abs() < 1e-10);
+        let irr = number("irr([-70000, 12000, 15000, 18000, 21000])");
+        assert!((irr + 0.021_244_848_273_410_947).abs() < 1e-10);
+        assert_close("npv(irr([-100, 60, 60]), [-100, 60, 60])", 0.0);
+    }
+
+    #[test]
+    fn test_exact_modes() {
+        let value = eval("compound(1000, 5%, 2)", NumericMode::Rational).unwrap();
+        assert_eq!(value.to_string(), "1102.5");
+        let value = eval("pmt(0, 4, 10)", NumericMode::Integer).unwrap();
+        assert_eq!(value.to_string(), "-2.5");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &str| eval(input, NumericMode::Float).unwrap_err().to_string();
+        assert_eq!(
+            error("irr([100, 50])"),
+            "Domain error in 'irr': the cash flows must include both payments and receipts"
+        );
+        assert_eq!(
+            error("pmt(-100%, 12, 1000)"),
+            "Domain error in 'pmt': the rate must be above -100%"
+        );
+        assert_eq!(
+            error("npv(5%, 100)"),
+            "Type mismatch in 'npv': expected a list but found a number"
+        );
+        assert_eq!(
+            error("compound(1000 m, 5%, 2)"),
+            "Incompatible dimensions: length and dimensionless"
+        );
+    }
+}
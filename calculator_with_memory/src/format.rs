@@ -0,0 +1,587 @@
+//! How results are written for reading: a notation for the digits, the
+//! radix of whole numbers and the separators around them. `Display` on
+//! values stays the exact form that memory files and the parser read back;
+//! a [`Format`] only changes what is printed.
+
+use crate::number::Number;
+use crate::value::Value;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
+use std::fmt;
+use std::str::FromStr;
+
+const DEFAULT_FIXED_DIGITS: usize = 2;
+const DEFAULT_SIGNIFICANT_DIGITS: usize = 6;
+
+// Auto notation writes numbers whose leading digit stands for a power of
+// ten in this range out in full.
+const AUTO_PLAIN_EXPONENTS: std::ops::Range<i32> = -6..21;
+const AUTO_DIGITS: usize = 17;
+
+/// The most digits a notation may ask for.
+pub const MAX_DIGITS: usize = 1000;
+
+/// How many digits a number is written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// As the number writes itself, shortest floats and exact fractions,
+    /// until it reaches 1e21 or falls below 1e-6; from there on with a
+    /// power of ten and up to 17 significant digits.
+    #[default]
+    Auto,
+    /// Rounded to this many digits after the point, zeros kept.
+    Fixed(usize),
+    /// Rounded to this many significant digits, with a power of ten once
+    /// the number is too large or small to write them out plainly.
+    Significant(usize),
+    /// This many significant digits and a power of ten: `1.23e5`.
+    Scientific(usize),
+    /// Like scientific, with the power a multiple of three: `123e3`.
+    Engineering(usize),
+}
+
+impl FromStr for Notation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, count) = match s.split_once(':') {
+            Some((name, count)) => (name, Some(count)),
+            None => (s, None),
+        };
+        let digits = |default: usize, least: usize| match count {
+            None => Ok(default),
+            Some(digits) => digits
+                .parse()
+                .ok()
+                .filter(|digits| (least..=MAX_DIGITS).contains(digits))
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid digit count: {} (expected {} to {})",
+                        digits, least, MAX_DIGITS
+                    )
+                }),
+        };
+        match name {
+            "auto" if count.is_none() => Ok(Notation::Auto),
+            "fixed" => digits(DEFAULT_FIXED_DIGITS, 0).map(Notation::Fixed),
+            "sig" => digits(DEFAULT_SIGNIFICANT_DIGITS, 1).map(Notation::Significant),
+            "sci" => digits(DEFAULT_SIGNIFICANT_DIGITS, 1).map(Notation::Scientific),
+            "eng" => digits(DEFAULT_SIGNIFICANT_DIGITS, 1).map(Notation::Engineering),
+            _ => Err(format!(
+                "Unknown notation '{}' (expected auto, fixed:N, sig:N, sci:N or eng:N)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Notation::Auto => write!(f, "auto"),
+            Notation::Fixed(digits) => write!(f, "fixed:{}", digits),
+            Notation::Significant(digits) => write!(f, "sig:{}", digits),
+            Notation::Scientific(digits) => write!(f, "sci:{}", digits),
+            Notation::Engineering(digits) => write!(f, "eng:{}", digits),
+        }
+    }
+}
+
+/// Everything that decides how a result is printed.
+///
+/// ```
+/// use calculator_with_memory::format::{Format, Notation};
+/// use calculator_with_memory::number::Number;
+///
+/// let format = Format {
+///     notation: Notation::Fixed(2),
+///     grouping: Some('.'),
+///     decimal_point: ',',
+///     ..Format::default()
+/// };
+/// assert_eq!(format.number(&Number::Float(1234567.891)), "1.234.567,89");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub notation: Notation,
+    /// The radix whole numbers are written in: 2, 8, 10 or 16. Other radixes
+    /// ignore the notation and the separators.
+    pub base: u32,
+    /// Put between groups of three digits before the point.
+    pub grouping: Option<char>,
+    /// Written in place of the decimal point: `.` or `,`.
+    pub decimal_point: char,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self {
+            notation: Notation::Auto,
+            base: 10,
+            grouping: None,
+            decimal_point: '.',
+        }
+    }
+}
+
+impl Format {
+    /// Rejects a group separator that would read as the decimal point.
+    pub fn check(&self) -> Result<(), String> {
+        if self.grouping == Some(self.decimal_point) {
+            return Err(format!(
+                "The group separator and the decimal point cannot both be '{}'",
+                self.decimal_point
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn number(&self, value: &Number) -> String {
+        if self.base != 10 && value.to_i64().is_some() {
+            return value.to_string_radix(self.base);
+        }
+        let text = match (self.notation, value) {
+            (_, Number::Float(x)) if !x.is_finite() => value.to_string(),
+            (Notation::Auto, _) => auto(value),
+            (Notation::Fixed(digits), _) => fixed(&value.to_rational(), digits),
+            (Notation::Significant(digits), _) => {
+                let (mantissa, exponent) = scientific(&value.to_rational(), digits);
+                if exponent < -4 || exponent >= digits as i32 {
+                    with_exponent(&mantissa, 1, exponent)
+                } else {
+                    positional(&mantissa, exponent)
+                }
+            }
+            (Notation::Scientific(digits), _) => {
+                let (mantissa, exponent) = scientific(&value.to_rational(), digits);
+                with_exponent(&mantissa, 1, exponent)
+            }
+            (Notation::Engineering(digits), _) => {
+                let (mantissa, exponent) = scientific(&value.to_rational(), digits);
+                let power = exponent.div_euclid(3) * 3;
+                with_exponent(&mantissa, (exponent - power + 1) as usize, power)
+            }
+        };
+        self.separate(&text)
+    }
+
+    /// Writes numbers inside the value with [`Format::number`]. With a
+    /// decimal comma, list items are separated by semicolons instead.
+    pub fn value(&self, value: &Value) -> String {
+        match value {
+            Value::Number(number) => self.number(number),
+            Value::Quantity { value, unit } => {
+                let text = self.number(value);
+                if text.contains('/') {
+                    format!("({}) {}", text, unit)
+                } else {
+                    format!("{} {}", text, unit)
+                }
+            }
+            Value::List(items) => {
+                let separator = if self.decimal_point == ',' {
+                    "; "
+                } else {
+                    ", "
+                };
+                let items: Vec<String> = items.iter().map(|item| self.value(item)).collect();
+                format!("[{}]", items.join(separator))
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    // Groups the whole part of every number in `text`, leaving fractions
+    // and exponents alone, and swaps in the decimal point.
+    fn separate(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if !chars[i].is_ascii_digit() {
+                out.push(if chars[i] == '.' {
+                    self.decimal_point
+                } else {
+                    chars[i]
+                });
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let before = |back: usize| start.checked_sub(back).map(|at| chars[at]);
+            let whole = before(1) != Some('.')
+                && before(1) != Some('e')
+                && !(before(1) == Some('-') && before(2) == Some('e'));
+            match self.grouping.filter(|_| whole) {
+                Some(separator) => {
+                    for (n, digit) in chars[start..i].iter().enumerate() {
+                        if n > 0 && (i - start - n) % 3 == 0 {
+                            out.push(separator);
+                        }
+                        out.push(*digit);
+                    }
+                }
+                None => out.extend(&chars[start..i]),
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} notation, base {}, ", self.notation, self.base)?;
+        match self.grouping {
+            Some(' ') => write!(f, "digits grouped by spaces, ")?,
+            Some(separator) => write!(f, "digits grouped by '{}', ", separator)?,
+            None => write!(f, "no digit grouping, ")?,
+        }
+        write!(f, "decimal point '{}'", self.decimal_point)
+    }
+}
+
+/// Reads a radix for whole numbers, as `:base` and `--base` take it.
+pub fn parse_base(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(radix @ (2 | 8 | 10 | 16)) => Ok(radix),
+        _ => Err(format!(
+            "Unsupported base '{}' (expected 2, 8, 10 or 16)",
+            s
+        )),
+    }
+}
+
+/// Reads a group separator; a space is spelled `space` so that it
+/// survives argument trimming.
+pub fn parse_separator(s: &str) -> Result<char, String> {
+    match s {
+        "," | "." | "_" | "'" => Ok(s.chars().next().unwrap_or_default()),
+        "space" => Ok(' '),
+        _ => Err(format!(
+            "Unsupported group separator '{}' (expected ',', '.', '_', ''' or space)",
+            s
+        )),
+    }
+}
+
+pub fn parse_decimal_point(s: &str) -> Result<char, String> {
+    match s {
+        "." => Ok('.'),
+        "," => Ok(','),
+        _ => Err(format!(
+            "Unsupported decimal point '{}' (expected '.' or ',')",
+            s
+        )),
+    }
+}
+
+/// `value` rounded to `digits` places, halves away from zero.
+fn fixed(value: &BigRational, digits: usize) -> String {
+    let scale = BigInt::from(10).pow(digits as u32);
+    let units = (value * BigRational::from_integer(scale))
+        .round()
+        .to_integer();
+    let padded = format!("{:0>width$}", units.abs(), width = digits + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - digits);
+    let sign = if units.is_negative() { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+// The sign and `digits` significant digits of `value`, rounded half away
+// from zero, and the power of ten of the first one: 1234.5 to three digits
+// is ("123", 3). Working on the exact value keeps numbers beyond the range
+// of a float intact.
+fn scientific(value: &BigRational, digits: usize) -> (String, i32) {
+    if value.is_zero() {
+        return ("0".repeat(digits), 0);
+    }
+    let mut exponent = leading_exponent(value);
+    let scaled = value.abs() * power_of_ten(digits as i32 - 1 - exponent);
+    let mut units = scaled.round().to_integer().to_string();
+    // Rounding 9.99 up gives 10.0: one digit too many, a power higher.
+    if units.len() > digits {
+        units.truncate(digits);
+        exponent += 1;
+    }
+    let sign = if value.is_negative() { "-" } else { "" };
+    (format!("{}{}", sign, units), exponent)
+}
+
+// The power of ten of the first digit of a nonzero value.
+fn leading_exponent(value: &BigRational) -> i32 {
+    let magnitude = value.abs();
+    let length = |n: &BigInt| n.to_string().len() as i32;
+    let exponent = length(magnitude.numer()) - length(magnitude.denom());
+    if magnitude < power_of_ten(exponent) {
+        exponent - 1
+    } else {
+        exponent
+    }
+}
+
+fn auto(value: &Number) -> String {
+    // A float's exponent is that of its shortest form: 1e-6 is stored a
+    // little below a millionth.
+    if let Number::Float(x) = value {
+        let text = format!("{:e}", x);
+        let (_, exponent) = text.split_once('e').unwrap_or_default();
+        return match exponent.parse() {
+            Ok(exponent) if !AUTO_PLAIN_EXPONENTS.contains(&exponent) && *x != 0.0 => text,
+            _ => value.to_string(),
+        };
+    }
+    let exact = value.to_rational();
+    if exact.is_zero() || AUTO_PLAIN_EXPONENTS.contains(&leading_exponent(&exact)) {
+        return value.to_string();
+    }
+    let (mantissa, exponent) = scientific(&exact, AUTO_DIGITS);
+    let digits = mantissa.trim_end_matches('0');
+    with_exponent(digits, 1, exponent)
+}
+
+fn power_of_ten(exponent: i32) -> BigRational {
+    let power = BigRational::from_integer(BigInt::from(10).pow(exponent.unsigned_abs()));
+    if exponent < 0 {
+        power.recip()
+    } else {
+        power
+    }
+}
+
+// Puts the point after `leading` digits of the mantissa and appends the
+// power of ten.
+fn with_exponent(mantissa: &str, leading: usize, exponent: i32) -> String {
+    let (sign, digits) = split_sign(mantissa);
+    let digits = format!("{:0<width$}", digits, width = leading);
+    let (whole, fraction) = digits.split_at(leading);
+    if fraction.is_empty() {
+        format!("{}{}e{}", sign, whole, exponent)
+    } else {
+        format!("{}{}.{}e{}", sign, whole, fraction, exponent)
+    }
+}
+
+// Writes the mantissa out in full, the first digit standing for
+// `10^exponent`; the caller keeps the exponent below the digit count.
+fn positional(mantissa: &str, exponent: i32) -> String {
+    let (sign, digits) = split_sign(mantissa);
+    if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        return format!("{}0.{}{}", sign, zeros, digits);
+    }
+    let (whole, fraction) = digits.split_at(exponent as usize + 1);
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+fn split_sign(mantissa: &str) -> (&str, &str) {
+    match mantissa.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", mantissa),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::parse_literal;
+    use crate::units::Unit;
+
+    fn with(notation: &str) -> Format {
+        Format {
+            notation: notation.parse().unwrap(),
+            ..Format::default()
+        }
+    }
+
+    fn float(format: &Format, value: f64) -> String {
+        format.number(&Number::Float(value))
+    }
+
+    #[test]
+    fn test_notation_from_str() {
+        assert_eq!("auto".parse(), Ok(Notation::Auto));
+        assert_eq!("fixed".parse(), Ok(Notation::Fixed(2)));
+        assert_eq!("fixed:0".parse(), Ok(Notation::Fixed(0)));
+        assert_eq!("sig:4".parse(), Ok(Notation::Significant(4)));
+        assert_eq!("sci".parse(), Ok(Notation::Scientific(6)));
+        assert_eq!("eng:3".parse(), Ok(Notation::Engineering(3)));
+        assert!("sig:0".parse::<Notation>().is_err());
+        assert_eq!("fixed:1000".parse(), Ok(Notation::Fixed(MAX_DIGITS)));
+        assert!("fixed:1001".parse::<Notation>().is_err());
+        assert!("sci:70000".parse::<Notation>().is_err());
+        assert!("fixed:x".parse::<Notation>().is_err());
+        assert!("auto:2".parse::<Notation>().is_err());
+        assert!("round".parse::<Notation>().is_err());
+        for notation in ["auto", "fixed:3", "sig:2", "sci:5", "eng:1"] {
+            assert_eq!(notation.parse::<Notation>().unwrap().to_string(), notation);
+        }
+    }
+
+    #[test]
+    fn test_fixed() {
+        let format = with("fixed:2");
+        assert_eq!(float(&format, 2.5), "2.50");
+        assert_eq!(float(&format, -1.005), "-1.00");
+        assert_eq!(float(&format, -0.001), "0.00");
+        assert_eq!(float(&format, 1e20), "100000000000000000000.00");
+        let third = Number::Rational(parse_literal("1").unwrap() / parse_literal("3").unwrap());
+        assert_eq!(format.number(&third), "0.33");
+        assert_eq!(with("fixed:0").number(&Number::Integer(7)), "7");
+        assert_eq!(float(&with("fixed:0"), 2.5), "3");
+        assert_eq!(float(&with("fixed:1000"), 0.5).len(), 1002);
+    }
+
+    #[test]
+    fn test_significant() {
+        let format = with("sig:3");
+        assert_eq!(float(&format, 1234.5), "1.23e3");
+        assert_eq!(float(&format, 123.45), "123");
+        assert_eq!(float(&format, 2.0), "2.00");
+        assert_eq!(float(&format, 9.999), "10.0");
+        assert_eq!(float(&format, 0.000_123_45), "0.000123");
+        assert_eq!(float(&format, 0.000_012_345), "1.23e-5");
+        assert_eq!(float(&format, -0.5), "-0.500");
+        assert_eq!(float(&format, 0.0), "0.00");
+    }
+
+    #[test]
+    fn test_scientific_and_engineering() {
+        assert_eq!(float(&with("sci:3"), 123_456.0), "1.23e5");
+        assert_eq!(float(&with("sci:1"), -0.004), "-4e-3");
+        let format = with("eng:3");
+        assert_eq!(float(&format, 123_456.0), "123e3");
+        assert_eq!(float(&format, 12_345.0), "12.3e3");
+        assert_eq!(float(&format, 0.001_5), "1.50e-3");
+        assert_eq!(float(&format, 0.000_15), "150e-6");
+        assert_eq!(float(&with("eng:1"), 50_000.0), "50e3");
+        assert_eq!(float(&format, f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn test_auto() {
+        let auto = Format::default();
+        assert_eq!(float(&auto, 1e300), "1e300");
+        assert_eq!(float(&auto, -1.5e-20), "-1.5e-20");
+        assert_eq!(float(&auto, 1e21), "1e21");
+        assert_eq!(float(&auto, 123_456_789.25), "123456789.25");
+        assert_eq!(float(&auto, 0.000_001), "0.000001");
+        assert_eq!(float(&auto, 1e-7), "1e-7");
+        assert_eq!(float(&auto, 0.0), "0");
+        assert_eq!(
+            auto.number(&Number::Integer(i64::MAX)),
+            "9223372036854775807"
+        );
+        let exact = |text: &str, exponent: i32| {
+            Number::Rational(parse_literal(text).unwrap() * power_of_ten(exponent))
+        };
+        assert_eq!(auto.number(&exact("1", 400)), "1e400");
+        assert_eq!(auto.number(&exact("2.5", -30)), "2.5e-30");
+        let third = parse_literal("1").unwrap() / parse_literal("3").unwrap();
+        assert_eq!(auto.number(&Number::Rational(third.clone())), "1/3");
+        let huge_third = Number::Rational(third * power_of_ten(400));
+        assert_eq!(auto.number(&huge_third), "3.3333333333333333e399");
+        let grouped = Format {
+            grouping: Some(','),
+            ..auto
+        };
+        assert_eq!(float(&grouped, 1.234_5e25), "1.2345e25");
+    }
+
+    #[test]
+    fn test_beyond_float_range() {
+        let exact = |mantissa: &str, exponent: i32| {
+            Number::Rational(parse_literal(mantissa).unwrap() * power_of_ten(exponent))
+        };
+        let huge = exact("1.5", 400);
+        let tiny = Number::Rational(-exact("2", -400).to_rational());
+        assert_eq!(with("sci:3").number(&huge), "1.50e400");
+        assert_eq!(with("sig:2").number(&huge), "1.5e400");
+        assert_eq!(with("eng:2").number(&huge), "15e399");
+        assert_eq!(with("sci:3").number(&tiny), "-2.00e-400");
+        assert_eq!(with("sig:1").number(&tiny), "-2e-400");
+        assert_eq!(with("eng:1").number(&tiny), "-200e-402");
+        assert_eq!(with("sci:2").number(&exact("9.99", 400)), "1.0e401");
+    }
+
+    #[test]
+    fn test_separators() {
+        let format = Format {
+            grouping: Some(','),
+            ..Format::default()
+        };
+        assert_eq!(float(&format, 1_234_567.891), "1,234,567.891");
+        assert_eq!(float(&format, -123.0), "-123");
+        assert_eq!(
+            float(&format, 123_456_789_012_345_680_000.0),
+            "123,456,789,012,345,680,000"
+        );
+        let huge =
+            Number::Rational(parse_literal("1234567").unwrap() / parse_literal("9").unwrap());
+        assert_eq!(format.number(&huge), "1,234,567/9");
+        let format = Format {
+            notation: Notation::Scientific(4),
+            grouping: Some(' '),
+            decimal_point: ',',
+            ..Format::default()
+        };
+        assert_eq!(float(&format, 12_345_678.0), "1,235e7");
+        assert_eq!(float(&format, 1e-12), "1,000e-12");
+        let format = Format {
+            grouping: Some('.'),
+            decimal_point: ',',
+            ..format
+        };
+        assert!(format.check().is_ok());
+        assert!(Format {
+            grouping: Some(','),
+            ..format
+        }
+        .check()
+        .is_err());
+    }
+
+    #[test]
+    fn test_base_and_values() {
+        let format = Format {
+            notation: Notation::Fixed(1),
+            base: 16,
+            grouping: Some('.'),
+            decimal_point: ',',
+        };
+        assert_eq!(format.number(&Number::Integer(65_535)), "0xffff");
+        assert_eq!(float(&format, 0.25), "0,3");
+        let list = Value::List(vec![
+            Value::Number(Number::Float(1.5)),
+            Value::Number(Number::Integer(255)),
+            Value::quantity(Number::Float(1500.25), Unit::lookup("m").unwrap()),
+            Value::Text("a.b".to_string()),
+        ]);
+        assert_eq!(format.value(&list), "[1,5; 0xff; 1.500,3 m; \"a.b\"]");
+        let third = Number::Rational(parse_literal("1").unwrap() / parse_literal("3").unwrap());
+        let quantity = Value::quantity(third, Unit::lookup("km").unwrap());
+        assert_eq!(Format::default().value(&quantity), "(1/3) km");
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!(parse_base("16"), Ok(16));
+        assert!(parse_base("7").is_err());
+        assert_eq!(parse_separator("space"), Ok(' '));
+        assert_eq!(parse_separator("'"), Ok('\''));
+        assert!(parse_separator(";").is_err());
+        assert_eq!(parse_decimal_point(","), Ok(','));
+        assert!(parse_decimal_point("_").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::number::{Number, NumberError};
+use std::cmp::Ordering;
+use std::f64::consts;
+use std::fmt;
+
+pub const CONSTANTS: &[(&str, f64)] = &[("pi", consts::PI), ("e", consts::E)];
+
+pub fn constant(name: &str) -> Option<f64> {
+    CONSTANTS
+        .iter()
+        .find(|(constant, _)| *constant == name)
+        .map(|(_, value)| *value)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    func: fn(&[Number]) -> Result<Number, &'static str>,
+}
+
+impl Builtin {
+    /// Applies the function to arguments whose count has already been
+    /// checked against `arity`. Domain violations come back as a message.
+    pub fn call(&self, args: &[Number]) -> Result<Number, &'static str> {
+        (self.func)(args)
+    }
+}
+
+const fn unary(name: &'static str, func: fn(&[Number]) -> Result<Number, &'static str>) -> Builtin {
+    Builtin {
+        name,
+        arity: Arity::Exact(1),
+        func,
+    }
+}
+
+fn positive(x: &Number) -> Result<&Number, &'static str> {
+    if x.is_negative() || x.is_zero() {
+        Err("argument must be positive")
+    } else {
+        Ok(x)
+    }
+}
+
+fn extreme(args: &[Number], keep: Ordering) -> Result<Number, &'static str> {
+    let mut best = &args[0];
+    for arg in &args[1..] {
+        if arg.partial_cmp(best) == Some(keep) {
+            best = arg;
+        }
+    }
+    Ok(best.clone())
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    unary("sqrt", |args| {
+        if args[0].is_negative() {
+            Err("argument must not be negative")
+        } else {
+            args[0].map_f64(f64::sqrt)
+        }
+    }),
+    unary("abs", |args| args[0].abs().map_err(NumberError::message)),
+    unary("ln", |args| positive(&args[0])?.map_f64(f64::ln)),
+    unary("log10", |args| positive(&args[0])?.map_f64(f64::log10)),
+    unary("exp", |args| args[0].map_f64(f64::exp)),
+    unary("sin", |args| args[0].map_f64(f64::sin)),
+    unary("cos", |args| args[0].map_f64(f64::cos)),
+    unary("tan", |args| args[0].map_f64(f64::tan)),
+    unary("round", |args| Ok(args[0].round())),
+    unary("floor", |args| Ok(args[0].floor())),
+    unary("ceil", |args| Ok(args[0].ceil())),
+    Builtin {
+        name: "min",
+        arity: Arity::AtLeast(1),
+        func: |args| extreme(args, Ordering::Less),
+    },
+    Builtin {
+        name: "max",
+        arity: Arity::AtLeast(1),
+        func: |args| extreme(args, Ordering::Greater),
+    },
+];
+
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[f64]) -> Result<f64, &'static str> {
+        let args: Vec<Number> = args.iter().copied().map(Number::from).collect();
+        builtin(name)
+            .unwrap()
+            .call(&args)
+            .map(|value| value.to_f64())
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert_eq!(call("sqrt", &[9.0]), Ok(3.0));
+        assert_eq!(call("abs", &[-2.5]), Ok(2.5));
+        assert_eq!(call("log10", &[1000.0]), Ok(3.0));
+        assert_eq!(call("min", &[3.0, -1.0, 2.0]), Ok(-1.0));
+        assert_eq!(call("max", &[3.0]), Ok(3.0));
+        assert_eq!(call("round", &[2.5]), Ok(3.0));
+        assert!(builtin("nope").is_none());
+    }
+
+    #[test]
+    fn test_domain_errors() {
+        assert!(call("sqrt", &[-1.0]).is_err());
+        assert!(call("ln", &[0.0]).is_err());
+        assert!(call("log10", &[-10.0]).is_err());
+    }
+
+    #[test]
+    fn test_arity() {
+        assert!(builtin("sqrt").unwrap().arity.accepts(1));
+        assert!(!builtin("sqrt").unwrap().arity.accepts(2));
+        assert!(!builtin("max").unwrap().arity.accepts(0));
+        assert!(builtin("max").unwrap().arity.accepts(5));
+        assert_eq!(constant("pi"), Some(consts::PI));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::value::Value;
+
+/// The name that refers to the most recent result inside an expression.
+pub const ANS: &str = "ans";
+
+/// Every result produced so far, numbered from 1 so that `$1` is the first
+/// and `ans` the latest.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    results: Vec<Value>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a result and returns the number it can be referred to by.
+    pub fn push(&mut self, value: Value) -> usize {
+        self.results.push(value);
+        self.results.len()
+    }
+
+    pub fn last(&self) -> Option<&Value> {
+        self.results.last()
+    }
+
+    pub fn get(&self, number: usize) -> Option<&Value> {
+        number
+            .checked_sub(1)
+            .and_then(|index| self.results.get(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Value)> {
+        self.results
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (index + 1, value))
+    }
+
+    pub fn clear(&mut self) {
+        self.results.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbering() {
+        let mut history = History::new();
+        assert!(history.last().is_none());
+        assert_eq!(history.push(Value::from(6.0)), 1);
+        assert_eq!(history.push(Value::from(7.0)), 2);
+        assert_eq!(history.get(1), Some(&Value::from(6.0)));
+        assert_eq!(history.last(), Some(&Value::from(7.0)));
+        assert!(history.get(0).is_none());
+        assert!(history.get(3).is_none());
+        assert_eq!(
+            history.iter().map(|(number, _)| number).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+}
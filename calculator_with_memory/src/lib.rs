@@ -1,3 +1,37 @@
+//! A calculator with memory slots, user functions, units and result
+//! history. [`Calculator`] is the entry point for embedding it:
+//!
+//! ```
+//! use calculator_with_memory::Calculator;
+//!
+//! let mut calc = Calculator::new();
+//! calc.eval("let speed = 90 km / 1 h").unwrap();
+//! let time = calc.eval("450 km / speed to min").unwrap();
+//! assert_eq!(time.to_string(), "300 min");
+//! ```
+
+pub mod calculator;
+pub mod command;
+pub mod completion;
+pub mod error;
 pub mod expression;
+pub mod finance;
+pub mod format;
+pub mod functions;
+pub mod history;
+pub mod matrix;
 pub mod memory;
+pub mod number;
+pub mod parser;
+pub mod pretty;
+pub mod quadrature;
+pub mod solver;
+pub mod statistics;
+pub mod symbolic;
 pub mod token;
+pub mod units;
+pub mod value;
+
+pub use calculator::{Calculator, Config, Outcome};
+pub use error::CalcError;
+pub use value::Value;
@@ -1,53 +1,412 @@
-use calculator_with_memory::expression::eval_expression;
+use calculator_with_memory::command::{Command, HELP};
+use calculator_with_memory::completion::complete;
+use calculator_with_memory::format::{self, Format, Notation};
 use calculator_with_memory::memory::Memory;
-use calculator_with_memory::token::Token;
-use std::io::{self, BufRead};
+use calculator_with_memory::number::NumericMode;
+use calculator_with_memory::solver::SolverSettings;
+use calculator_with_memory::{Calculator, Config, Outcome};
+use clap::Parser;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
-    let mut memory = Memory::new();
-    let mut prev_result = 0.0;
+#[derive(Parser)]
+#[clap(version)]
+struct App {
+    /// Number representation: float, rational, decimal, decimal:<digits> or integer
+    #[clap(long, default_value = "float")]
+    mode: NumericMode,
+    /// Restore memory from this file on start and write it back after every change
+    #[clap(long)]
+    memory_file: Option<PathBuf>,
+    /// Where interactive line history is kept [default: ~/.calculator_with_memory_history]
+    #[clap(long)]
+    history_file: Option<PathBuf>,
+    /// Run the lines of this script instead of reading from the terminal
+    #[clap(long)]
+    file: Option<PathBuf>,
+    /// Stop a script at its first failing line
+    #[clap(long)]
+    fail_fast: bool,
+    /// How results are written: auto, fixed:<digits>, sig:<digits>, sci:<digits> or eng:<digits>
+    #[clap(long, default_value = "auto")]
+    format: Notation,
+    /// Radix for whole-number results: 2, 8, 10 or 16
+    #[clap(long, default_value = "10", value_parser = format::parse_base)]
+    base: u32,
+    /// Group digits in threes with this separator: ",", ".", "_", "'" or "space"
+    #[clap(long, value_parser = format::parse_separator)]
+    group: Option<char>,
+    /// Decimal point for results: "." or ","
+    #[clap(long, default_value = ".", value_parser = format::parse_decimal_point)]
+    decimal_point: char,
+}
 
-    for line in io::stdin().lock().lines() {
-        let line = line.unwrap();
-        if line.is_empty() {
-            break;
-        }
+struct CalcHelper {
+    names: Vec<String>,
+}
 
-        let tokens: Vec<Token> = match line
-            .split_whitespace()
-            .map(|word| Token::parse(word, &memory.slots))
-            .collect::<Result<Vec<_>, _>>()
-        {
-            Ok(toks) => toks,
-            Err(e) => {
-                eprintln!("Error parsing tokens: {}", e);
-                break;
-            }
+impl Completer for CalcHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, &self.names))
+    }
+}
+
+impl Hinter for CalcHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CalcHelper {}
+
+impl Validator for CalcHelper {}
+
+impl Helper for CalcHelper {}
+
+// Line editing only makes sense on a terminal; script files and piped input
+// are read as plain lines so they see no prompts or escape sequences.
+enum Input {
+    Editor {
+        editor: Box<Editor<CalcHelper, DefaultHistory>>,
+        history: Option<PathBuf>,
+    },
+    Plain(io::Lines<Box<dyn BufRead>>),
+}
+
+impl Input {
+    fn script(path: &Path) -> io::Result<Self> {
+        let reader: Box<dyn BufRead> = Box::new(BufReader::new(File::open(path)?));
+        Ok(Input::Plain(reader.lines()))
+    }
+
+    fn open(history: Option<PathBuf>) -> Self {
+        let stdin = io::stdin();
+        let plain = || {
+            let reader: Box<dyn BufRead> = Box::new(io::stdin().lock());
+            Input::Plain(reader.lines())
         };
+        if !stdin.is_terminal() {
+            return plain();
+        }
+        match Editor::new() {
+            Ok(mut editor) => {
+                editor.set_helper(Some(CalcHelper { names: Vec::new() }));
+                if let Some(path) = &history {
+                    let _ = editor.load_history(path);
+                }
+                Input::Editor {
+                    editor: Box::new(editor),
+                    history,
+                }
+            }
+            Err(_) => plain(),
+        }
+    }
 
-        if tokens.len() == 1 {
-            match &tokens[0] {
-                Token::MemoryPlus(name) => {
-                    memory.update(name.clone(), prev_result);
-                    println!(" => {:?}", memory.get(name).unwrap());
+    fn read_line(&mut self, memory: &Memory) -> Option<String> {
+        match self {
+            Input::Editor { editor, .. } => {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.names = memory.names();
                 }
-                Token::MemoryMinus(name) => {
-                    memory.update(name.clone(), -prev_result);
-                    println!(" => {:?}", memory.get(name).unwrap());
+                loop {
+                    match editor.readline("> ") {
+                        Ok(line) => {
+                            if !line.trim().is_empty() {
+                                let _ = editor.add_history_entry(line.as_str());
+                            }
+                            return Some(line);
+                        }
+                        Err(rustyline::error::ReadlineError::Interrupted) => continue,
+                        Err(_) => return None,
+                    }
                 }
-                _ => unreachable!(),
             }
+            Input::Plain(lines) => lines.next()?.ok(),
+        }
+    }
+
+    fn is_interactive(&self) -> bool {
+        matches!(self, Input::Editor { .. })
+    }
+
+    fn close(&mut self) {
+        if let Input::Editor {
+            editor,
+            history: Some(path),
+        } = self
+        {
+            if let Err(e) = editor.save_history(path) {
+                eprintln!("Error saving history to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn run_command(command: Command, calc: &mut Calculator) -> Result<bool, String> {
+    match command {
+        Command::Save(path) => {
+            calc.memory().save(&path).map_err(|e| e.to_string())?;
+            println!("Saved memory to {}", path.display());
+            Ok(false)
+        }
+        Command::Load(path) => {
+            calc.memory_mut().load(&path).map_err(|e| e.to_string())?;
+            println!("Loaded memory from {}", path.display());
+            Ok(true)
+        }
+        Command::ListMemory => {
+            let memory = calc.memory();
+            if memory.is_empty() {
+                println!("Memory is empty.");
+            }
+            for (name, value) in memory.slots() {
+                println!("  {} = {}", name, calc.format(value));
+            }
+            for (name, function) in memory.functions() {
+                println!("  {}{}", name, function);
+            }
+            Ok(false)
+        }
+        Command::Clear(name) => {
+            if !calc.memory_mut().remove(&name) {
+                return Err(format!("Nothing named '{}' in memory", name));
+            }
+            println!("Cleared {}", name);
+            Ok(true)
+        }
+        Command::Reset => {
+            calc.memory_mut().reset();
+            println!("Memory reset.");
+            Ok(true)
+        }
+        Command::History => {
+            let history = calc.history();
+            if history.is_empty() {
+                println!("No results yet.");
+            }
+            for (number, value) in history.iter() {
+                println!("  ${} = {}", number, calc.format(value));
+            }
+            Ok(false)
+        }
+        Command::Base(radix) => {
+            calc.set_base(radix);
+            println!("Printing results in base {}", radix);
+            Ok(false)
+        }
+        Command::Format(None) => {
+            println!("{}", calc.config().format);
+            Ok(false)
+        }
+        Command::Format(Some(notation)) => {
+            set_format(calc, |format| format.notation = notation)?;
+            println!("Printing results in {} notation", notation);
+            Ok(false)
+        }
+        Command::Grouping(grouping) => {
+            set_format(calc, |format| format.grouping = grouping)?;
+            match grouping {
+                Some(' ') => println!("Grouping digits by spaces"),
+                Some(separator) => println!("Grouping digits by '{}'", separator),
+                None => println!("Not grouping digits"),
+            }
+            Ok(false)
+        }
+        Command::DecimalPoint(decimal_point) => {
+            set_format(calc, |format| format.decimal_point = decimal_point)?;
+            println!("Decimal point is '{}'", decimal_point);
+            Ok(false)
+        }
+        Command::Tolerance(tolerance) => {
+            let config = calc.config();
+            calc.set_solver(SolverSettings {
+                tolerance,
+                ..config.solver
+            });
+            println!("solve() tolerance is {}", tolerance);
+            Ok(false)
+        }
+        Command::Iterations(max_iterations) => {
+            let config = calc.config();
+            calc.set_solver(SolverSettings {
+                max_iterations,
+                ..config.solver
+            });
+            println!("solve() takes at most {} iterations", max_iterations);
+            Ok(false)
+        }
+        Command::Help => {
+            println!("{}", HELP);
+            Ok(false)
+        }
+        Command::Quit => Ok(false),
+    }
+}
+
+fn set_format(calc: &mut Calculator, change: impl FnOnce(&mut Format)) -> Result<(), String> {
+    let mut format = calc.config().format;
+    change(&mut format);
+    format.check()?;
+    calc.set_format(format);
+    Ok(())
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Session {
+    calc: Calculator,
+    memory_file: Option<PathBuf>,
+}
+
+impl Session {
+    fn persist(&self) {
+        if let Some(path) = &self.memory_file {
+            if let Err(e) = self.calc.memory().save(path) {
+                eprintln!("Error saving {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Runs one input line, printing its result. A failure comes back as
+    /// the message to report, already rendered against the line.
+    fn execute(&mut self, line: &str) -> Result<Flow, String> {
+        if Command::is_command(line) {
+            let changed = match Command::parse(line) {
+                Ok(Command::Quit) => return Ok(Flow::Quit),
+                Ok(command) => run_command(command, &mut self.calc),
+                Err(e) => Err(e),
+            }
+            .map_err(|e| format!("Error: {}", e))?;
+            if changed {
+                self.persist();
+            }
+            return Ok(Flow::Continue);
+        }
+
+        let outcome = self.calc.execute(line).map_err(|e| e.render(line))?;
+        let calc = &self.calc;
+        match &outcome {
+            Outcome::Value(value)
+            | Outcome::Assigned { value, .. }
+            | Outcome::Updated { value, .. } => println!(" => {}", calc.format(value)),
+            Outcome::Defined { name, function } => println!(" => {}{}", name, function),
+            Outcome::Cleared(name) => println!("Cleared {}", name),
+            Outcome::Swapped {
+                first: (a, a_value),
+                second: (b, b_value),
+            } => println!(
+                " => {} = {}, {} = {}",
+                a,
+                calc.format(a_value),
+                b,
+                calc.format(b_value)
+            ),
+        }
+        if outcome.changes_memory() {
+            self.persist();
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+fn main() -> ExitCode {
+    let app = App::parse();
+    let format = Format {
+        notation: app.format,
+        base: app.base,
+        grouping: app.group,
+        decimal_point: app.decimal_point,
+    };
+    if let Err(e) = format.check() {
+        eprintln!("Error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let mut session = Session {
+        calc: Calculator::with_config(Config {
+            mode: app.mode,
+            format,
+            ..Config::default()
+        }),
+        memory_file: app.memory_file.clone(),
+    };
+
+    if let Some(path) = app.memory_file.as_deref().filter(|path| path.exists()) {
+        // Saving over a file that was only partly read would lose whatever
+        // came after the bad line, so the session stops writing to it.
+        if let Err(e) = session.calc.memory_mut().load(path) {
+            eprintln!("Error loading {}: {}", path.display(), e);
+            eprintln!("Memory will not be saved to {}", path.display());
+            session.memory_file = None;
+        }
+    }
+
+    let mut input = match &app.file {
+        Some(path) => match Input::script(path) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Input::open(app.history_file.clone().or_else(|| {
+            env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".calculator_with_memory_history"))
+        })),
+    };
+    let interactive = input.is_interactive();
+    let source = app
+        .file
+        .as_ref()
+        .map_or_else(|| "<stdin>".to_string(), |path| path.display().to_string());
+
+    let mut failed = false;
+    let mut line_number = 0;
+    while let Some(line) = input.read_line(session.calc.memory()) {
+        line_number += 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
 
-        match eval_expression(&tokens, &memory) {
-            Ok(result) => {
-                println!(" => {}", result);
-                prev_result = result
+        match session.execute(&line) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(message) if interactive => eprintln!("{}", message),
+            Err(message) => {
+                eprintln!("{}:{}: {}", source, line_number, message);
+                failed = true;
+                if app.fail_fast {
+                    break;
+                }
             }
-            Err(e) => eprintln!("Error: {}", e),
         }
     }
 
-    println!("Program terminated.")
+    input.close();
+    if interactive {
+        println!("Program terminated.");
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }
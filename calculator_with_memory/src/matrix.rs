@@ -0,0 +1,392 @@
+//! Functions that take whole lists and matrices rather than numbers. The
+//! element-wise operators live on [`Value`] itself, the statistics in
+//! [`crate::statistics`] and the money functions in [`crate::finance`].
+
+use crate::finance::FINANCE;
+use crate::functions::Arity;
+use crate::number::{Number, NumericMode};
+use crate::statistics::STATISTICS;
+use crate::value::{Value, ValueError};
+
+pub struct ListFunction {
+    pub name: &'static str,
+    pub arity: Arity,
+    func: fn(&[Value], NumericMode) -> Result<Value, ValueError>,
+}
+
+impl ListFunction {
+    /// Applies the function to arguments whose count has already been
+    /// checked against `arity`; `mode` supplies the numbers it starts from.
+    pub fn call(&self, args: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+        (self.func)(args, mode)
+    }
+}
+
+pub(crate) const fn function(
+    name: &'static str,
+    arity: usize,
+    func: fn(&[Value], NumericMode) -> Result<Value, ValueError>,
+) -> ListFunction {
+    ListFunction {
+        name,
+        arity: Arity::Exact(arity),
+        func,
+    }
+}
+
+pub const LIST_FUNCTIONS: &[ListFunction] = &[
+    function("dot", 2, |args, mode| dot(&args[0], &args[1], mode)),
+    function("cross", 2, |args, _| cross(&args[0], &args[1])),
+    function("transpose", 1, |args, _| transpose(&args[0])),
+    function("det", 1, |args, mode| det(&args[0], mode)),
+    function("inverse", 1, |args, mode| inverse(&args[0], mode)),
+    function("sum", 1, |args, mode| sum(&elements(&args[0])?, mode)),
+];
+
+/// Looks `name` up among [`LIST_FUNCTIONS`], [`STATISTICS`] and
+/// [`FINANCE`].
+pub fn list_function(name: &str) -> Option<&'static ListFunction> {
+    LIST_FUNCTIONS
+        .iter()
+        .chain(STATISTICS)
+        .chain(FINANCE)
+        .find(|function| function.name == name)
+}
+
+fn zero(mode: NumericMode) -> Value {
+    Value::Number(mode.from_f64(0.0))
+}
+
+fn one(mode: NumericMode) -> Value {
+    Value::Number(mode.from_f64(1.0))
+}
+
+// Integer mode truncates division, which would make a mean or an inverse
+// meaningless, so there the elements become floats first.
+pub(crate) fn divisible(value: &Value, mode: NumericMode) -> Value {
+    match value {
+        Value::Number(Number::Integer(n)) if mode == NumericMode::Integer => {
+            Value::Number(Number::Float(*n as f64))
+        }
+        _ => value.clone(),
+    }
+}
+
+pub(crate) fn vector(value: &Value) -> Result<&[Value], ValueError> {
+    match value {
+        Value::List(items) if value.shape().0.len() == 1 => Ok(items),
+        _ => Err(ValueError::Mismatch("list", value.kind())),
+    }
+}
+
+fn rows(value: &Value) -> Result<Vec<Vec<Value>>, ValueError> {
+    match value {
+        Value::List(rows) if value.shape().0.len() == 2 => Ok(rows
+            .iter()
+            .map(|row| vector(row).map(<[Value]>::to_vec))
+            .collect::<Result<_, _>>()?),
+        _ => Err(ValueError::Mismatch("matrix", value.kind())),
+    }
+}
+
+fn square(value: &Value) -> Result<Vec<Vec<Value>>, ValueError> {
+    let rows = rows(value)?;
+    if rows.is_empty() || rows.iter().any(|row| row.len() != rows.len()) {
+        return Err(ValueError::Domain("the matrix must be square"));
+    }
+    Ok(rows)
+}
+
+fn matrix(rows: Vec<Vec<Value>>) -> Value {
+    Value::List(rows.into_iter().map(Value::List).collect())
+}
+
+/// Every number in a list or matrix, row by row.
+pub(crate) fn elements(value: &Value) -> Result<Vec<Value>, ValueError> {
+    let Value::List(_) = value else {
+        return Err(ValueError::Mismatch("list", value.kind()));
+    };
+    let mut elements = Vec::new();
+    value.map_elements(&mut |element| {
+        elements.push(element.clone());
+        Ok::<_, ValueError>(Value::Bool(true))
+    })?;
+    Ok(elements)
+}
+
+pub(crate) fn sum(values: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    let Some((first, rest)) = values.split_first() else {
+        return Ok(zero(mode));
+    };
+    rest.iter()
+        .try_fold(first.clone(), |total, value| total.add(value))
+}
+
+fn dot_vectors(a: &[Value], b: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    let products = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| a.mul(b))
+        .collect::<Result<Vec<_>, _>>()?;
+    sum(&products, mode)
+}
+
+fn columns(rows: &[Vec<Value>]) -> Vec<Vec<Value>> {
+    let width = rows.first().map_or(0, Vec::len);
+    (0..width)
+        .map(|column| rows.iter().map(|row| row[column].clone()).collect())
+        .collect()
+}
+
+/// The dot product of two lists, or the matrix product when either side
+/// is a matrix; a list next to a matrix acts as a row on the left and as a
+/// column on the right.
+fn dot(a: &Value, b: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    let shape_error = || ValueError::Shape(a.shape(), b.shape());
+    let (a_rank, b_rank) = (a.shape().0.len(), b.shape().0.len());
+    if a_rank == 1 && b_rank == 1 {
+        let (a, b) = (vector(a)?, vector(b)?);
+        if a.len() != b.len() {
+            return Err(shape_error());
+        }
+        return dot_vectors(a, b, mode);
+    }
+    let left = match a_rank {
+        1 => vec![vector(a)?.to_vec()],
+        _ => rows(a)?,
+    };
+    let right = match b_rank {
+        1 => vector(b)?.iter().map(|value| vec![value.clone()]).collect(),
+        _ => rows(b)?,
+    };
+    if left.first().map_or(0, Vec::len) != right.len() {
+        return Err(shape_error());
+    }
+    let right = columns(&right);
+    let product = left
+        .iter()
+        .map(|row| {
+            right
+                .iter()
+                .map(|column| dot_vectors(row, column, mode))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(match (a_rank, b_rank) {
+        (1, _) => Value::List(product.into_iter().next().unwrap_or_default()),
+        (_, 1) => Value::List(product.into_iter().flatten().collect()),
+        _ => matrix(product),
+    })
+}
+
+fn cross(a: &Value, b: &Value) -> Result<Value, ValueError> {
+    let (Ok([a1, a2, a3]), Ok([b1, b2, b3])) = (
+        <&[Value; 3]>::try_from(vector(a)?),
+        <&[Value; 3]>::try_from(vector(b)?),
+    ) else {
+        return Err(ValueError::Domain("both lists must have 3 elements"));
+    };
+    let term = |x: &Value, y: &Value, z: &Value, w: &Value| x.mul(y)?.sub(&z.mul(w)?);
+    Ok(Value::List(vec![
+        term(a2, b3, a3, b2)?,
+        term(a3, b1, a1, b3)?,
+        term(a1, b2, a2, b1)?,
+    ]))
+}
+
+/// Swaps rows and columns; a list becomes a single column.
+fn transpose(value: &Value) -> Result<Value, ValueError> {
+    if let Ok(items) = vector(value) {
+        return Ok(matrix(
+            items.iter().map(|item| vec![item.clone()]).collect(),
+        ));
+    }
+    Ok(matrix(columns(&rows(value)?)))
+}
+
+// Bareiss elimination divides only where the division is exact, so the
+// determinant of whole numbers stays whole, even in integer mode.
+fn det(value: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    let mut rows = square(value)?;
+    // A 1×1 matrix or an early zero column would leave some elements
+    // untouched, so they are all checked up front.
+    for element in rows.iter().flatten() {
+        element.magnitude()?;
+    }
+    let n = rows.len();
+    let mut previous = one(mode);
+    let mut negate = false;
+    for k in 0..n - 1 {
+        let Some(pivot) = (k..n).find(|&row| !rows[row][k].is_zero()) else {
+            return Ok(zero(mode));
+        };
+        if pivot != k {
+            rows.swap(pivot, k);
+            negate = !negate;
+        }
+        for i in k + 1..n {
+            for j in k + 1..n {
+                let cross = rows[i][j].mul(&rows[k][k])?;
+                let cross = cross.sub(&rows[i][k].mul(&rows[k][j])?)?;
+                rows[i][j] = cross.div(&previous)?;
+            }
+        }
+        previous = rows[k][k].clone();
+    }
+    let det = rows[n - 1][n - 1].clone();
+    if negate {
+        det.neg()
+    } else {
+        Ok(det)
+    }
+}
+
+// Gauss-Jordan elimination on the matrix next to the identity, picking the
+// largest pivot in each column to keep rounding in check.
+fn inverse(value: &Value, mode: NumericMode) -> Result<Value, ValueError> {
+    let n = square(value)?.len();
+    let mut rows: Vec<Vec<Value>> = square(value)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let identity = (0..n).map(|j| if i == j { one(mode) } else { zero(mode) });
+            row.iter()
+                .map(|value| divisible(value, mode))
+                .chain(identity.map(|value| divisible(&value, mode)))
+                .collect()
+        })
+        .collect();
+    let size = |value: &Value| {
+        value
+            .magnitude()
+            .map_or(0.0, |number| number.to_f64().abs())
+    };
+    for k in 0..n {
+        let pivot = (k..n)
+            .filter(|&row| !rows[row][k].is_zero())
+            .max_by(|&a, &b| size(&rows[a][k]).total_cmp(&size(&rows[b][k])))
+            .ok_or(ValueError::Domain("the matrix is singular"))?;
+        rows.swap(pivot, k);
+        let pivot = rows[k][k].clone();
+        rows[k] = rows[k]
+            .iter()
+            .map(|value| value.div(&pivot))
+            .collect::<Result<_, _>>()?;
+        for i in (0..n).filter(|&i| i != k) {
+            let factor = rows[i][k].clone();
+            rows[i] = rows[i]
+                .iter()
+                .zip(&rows[k])
+                .map(|(value, below)| value.sub(&factor.mul(below)?))
+                .collect::<Result<_, _>>()?;
+        }
+    }
+    Ok(matrix(
+        rows.into_iter().map(|row| row[n..].to_vec()).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::eval_expression;
+    use crate::memory::Memory;
+    use crate::token::tokenize;
+
+    fn eval(input: &str, mode: NumericMode) -> Result<Value, crate::error::CalcError> {
+        eval_expression(&tokenize(input).unwrap(), &Memory::with_mode(mode))
+    }
+
+    fn show(input: &str) -> String {
+        eval(input, NumericMode::Float).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_products() {
+        assert_eq!(show("dot([1, 2, 3], [4, 5, 6])"), "32");
+        assert_eq!(
+            show("dot([[1, 2], [3, 4]], [[5, 6], [7, 8]])"),
+            "[[19, 22], [43, 50]]"
+        );
+        assert_eq!(show("dot([[1, 2], [3, 4]], [1, 1])"), "[3, 7]");
+        assert_eq!(show("dot([1, 1], [[1, 2], [3, 4]])"), "[4, 6]");
+        assert_eq!(show("cross([1, 0, 0], [0, 1, 0])"), "[0, 0, 1]");
+        assert_eq!(
+            show("transpose([[1, 2, 3], [4, 5, 6]])"),
+            "[[1, 4], [2, 5], [3, 6]]"
+        );
+        assert_eq!(show("transpose([1, 2])"), "[[1], [2]]");
+    }
+
+    #[test]
+    fn test_det_and_inverse() {
+        assert_eq!(show("det([[1, 2], [3, 4]])"), "-2");
+        assert_eq!(show("det([[0, 1], [1, 0]])"), "-1");
+        assert_eq!(show("det([[2, 0, 1], [1, 3, 2], [1, 1, 2]])"), "6");
+        assert_eq!(show("det([[1, 2], [2, 4]])"), "0");
+        let det = eval(
+            "det([[2, 0, 1], [1, 3, 2], [1, 1, 2]])",
+            NumericMode::Integer,
+        );
+        assert_eq!(det, Ok(Value::Number(Number::Integer(6))));
+        assert_eq!(show("inverse([[2, 1], [1, 1]])"), "[[1, -1], [-1, 2]]");
+        let inverse = eval("inverse([[1, 2], [3, 4]])", NumericMode::Rational).unwrap();
+        assert_eq!(inverse.to_string(), "[[-2, 1], [1.5, -0.5]]");
+        let inverse = eval("inverse([[4, 0], [0, 2]])", NumericMode::Integer).unwrap();
+        assert_eq!(inverse.to_string(), "[[0.25, 0], [0, 0.5]]");
+        assert_eq!(
+            eval("inverse([[1, 2], [2, 4]])", NumericMode::Float)
+                .unwrap_err()
+                .to_string(),
+            "Domain error in 'inverse': the matrix is singular"
+        );
+        assert_eq!(
+            eval("det([[1, 2, 3], [4, 5, 6]])", NumericMode::Float)
+                .unwrap_err()
+                .to_string(),
+            "Domain error in 'det': the matrix must be square"
+        );
+        assert_eq!(show("det([[5]])"), "5");
+        assert_eq!(
+            eval("det([[\"a\"]])", NumericMode::Float)
+                .unwrap_err()
+                .to_string(),
+            "Type mismatch in 'det': expected a number but found a string"
+        );
+        assert!(eval("det([[true]])", NumericMode::Float).is_err());
+        assert!(eval("det([[0, \"a\"], [0, 1]])", NumericMode::Float).is_err());
+    }
+
+    #[test]
+    fn test_aggregates() {
+        assert_eq!(show("sum([1, 2, 3, 4])"), "10");
+        assert_eq!(show("sum([[1, 2], [3, 4]])"), "10");
+        assert_eq!(show("sum([])"), "0");
+        assert_eq!(show("sum([1 m, 50 cm])"), "1.5 m");
+    }
+
+    #[test]
+    fn test_shape_errors() {
+        let error = |input: &str| eval(input, NumericMode::Float).unwrap_err().to_string();
+        assert_eq!(
+            error("dot([1, 2], [1, 2, 3])"),
+            "Shape mismatch in 'dot': list of 2 and list of 3"
+        );
+        assert_eq!(
+            error("dot([[1, 2], [3, 4]], [[1, 2, 3]])"),
+            "Shape mismatch in 'dot': 2x2 matrix and 1x3 matrix"
+        );
+        assert_eq!(
+            error("cross([1, 2], [3, 4])"),
+            "Domain error in 'cross': both lists must have 3 elements"
+        );
+        assert_eq!(
+            error("det([1, 2])"),
+            "Type mismatch in 'det': expected a matrix but found a list"
+        );
+        assert_eq!(
+            error("mean(3)"),
+            "Type mismatch in 'mean': expected a list but found a number"
+        );
+    }
+}
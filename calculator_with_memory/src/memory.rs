@@ -1,7 +1,93 @@
+use crate::error::CalcError;
+use crate::expression::{Expr, Statement};
+use crate::number::NumericMode;
+use crate::parser::parse_statement;
+use crate::solver::SolverSettings;
+use crate::token::tokenize;
+use crate::value::{Value, ValueError};
 use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
 
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Line { line: usize, error: CalcError },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Line { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+impl fmt::Display for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}) = {}", self.params.join(", "), self.body)
+    }
+}
+
+/// What a `memX…` statement does to slot X. The arithmetic operations and
+/// store take an operand, which defaults to the previous result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Store,
+    Recall,
+    Clear,
+    Swap(String),
+}
+
+impl MemoryOp {
+    pub fn takes_operand(&self) -> bool {
+        matches!(
+            self,
+            MemoryOp::Add | MemoryOp::Subtract | MemoryOp::Multiply | MemoryOp::Store
+        )
+    }
+}
+
+impl fmt::Display for MemoryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryOp::Add => write!(f, "+"),
+            MemoryOp::Subtract => write!(f, "-"),
+            MemoryOp::Multiply => write!(f, "*"),
+            MemoryOp::Store => write!(f, "="),
+            MemoryOp::Recall => write!(f, "?"),
+            MemoryOp::Clear => write!(f, "!"),
+            MemoryOp::Swap(other) => write!(f, "<>{}", other),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Memory {
-    pub slots: HashMap<String, f64>,
+    slots: HashMap<String, Value>,
+    functions: HashMap<String, UserFunction>,
+    mode: NumericMode,
+    solver: SolverSettings,
 }
 
 impl Default for Memory {
@@ -12,22 +98,324 @@ impl Default for Memory {
 
 impl Memory {
     pub fn new() -> Self {
+        Memory::with_mode(NumericMode::default())
+    }
+
+    pub fn with_mode(mode: NumericMode) -> Self {
         Self {
             slots: HashMap::new(),
+            functions: HashMap::new(),
+            mode,
+            solver: SolverSettings::default(),
+        }
+    }
+
+    pub fn mode(&self) -> NumericMode {
+        self.mode
+    }
+
+    /// How `solve` searches for roots.
+    pub fn solver(&self) -> SolverSettings {
+        self.solver
+    }
+
+    pub fn set_solver(&mut self, settings: SolverSettings) {
+        self.solver = settings;
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.slots.get(key).cloned()
+    }
+
+    pub fn set(&mut self, mem_name: String, value: Value) {
+        self.slots.insert(mem_name, value);
+    }
+
+    /// Adds `value` to slot `mem_name`, which starts out empty as `value`
+    /// itself. Fails when both hold quantities of different dimensions.
+    pub fn update(&mut self, mem_name: String, value: Value) -> Result<(), ValueError> {
+        match self.slots.get_mut(&mem_name) {
+            Some(slot) => *slot = slot.add(&value)?,
+            None => {
+                self.slots.insert(mem_name, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Multiplies slot `mem_name` by `value`; a missing slot counts as zero.
+    pub fn multiply(&mut self, mem_name: String, value: Value) -> Result<(), ValueError> {
+        match self.slots.get_mut(&mem_name) {
+            Some(slot) => *slot = slot.mul(&value)?,
+            None => {
+                let zero = Value::Number(self.mode.from_f64(0.0));
+                self.slots.insert(mem_name, zero);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes only the slot called `name`, leaving a function of the same
+    /// name alone, and returns what it held.
+    pub fn take(&mut self, name: &str) -> Option<Value> {
+        self.slots.remove(name)
+    }
+
+    /// Exchanges two slots. Nothing changes unless both exist.
+    pub fn swap(&mut self, a: &str, b: &str) -> bool {
+        match (self.slots.remove(a), self.slots.remove(b)) {
+            (Some(a_value), Some(b_value)) => {
+                self.slots.insert(a.to_string(), b_value);
+                self.slots.insert(b.to_string(), a_value);
+                true
+            }
+            (a_value, b_value) => {
+                if let Some(value) = a_value {
+                    self.slots.insert(a.to_string(), value);
+                }
+                if let Some(value) = b_value {
+                    self.slots.insert(b.to_string(), value);
+                }
+                false
+            }
         }
     }
 
-    pub fn get(&self, key: &str) -> Result<f64, String> {
-        self.slots
-            .get(key)
-            .copied()
-            .ok_or_else(|| format!("Key not found: {}", key))
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    pub fn define(&mut self, name: String, function: UserFunction) {
+        self.functions.insert(name, function);
     }
 
-    pub fn update(&mut self, mem_name: String, value: f64) {
-        self.slots
-            .entry(mem_name)
-            .and_modify(|v| *v += value)
-            .or_insert(value);
+    pub fn slots(&self) -> Vec<(&str, &Value)> {
+        sorted_entries(&self.slots)
+    }
+
+    pub fn functions(&self) -> Vec<(&str, &UserFunction)> {
+        sorted_entries(&self.functions)
+    }
+
+    /// Names of every slot and function, sorted and without duplicates.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .slots
+            .keys()
+            .chain(self.functions.keys())
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty() && self.functions.is_empty()
+    }
+
+    /// Removes the slot and the function called `name`, returning whether
+    /// either existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let slot = self.slots.remove(name).is_some();
+        let function = self.functions.remove(name).is_some();
+        slot || function
+    }
+
+    pub fn reset(&mut self) {
+        self.slots.clear();
+        self.functions.clear();
+    }
+
+    /// Serializes slots and functions as calculator statements, one per
+    /// line and sorted by name, so a saved file can be read, edited and
+    /// diffed by hand:
+    ///
+    /// ```text
+    /// # calculator_with_memory
+    /// let A = 12.5
+    /// let third = 1/3
+    /// fn area(w, h) = w * h
+    /// ```
+    ///
+    /// Blank lines and lines starting with `#` are ignored when loading.
+    /// Slots holding infinity or NaN cannot be written back as a literal
+    /// and are left out.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# calculator_with_memory\n");
+        for (name, value) in self.slots() {
+            match value {
+                // Reading `simplify(...)` back gives the same expression.
+                Value::Expr(expr) => text.push_str(&format!("let {} = simplify({})\n", name, expr)),
+                _ if value.is_finite() => text.push_str(&format!("let {} = {}\n", name, value)),
+                _ => {}
+            }
+        }
+        for (name, function) in self.functions() {
+            text.push_str(&format!("fn {}{}\n", name, function));
+        }
+        text
+    }
+
+    /// Reads statements written by `to_text`, overwriting slots and
+    /// functions of the same name and keeping every other entry.
+    pub fn load_text(&mut self, text: &str) -> Result<(), LoadError> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.load_line(line).map_err(|error| LoadError::Line {
+                line: index + 1,
+                error,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn load_line(&mut self, line: &str) -> Result<(), CalcError> {
+        match parse_statement(&tokenize(line)?)? {
+            Statement::Let { name, value } => {
+                let value = value.eval(self)?;
+                self.set(name, value);
+            }
+            Statement::FnDef { name, function } => self.define(name, function),
+            Statement::Expr(expr) => {
+                return Err(CalcError::UnexpectedToken {
+                    found: expr.to_string(),
+                    span: expr.span,
+                })
+            }
+            Statement::Memory { name, op, span, .. } => {
+                return Err(CalcError::UnexpectedToken {
+                    found: format!("mem{}{}", name, op),
+                    span,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), LoadError> {
+        let text = fs::read_to_string(path)?;
+        self.load_text(&text)
+    }
+}
+
+fn sorted_entries<T>(map: &HashMap<String, T>) -> Vec<(&str, &T)> {
+    let mut entries: Vec<_> = map.iter().map(|(name, v)| (name.as_str(), v)).collect();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_from(mode: NumericMode, text: &str) -> Memory {
+        let mut memory = Memory::with_mode(mode);
+        memory.load_text(text).unwrap();
+        memory
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = "let third = 1 / 3\nlet A = 0.1 + 0.2\nfn area(w, h) = w * (h)\n";
+        let memory = memory_from(NumericMode::Rational, source);
+        let text = memory.to_text();
+        assert_eq!(
+            text,
+            "# calculator_with_memory\nlet A = 0.3\nlet third = 1/3\nfn area(w, h) = w * h\n"
+        );
+
+        let restored = memory_from(NumericMode::Rational, &text);
+        assert_eq!(restored.slots(), memory.slots());
+        assert_eq!(restored.functions(), memory.functions());
+    }
+
+    #[test]
+    fn test_float_values_survive_round_trip() {
+        let mut memory = Memory::new();
+        memory.set("tiny".to_string(), Value::from(0.1 + 0.2));
+        memory.set("huge".to_string(), Value::from(-1.5e300));
+        memory.set("bad".to_string(), Value::from(f64::NAN));
+        let restored = memory_from(NumericMode::Float, &memory.to_text());
+        assert_eq!(restored.get("tiny"), memory.get("tiny"));
+        assert_eq!(restored.get("huge"), memory.get("huge"));
+        assert_eq!(restored.get("bad"), None);
+    }
+
+    #[test]
+    fn test_quantities_round_trip() {
+        let source = "let link = 100 Mbit/s\nlet third = (1/3) km\nlet twice = simplify(x + x)\nlet v = [1/2, 2] m\nlet path = \"data.csv\"\n";
+        let memory = memory_from(NumericMode::Rational, source);
+        assert_eq!(
+            memory.to_text(),
+            "# calculator_with_memory\nlet link = 100 Mbit/s\nlet path = \"data.csv\"\nlet third = (1/3) km\nlet twice = simplify(2 * x)\nlet v = [0.5 m, 2 m]\n"
+        );
+        let restored = memory_from(NumericMode::Rational, &memory.to_text());
+        assert_eq!(restored.slots(), memory.slots());
+    }
+
+    #[test]
+    fn test_slot_operations() {
+        let mut memory = memory_from(NumericMode::Float, "let a = 2\nlet b = 5\nfn a(x) = x\n");
+        memory.multiply("a".to_string(), Value::from(3.0)).unwrap();
+        memory.multiply("z".to_string(), Value::from(3.0)).unwrap();
+        assert_eq!(memory.get("a"), Some(Value::from(6.0)));
+        assert_eq!(memory.get("z"), Some(Value::from(0.0)));
+
+        assert!(memory.swap("a", "b"));
+        assert_eq!(memory.get("a"), Some(Value::from(5.0)));
+        assert_eq!(memory.get("b"), Some(Value::from(6.0)));
+        assert!(!memory.swap("a", "missing"));
+        assert_eq!(memory.get("a"), Some(Value::from(5.0)));
+        assert_eq!(memory.get("missing"), None);
+
+        assert_eq!(memory.take("a"), Some(Value::from(5.0)));
+        assert!(memory.function("a").is_some());
+        assert_eq!(memory.take("a"), None);
+    }
+
+    #[test]
+    fn test_remove_and_reset() {
+        let mut memory = memory_from(
+            NumericMode::Float,
+            "let b = 2\nlet a = 1\nfn a(x) = x\nfn f(x) = x",
+        );
+        let names: Vec<_> = memory.slots().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(memory.names(), vec!["a", "b", "f"]);
+
+        assert!(memory.remove("a"));
+        assert!(!memory.remove("a"));
+        assert_eq!(memory.get("a"), None);
+        assert!(memory.function("a").is_none());
+        assert!(memory.function("f").is_some());
+
+        memory.reset();
+        assert!(memory.is_empty());
+    }
+
+    #[test]
+    fn test_load_merges_and_reports_line() {
+        let mut memory = memory_from(NumericMode::Float, "let A = 1\nlet B = 2");
+        memory.load_text("# comment\n\nlet B = 5\n").unwrap();
+        assert_eq!(memory.get("A"), Some(Value::from(1.0)));
+        assert_eq!(memory.get("B"), Some(Value::from(5.0)));
+
+        let error = memory.load_text("let C = 1\nlet D = C +\n").unwrap_err();
+        assert!(matches!(
+            error,
+            LoadError::Line {
+                line: 2,
+                error: CalcError::UnexpectedEnd { .. }
+            }
+        ));
+        assert!(memory.load_text("1 + 2").is_err());
     }
 }
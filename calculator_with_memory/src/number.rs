@@ -0,0 +1,671 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT_DECIMAL_PRECISION: u32 = 20;
+
+/// The most digits `decimal:N` keeps after the point. Every decimal result
+/// is rounded through a power of ten this long.
+pub const MAX_DECIMAL_PRECISION: u32 = 1000;
+
+// Exponents above this are computed in floating point even in the exact
+// modes, since the exact result would be millions of digits long.
+const MAX_EXACT_EXPONENT: i32 = 4096;
+
+// Literal exponents beyond this are rejected by `parse_literal` rather than
+// expanded into a power of ten with hundreds of thousands of digits.
+const MAX_LITERAL_EXPONENT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumericMode {
+    #[default]
+    Float,
+    Rational,
+    Decimal(u32),
+    /// Whole numbers are 64-bit integers; anything else stays a float.
+    Integer,
+}
+
+impl NumericMode {
+    pub fn convert(self, number: &Number) -> Number {
+        match self {
+            NumericMode::Integer => match number.to_i64() {
+                Some(value) => Number::Integer(value),
+                None => Number::Float(number.to_f64()),
+            },
+            NumericMode::Float => Number::Float(number.to_f64()),
+            NumericMode::Rational => match number {
+                Number::Float(value) => Number::from_f64_exact(*value, None),
+                _ => Number::Rational(number.to_rational()),
+            },
+            NumericMode::Decimal(scale) => match number {
+                Number::Float(value) => Number::from_f64_exact(*value, Some(scale)),
+                _ => Number::Decimal(Decimal::from_rational(&number.to_rational(), scale)),
+            },
+        }
+    }
+
+    pub fn from_f64(self, value: f64) -> Number {
+        self.convert(&Number::Float(value))
+    }
+}
+
+impl FromStr for NumericMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "float" => Ok(NumericMode::Float),
+            None if s == "rational" => Ok(NumericMode::Rational),
+            None if s == "decimal" => Ok(NumericMode::Decimal(DEFAULT_DECIMAL_PRECISION)),
+            None if s == "integer" => Ok(NumericMode::Integer),
+            Some(("decimal", digits)) => digits
+                .parse()
+                .ok()
+                .filter(|&digits| digits <= MAX_DECIMAL_PRECISION)
+                .map(NumericMode::Decimal)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid decimal precision: {} (expected at most {})",
+                        digits, MAX_DECIMAL_PRECISION
+                    )
+                }),
+            _ => Err(format!(
+                "Unknown numeric mode '{}' (expected float, rational, decimal, decimal:N or integer)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NumericMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NumericMode::Float => write!(f, "float"),
+            NumericMode::Rational => write!(f, "rational"),
+            NumericMode::Decimal(scale) => write!(f, "decimal:{}", scale),
+            NumericMode::Integer => write!(f, "integer"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decimal {
+    units: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    /// Rounds `value` to `scale` fractional digits, halves away from zero.
+    pub fn from_rational(value: &BigRational, scale: u32) -> Self {
+        let scaled = value * BigRational::from_integer(pow10(scale));
+        Self {
+            units: scaled.round().to_integer(),
+            scale,
+        }
+    }
+
+    pub fn to_rational(&self) -> BigRational {
+        BigRational::new(self.units.clone(), pow10(self.scale))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", decimal_string(&self.units, self.scale))
+    }
+}
+
+/// Why an operation has no result. Only integers can overflow; the other
+/// kinds grow or lose precision instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberError {
+    Overflow,
+    Domain(&'static str),
+}
+
+impl NumberError {
+    pub fn message(self) -> &'static str {
+        match self {
+            NumberError::Overflow => "integer overflow",
+            NumberError::Domain(message) => message,
+        }
+    }
+}
+
+const NOT_WHOLE: NumberError = NumberError::Domain("operands must be whole numbers");
+const ZERO_TO_NEGATIVE_POWER: NumberError =
+    NumberError::Domain("zero cannot be raised to a negative power");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Float(f64),
+    Rational(BigRational),
+    Decimal(Decimal),
+    Integer(i64),
+}
+
+impl From<f64> for Number {
+    fn from(value: f64) -> Self {
+        Number::Float(value)
+    }
+}
+
+impl Number {
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Float(value) => *value,
+            Number::Rational(value) => value.to_f64().unwrap_or(f64::NAN),
+            Number::Decimal(value) => value.to_rational().to_f64().unwrap_or(f64::NAN),
+            Number::Integer(value) => *value as f64,
+        }
+    }
+
+    /// The value as an `i64` if it is a whole number in range.
+    pub fn to_i64(&self) -> Option<i64> {
+        match self {
+            Number::Integer(value) => Some(*value),
+            Number::Float(value) => {
+                let in_range = *value >= i64::MIN as f64 && *value < i64::MAX as f64;
+                (value.fract() == 0.0 && in_range).then_some(*value as i64)
+            }
+            _ => {
+                let value = self.to_rational();
+                value
+                    .is_integer()
+                    .then(|| value.to_integer().to_i64())
+                    .flatten()
+            }
+        }
+    }
+
+    /// The exact value of a rational or decimal; floats are expanded to the
+    /// rational they represent, with non-finite floats collapsing to zero.
+    pub fn to_rational(&self) -> BigRational {
+        match self {
+            Number::Float(value) => BigRational::from_float(*value).unwrap_or_default(),
+            Number::Rational(value) => value.clone(),
+            Number::Decimal(value) => value.to_rational(),
+            Number::Integer(value) => BigRational::from_integer(BigInt::from(*value)),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Float(value) => *value == 0.0,
+            Number::Rational(value) => value.is_zero(),
+            Number::Decimal(value) => value.units.is_zero(),
+            Number::Integer(value) => *value == 0,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Number::Float(value) => *value < 0.0,
+            Number::Rational(value) => value.is_negative(),
+            Number::Decimal(value) => value.units.is_negative(),
+            Number::Integer(value) => *value < 0,
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Number::Float(value) => value.is_finite(),
+            _ => true,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Number::Float(_))
+    }
+
+    fn scale(&self) -> Option<u32> {
+        match self {
+            Number::Decimal(value) => Some(value.scale),
+            _ => None,
+        }
+    }
+
+    fn exact(value: BigRational, scale: Option<u32>) -> Number {
+        match scale {
+            Some(scale) => Number::Decimal(Decimal::from_rational(&value, scale)),
+            None => Number::Rational(value),
+        }
+    }
+
+    fn from_f64_exact(value: f64, scale: Option<u32>) -> Number {
+        match BigRational::from_float(value) {
+            Some(value) => Number::exact(value, scale),
+            None => Number::Float(value),
+        }
+    }
+
+    // Mixed operands are promoted to the less exact kind: any float makes
+    // the result a float, any decimal makes it a decimal at the larger scale
+    // and integers only stay integers among themselves.
+    fn combine(
+        &self,
+        other: &Number,
+        integer: impl FnOnce(i64, i64) -> Option<i64>,
+        float: impl FnOnce(f64, f64) -> f64,
+        exact: impl FnOnce(BigRational, BigRational) -> BigRational,
+    ) -> Result<Number, NumberError> {
+        if let (Number::Integer(a), Number::Integer(b)) = (self, other) {
+            return integer(*a, *b)
+                .map(Number::Integer)
+                .ok_or(NumberError::Overflow);
+        }
+        if self.is_float() || other.is_float() {
+            return Ok(Number::Float(float(self.to_f64(), other.to_f64())));
+        }
+        let scale = self.scale().max(other.scale());
+        Ok(Number::exact(
+            exact(self.to_rational(), other.to_rational()),
+            scale,
+        ))
+    }
+
+    pub fn add(&self, other: &Number) -> Result<Number, NumberError> {
+        self.combine(other, i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+
+    pub fn sub(&self, other: &Number) -> Result<Number, NumberError> {
+        self.combine(other, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub fn mul(&self, other: &Number) -> Result<Number, NumberError> {
+        self.combine(other, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+    }
+
+    /// Callers check `is_zero` on the divisor first. Integers divide with
+    /// truncation, like integer division in C or Rust.
+    pub fn div(&self, other: &Number) -> Result<Number, NumberError> {
+        self.combine(other, i64::checked_div, |a, b| a / b, |a, b| a / b)
+    }
+
+    /// Truncating remainder with the sign of the dividend, like `f64::rem`.
+    pub fn rem(&self, other: &Number) -> Result<Number, NumberError> {
+        self.combine(
+            other,
+            i64::checked_rem,
+            |a, b| a % b,
+            |a, b| &a - &b * (&a / &b).trunc(),
+        )
+    }
+
+    pub fn neg(&self) -> Result<Number, NumberError> {
+        Ok(match self {
+            Number::Float(value) => Number::Float(-value),
+            Number::Rational(value) => Number::Rational(-value),
+            Number::Decimal(value) => Number::Decimal(Decimal {
+                units: -&value.units,
+                scale: value.scale,
+            }),
+            Number::Integer(value) => {
+                Number::Integer(value.checked_neg().ok_or(NumberError::Overflow)?)
+            }
+        })
+    }
+
+    /// Applies a bitwise operation to two whole numbers. The result has the
+    /// kind the operands would have been promoted to.
+    pub fn bitwise(
+        &self,
+        other: &Number,
+        op: impl FnOnce(i64, i64) -> Result<i64, NumberError>,
+    ) -> Result<Number, NumberError> {
+        let a = self.to_i64().ok_or(NOT_WHOLE)?;
+        let b = other.to_i64().ok_or(NOT_WHOLE)?;
+        let value = op(a, b)?;
+        self.combine(
+            other,
+            |_, _| Some(value),
+            |_, _| value as f64,
+            |_, _| BigRational::from_integer(BigInt::from(value)),
+        )
+    }
+
+    pub fn bit_not(&self) -> Result<Number, NumberError> {
+        self.bitwise(self, |a, _| Ok(!a))
+    }
+
+    pub fn pow(&self, exponent: &Number) -> Result<Number, NumberError> {
+        if let (Number::Integer(base), Number::Integer(power)) = (self, exponent) {
+            // A negative power is rarely whole, so it gives a float, as
+            // anything else that is not a whole number does in integer mode.
+            if *power < 0 {
+                if *base == 0 {
+                    return Err(ZERO_TO_NEGATIVE_POWER);
+                }
+                return Ok(Number::Float((*base as f64).powf(*power as f64)));
+            }
+            // Past u32::MAX only 0, 1 and -1 do not overflow, and those come
+            // out the same at any power of the same parity.
+            let power = u32::try_from(*power).unwrap_or(u32::MAX - u32::from(power % 2 == 0));
+            return base
+                .checked_pow(power)
+                .map(Number::Integer)
+                .ok_or(NumberError::Overflow);
+        }
+        if !self.is_float() && !exponent.is_float() {
+            let power = exponent.to_rational();
+            let small_integer = power
+                .is_integer()
+                .then(|| power.to_integer().to_i32())
+                .flatten()
+                .filter(|n| n.abs() <= MAX_EXACT_EXPONENT);
+            if let Some(n) = small_integer {
+                let base = self.to_rational();
+                if base.is_zero() && n < 0 {
+                    return Err(ZERO_TO_NEGATIVE_POWER);
+                }
+                let scale = self.scale().max(exponent.scale());
+                return Ok(Number::exact(base.pow(n), scale));
+            }
+        }
+        let value = self.to_f64().powf(exponent.to_f64());
+        if self.is_float() || exponent.is_float() {
+            return Ok(Number::Float(value));
+        }
+        BigRational::from_float(value)
+            .map(|value| Number::exact(value, self.scale().max(exponent.scale())))
+            .ok_or(NumberError::Domain("result is not a finite number"))
+    }
+
+    /// Computes `f` in floating point and converts the result back into the
+    /// kind of `self`, for functions that have no exact counterpart. Integers
+    /// give floats, as the result is rarely whole.
+    pub fn map_f64(&self, f: impl FnOnce(f64) -> f64) -> Result<Number, &'static str> {
+        let value = f(self.to_f64());
+        if matches!(self, Number::Float(_) | Number::Integer(_)) {
+            return Ok(Number::Float(value));
+        }
+        BigRational::from_float(value)
+            .map(|value| Number::exact(value, self.scale()))
+            .ok_or("result is not a finite number")
+    }
+
+    fn map_exact(
+        &self,
+        float: impl FnOnce(f64) -> f64,
+        exact: impl FnOnce(&BigRational) -> BigRational,
+    ) -> Number {
+        match self {
+            Number::Float(value) => Number::Float(float(*value)),
+            Number::Integer(value) => Number::Integer(*value),
+            _ => Number::exact(exact(&self.to_rational()), self.scale()),
+        }
+    }
+
+    pub fn abs(&self) -> Result<Number, NumberError> {
+        if let Number::Integer(value) = self {
+            return value
+                .checked_abs()
+                .map(Number::Integer)
+                .ok_or(NumberError::Overflow);
+        }
+        Ok(self.map_exact(f64::abs, |value| value.abs()))
+    }
+
+    /// Writes a whole number in base 2, 8 or 16 with the prefix it would be
+    /// typed with, e.g. `-0xff`. Other numbers print as usual.
+    pub fn to_string_radix(&self, radix: u32) -> String {
+        let Some(value) = self.to_i64().filter(|_| radix != 10) else {
+            return self.to_string();
+        };
+        let sign = if value < 0 { "-" } else { "" };
+        let magnitude = value.unsigned_abs();
+        match radix {
+            2 => format!("{}0b{:b}", sign, magnitude),
+            8 => format!("{}0o{:o}", sign, magnitude),
+            16 => format!("{}0x{:x}", sign, magnitude),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        self.map_exact(f64::floor, BigRational::floor)
+    }
+
+    pub fn ceil(&self) -> Number {
+        self.map_exact(f64::ceil, BigRational::ceil)
+    }
+
+    pub fn round(&self) -> Number {
+        self.map_exact(f64::round, BigRational::round)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.is_float() || other.is_float() {
+            self.to_f64().partial_cmp(&other.to_f64())
+        } else {
+            self.to_rational().partial_cmp(&other.to_rational())
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Number::Float(value) => write!(f, "{}", value),
+            Number::Rational(value) => match terminating_scale(value.denom()) {
+                Some(scale) => {
+                    let units = value * BigRational::from_integer(pow10(scale));
+                    write!(f, "{}", decimal_string(&units.to_integer(), scale))
+                }
+                None => write!(f, "{}/{}", value.numer(), value.denom()),
+            },
+            Number::Decimal(value) => write!(f, "{}", value),
+            Number::Integer(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Parses a literal such as `12`, `.5` or `2.5e-3` into its exact value.
+pub fn parse_literal(text: &str) -> Option<BigRational> {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(at) => (&text[..at], text[at + 1..].parse::<i64>().ok()?),
+        None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let digits = BigInt::from_str(&format!("0{}{}", whole, fraction)).ok()?;
+    let exponent = exponent.checked_sub(fraction.len() as i64)?;
+    if exponent.abs() > MAX_LITERAL_EXPONENT {
+        return None;
+    }
+    let power = pow10(exponent.unsigned_abs() as u32);
+    Some(if exponent < 0 {
+        BigRational::new(digits, power)
+    } else {
+        BigRational::from_integer(digits * power)
+    })
+}
+
+fn pow10(exponent: u32) -> BigInt {
+    num_traits::pow(BigInt::from(10), exponent as usize)
+}
+
+// A fraction has a finite decimal expansion exactly when its reduced
+// denominator has no prime factors other than 2 and 5.
+fn terminating_scale(denom: &BigInt) -> Option<u32> {
+    let mut rest = denom.clone();
+    let (mut twos, mut fives) = (0, 0);
+    let (two, five) = (BigInt::from(2), BigInt::from(5));
+    while (&rest % &two).is_zero() {
+        rest /= &two;
+        twos += 1;
+    }
+    while (&rest % &five).is_zero() {
+        rest /= &five;
+        fives += 1;
+    }
+    rest.is_one().then_some(twos.max(fives))
+}
+
+fn decimal_string(units: &BigInt, scale: u32) -> String {
+    let digits = units.abs().to_string();
+    let scale = scale as usize;
+    let padded = format!("{:0>width$}", digits, width = scale + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - scale);
+    let fraction = fraction.trim_end_matches('0');
+    let sign = if units.is_negative() { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> Number {
+        Number::Rational(parse_literal(text).unwrap())
+    }
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(literal("0.1").to_string(), "0.1");
+        assert_eq!(literal("2.5e-3").to_string(), "0.0025");
+        assert_eq!(literal("1E3").to_string(), "1000");
+        assert_eq!(literal(".5").to_string(), "0.5");
+        assert_eq!(parse_literal("."), None);
+        assert_eq!(parse_literal("1e99999"), None);
+    }
+
+    #[test]
+    fn test_rational_is_exact() {
+        let sum = literal("0.1").add(&literal("0.2")).unwrap();
+        assert_eq!(sum, literal("0.3"));
+        assert_eq!(sum.to_string(), "0.3");
+        assert_eq!(literal("1").div(&literal("3")).unwrap().to_string(), "1/3");
+        let negative = |text| literal(text).neg().unwrap();
+        assert_eq!(literal("2").pow(&negative("2")), Ok(literal("0.25")));
+        assert_eq!(literal("7").rem(&negative("3")), Ok(literal("1")));
+        assert_eq!(negative("7").rem(&literal("3")), Ok(negative("1")));
+    }
+
+    #[test]
+    fn test_decimal_rounds_to_precision() {
+        let mode = NumericMode::Decimal(4);
+        let third = mode
+            .convert(&literal("1"))
+            .div(&mode.convert(&literal("3")))
+            .unwrap();
+        assert_eq!(third.to_string(), "0.3333");
+        assert_eq!(
+            third.mul(&mode.convert(&literal("3"))).unwrap().to_string(),
+            "0.9999"
+        );
+        let two_thirds = mode
+            .convert(&literal("2"))
+            .div(&mode.convert(&literal("3")))
+            .unwrap();
+        assert_eq!(two_thirds.to_string(), "0.6667");
+        assert_eq!(two_thirds.neg().unwrap().round().to_string(), "-1");
+    }
+
+    #[test]
+    fn test_mixed_kinds_promote() {
+        let half = NumericMode::Decimal(2).convert(&literal("0.5"));
+        assert_eq!(half.add(&literal("0.25")).unwrap().to_string(), "0.75");
+        assert_eq!(half.add(&Number::Float(1.0)), Ok(Number::Float(1.5)));
+        assert_eq!(
+            Number::Integer(2).add(&literal("0.5")).unwrap().to_string(),
+            "2.5"
+        );
+        assert!(literal("0.1") < literal("0.2"));
+    }
+
+    #[test]
+    fn test_mode_from_str() {
+        assert_eq!("float".parse(), Ok(NumericMode::Float));
+        assert_eq!("rational".parse(), Ok(NumericMode::Rational));
+        assert_eq!(
+            "decimal".parse(),
+            Ok(NumericMode::Decimal(DEFAULT_DECIMAL_PRECISION))
+        );
+        assert_eq!("decimal:4".parse(), Ok(NumericMode::Decimal(4)));
+        assert!("decimal:x".parse::<NumericMode>().is_err());
+        assert_eq!("decimal:1000".parse(), Ok(NumericMode::Decimal(1000)));
+        assert_eq!(
+            "decimal:4000000000".parse::<NumericMode>(),
+            Err("Invalid decimal precision: 4000000000 (expected at most 1000)".to_string())
+        );
+        assert_eq!("integer".parse(), Ok(NumericMode::Integer));
+        assert!("int".parse::<NumericMode>().is_err());
+    }
+
+    #[test]
+    fn test_integer_overflow_is_an_error() {
+        let max = Number::Integer(i64::MAX);
+        assert_eq!(max.add(&Number::Integer(1)), Err(NumberError::Overflow));
+        assert_eq!(
+            Number::Integer(2).pow(&Number::Integer(63)),
+            Err(NumberError::Overflow)
+        );
+        assert_eq!(Number::Integer(i64::MIN).neg(), Err(NumberError::Overflow));
+        assert_eq!(
+            Number::Integer(7).div(&Number::Integer(-2)),
+            Ok(Number::Integer(-3))
+        );
+        assert_eq!(
+            Number::Integer(2).pow(&Number::Integer(-1)),
+            Ok(Number::Float(0.5))
+        );
+        assert_eq!(
+            Number::Integer(0).pow(&Number::Integer(-1)),
+            Err(ZERO_TO_NEGATIVE_POWER)
+        );
+        assert_eq!(
+            Number::Integer(-1).pow(&Number::Integer(i64::MAX)),
+            Ok(Number::Integer(-1))
+        );
+        assert_eq!(
+            Number::Integer(2).pow(&Number::Integer(1 << 40)),
+            Err(NumberError::Overflow)
+        );
+        assert_eq!(
+            NumericMode::Integer.convert(&literal("2.5")),
+            Number::Float(2.5)
+        );
+    }
+
+    #[test]
+    fn test_bitwise_and_radix() {
+        let value = Number::Integer(0xf0);
+        assert_eq!(
+            value.bitwise(&Number::Integer(0x3c), |a, b| Ok(a & b)),
+            Ok(Number::Integer(0x30))
+        );
+        assert_eq!(Number::Integer(0).bit_not(), Ok(Number::Integer(-1)));
+        assert_eq!(
+            literal("1.5").bitwise(&value, |a, b| Ok(a | b)),
+            Err(NOT_WHOLE)
+        );
+        assert_eq!(value.to_string_radix(16), "0xf0");
+        assert_eq!(Number::Integer(-10).to_string_radix(2), "-0b1010");
+        assert_eq!(literal("15").to_string_radix(8), "0o17");
+        assert_eq!(literal("0.5").to_string_radix(16), "0.5");
+    }
+
+    #[test]
+    fn test_map_f64_keeps_kind() {
+        assert_eq!(literal("4").map_f64(f64::sqrt), Ok(literal("2")));
+        assert!(literal("1").neg().unwrap().map_f64(f64::ln).is_err());
+        assert!(Number::Float(-1.0)
+            .map_f64(f64::ln)
+            .unwrap()
+            .to_f64()
+            .is_nan());
+    }
+}
@@ -0,0 +1,919 @@
+use crate::error::CalcError;
+use crate::expression::{
+    BinaryOp, Expr, ExprKind, Statement, UnaryOp, CONVERT_PRECEDENCE, NOT_PRECEDENCE,
+};
+use crate::memory::UserFunction;
+use crate::token::{Span, Token, TokenKind};
+use crate::units::Unit;
+use num_traits::{One, ToPrimitive};
+
+// Words that continue an expression rather than start an operand.
+const INFIX_WORDS: &[&str] = &["and", "or", "xor", "to", "in"];
+
+/// How deeply parentheses, brackets, calls, prefix operators and exponents
+/// may nest. Each level recurses, so deeper input is refused before it can
+/// exhaust the stack. A run of left-associative operators such as
+/// `1 + 2 + 3` is read in a loop and does not count.
+pub const MAX_NESTING: usize = 64;
+
+/// How many binary operators and conversions one expression may hold. Each
+/// still adds a level to the tree that evaluation and printing walk.
+pub const MAX_OPERATORS: usize = 1000;
+
+pub fn parse(tokens: &[Token]) -> Result<Expr, CalcError> {
+    let mut parser = Parser::new(tokens);
+    parser.expression_to_end()
+}
+
+pub fn parse_statement(tokens: &[Token]) -> Result<Statement, CalcError> {
+    let mut parser = Parser::new(tokens);
+    let keyword = match tokens {
+        [Token {
+            kind: TokenKind::Ident(keyword),
+            ..
+        }, Token {
+            kind: TokenKind::Ident(_),
+            ..
+        }, ..] => keyword.as_str(),
+        _ => "",
+    };
+    if let Some(Token {
+        kind: TokenKind::Memory { name, op },
+        span,
+    }) = tokens.first()
+    {
+        parser.index += 1;
+        let operand = match parser.peek() {
+            None => None,
+            Some(_) if op.takes_operand() => Some(parser.expression_to_end()?),
+            Some(token) => return Err(unexpected(token)),
+        };
+        return Ok(Statement::Memory {
+            name: name.clone(),
+            op: op.clone(),
+            operand,
+            span: *span,
+        });
+    }
+    // `name = value` is short for `let name = value`.
+    if let [Token {
+        kind: TokenKind::Ident(name),
+        ..
+    }, Token {
+        kind: TokenKind::Equals,
+        ..
+    }, ..] = tokens
+    {
+        parser.index += 2;
+        let value = parser.expression_to_end()?;
+        return Ok(Statement::Let {
+            name: name.clone(),
+            value,
+        });
+    }
+    match keyword {
+        "let" => {
+            parser.index += 1;
+            let name = parser.ident()?;
+            parser.expect(TokenKind::Equals)?;
+            let value = parser.expression_to_end()?;
+            Ok(Statement::Let { name, value })
+        }
+        "fn" => {
+            parser.index += 1;
+            let name = parser.ident()?;
+            parser.expect(TokenKind::LParen)?;
+            let mut params = Vec::new();
+            if parser.peek_kind() != Some(&TokenKind::RParen) {
+                params.push(parser.ident()?);
+                while parser.peek_kind() == Some(&TokenKind::Comma) {
+                    parser.index += 1;
+                    params.push(parser.ident()?);
+                }
+            }
+            parser.expect(TokenKind::RParen)?;
+            parser.expect(TokenKind::Equals)?;
+            let body = parser.expression_to_end()?;
+            Ok(Statement::FnDef {
+                name,
+                function: UserFunction { params, body },
+            })
+        }
+        _ => parser.expression_to_end().map(Statement::Expr),
+    }
+}
+
+fn unexpected(token: &Token) -> CalcError {
+    CalcError::UnexpectedToken {
+        found: token.kind.to_string(),
+        span: token.span,
+    }
+}
+
+// The binary operator a token stands for. Bitwise operators follow C, but
+// `^` is already the power operator, so exclusive or is spelled `xor`; `^`
+// itself is read with the exponent, as it associates to the right.
+fn binary_op(kind: &TokenKind) -> Option<BinaryOp> {
+    let op = match kind {
+        TokenKind::Ident(word) => match word.as_str() {
+            "or" => BinaryOp::Or,
+            "and" => BinaryOp::And,
+            "xor" => BinaryOp::BitXor,
+            _ => return None,
+        },
+        TokenKind::DoubleEquals => BinaryOp::Eq,
+        TokenKind::BangEquals => BinaryOp::Ne,
+        TokenKind::Less => BinaryOp::Lt,
+        TokenKind::LessEquals => BinaryOp::Le,
+        TokenKind::Greater => BinaryOp::Gt,
+        TokenKind::GreaterEquals => BinaryOp::Ge,
+        TokenKind::Pipe => BinaryOp::BitOr,
+        TokenKind::Ampersand => BinaryOp::BitAnd,
+        TokenKind::ShiftLeft => BinaryOp::Shl,
+        TokenKind::ShiftRight => BinaryOp::Shr,
+        TokenKind::Plus => BinaryOp::Add,
+        TokenKind::Minus => BinaryOp::Sub,
+        TokenKind::Asterisk => BinaryOp::Mul,
+        TokenKind::Slash => BinaryOp::Div,
+        TokenKind::Percent => BinaryOp::Rem,
+        _ => return None,
+    };
+    Some(op)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    index: usize,
+    depth: usize,
+    operators: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self {
+            tokens,
+            index: 0,
+            depth: 0,
+            operators: 0,
+        }
+    }
+
+    /// Steps over a binary operator or `to`, failing on it when that makes
+    /// more than `MAX_OPERATORS`.
+    fn operator(&mut self) -> Result<(), CalcError> {
+        if self.operators >= MAX_OPERATORS {
+            return Err(CalcError::TooLong {
+                span: self.tokens[self.index].span,
+            });
+        }
+        self.operators += 1;
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Parses one nested level with `parse`, failing at `span` when that
+    /// goes past `MAX_NESTING`.
+    fn nested<T>(
+        &mut self,
+        span: Span,
+        parse: impl FnOnce(&mut Self) -> Result<T, CalcError>,
+    ) -> Result<T, CalcError> {
+        if self.depth >= MAX_NESTING {
+            return Err(CalcError::TooDeep { span });
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Result<&Token, CalcError> {
+        let token = self.tokens.get(self.index).ok_or_else(|| {
+            let end = self.tokens.last().map_or(0, |token| token.span.end);
+            CalcError::UnexpectedEnd {
+                span: Span::new(end, end),
+            }
+        })?;
+        self.index += 1;
+        Ok(token)
+    }
+
+    fn expression_to_end(&mut self) -> Result<Expr, CalcError> {
+        let expr = self.expression()?;
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::RParen => {
+                Err(CalcError::UnbalancedParen { span: token.span })
+            }
+            Some(token) => Err(unexpected(token)),
+            None => Ok(expr),
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), CalcError> {
+        let token = self.next()?;
+        if token.kind == kind {
+            Ok(())
+        } else {
+            Err(unexpected(token))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CalcError> {
+        let token = self.next()?;
+        match &token.kind {
+            TokenKind::Ident(name) => Ok(name.clone()),
+            _ => Err(unexpected(token)),
+        }
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|token| &token.kind)
+    }
+
+    fn at_word(&self, words: &[&str]) -> bool {
+        matches!(self.peek_kind(), Some(TokenKind::Ident(word)) if words.contains(&word.as_str()))
+    }
+
+    fn expression(&mut self) -> Result<Expr, CalcError> {
+        self.binary(0)
+    }
+
+    // Binary operators are read by precedence climbing on the precedences
+    // `Display` prints by: an operand, then each operator binding at least
+    // as tightly as `min`, whose right-hand side is whatever follows that
+    // binds tighter still. A run such as `1 + 2 + 3` is a loop, not a
+    // recursion. Logical operators bind loosest and `not` sits between them
+    // and the comparisons, as in Python, so `not a < b` negates the
+    // comparison. Conversion binds looser than arithmetic, so
+    // `1 h + 30 min to min` converts the sum, but tighter than comparisons.
+    fn binary(&mut self, min: u8) -> Result<Expr, CalcError> {
+        let mut expr = if min <= NOT_PRECEDENCE && self.at_word(&["not"]) {
+            self.not()?
+        } else {
+            self.unary()?
+        };
+        // Only `to` and looser operators may follow a conversion.
+        let mut ceiling = u8::MAX;
+        loop {
+            let binds = |precedence: u8| (min..=ceiling).contains(&precedence);
+            if let Some(op) = self
+                .peek_kind()
+                .and_then(binary_op)
+                .filter(|op| binds(op.precedence()))
+            {
+                self.operator()?;
+                let rhs = self.binary(op.precedence() + 1)?;
+                expr = Expr::binary(op, expr, rhs);
+            } else if binds(CONVERT_PRECEDENCE) && self.at_word(&["to", "in"]) {
+                self.operator()?;
+                let (unit, unit_span) = self.target_unit()?;
+                let span = expr.span.to(unit_span);
+                expr = Expr::new(
+                    ExprKind::Convert {
+                        expr: Box::new(expr),
+                        unit,
+                    },
+                    span,
+                );
+                ceiling = CONVERT_PRECEDENCE;
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr, CalcError> {
+        let op_span = self.next()?.span;
+        let operand = self.nested(op_span, |parser| parser.binary(NOT_PRECEDENCE))?;
+        Ok(Expr::unary(UnaryOp::Not, operand, op_span))
+    }
+
+    fn target_unit(&mut self) -> Result<(Unit, Span), CalcError> {
+        if let Some((unit, end)) = self.scan_unit(self.index, false) {
+            let span = self.tokens[self.index].span.to(self.tokens[end - 1].span);
+            self.index = end;
+            return Ok((unit, span));
+        }
+        let token = self.next()?;
+        match &token.kind {
+            TokenKind::Ident(name) => Err(CalcError::UnknownUnit {
+                name: name.clone(),
+                span: token.span,
+            }),
+            _ => Err(unexpected(token)),
+        }
+    }
+
+    // A unit written right after a number or a parenthesized expression
+    // belongs to it. Its `*`, `/` and `^` must touch their operands, so
+    // `200 MB/s` is one rate while `10 m / s` divides by the slot `s`.
+    fn unit_suffix(&mut self, expr: Expr) -> Expr {
+        match self.scan_unit(self.index, true) {
+            Some((unit, end)) => {
+                let span = expr.span.to(self.tokens[end - 1].span);
+                self.index = end;
+                Expr::new(
+                    ExprKind::Quantity {
+                        value: Box::new(expr),
+                        unit,
+                    },
+                    span,
+                )
+            }
+            None => expr,
+        }
+    }
+
+    fn kind_at(&self, index: usize) -> Option<&TokenKind> {
+        self.tokens.get(index).map(|token| &token.kind)
+    }
+
+    fn touches_previous(&self, index: usize) -> bool {
+        index > 0
+            && self
+                .tokens
+                .get(index)
+                .is_some_and(|token| token.span.start == self.tokens[index - 1].span.end)
+    }
+
+    /// Looks for a unit such as `km`, `m/s^2` or `GiB` starting at `at`
+    /// without consuming anything, returning it and the index just past it.
+    fn scan_unit(&self, at: usize, compact: bool) -> Option<(Unit, usize)> {
+        let (mut unit, mut end) = self.scan_unit_term(at, compact)?;
+        while let Some(op @ (TokenKind::Asterisk | TokenKind::Slash)) = self.kind_at(end) {
+            if compact && !(self.touches_previous(end) && self.touches_previous(end + 1)) {
+                break;
+            }
+            let Some((term, next)) = self.scan_unit_term(end + 1, compact) else {
+                break;
+            };
+            let term = if *op == TokenKind::Slash {
+                term.powi(-1)
+            } else {
+                Some(term)
+            };
+            // `km/m` and the like would need a scale factor, so they are left
+            // to ordinary arithmetic, as are exponents too large for a unit.
+            let Some((product, scale)) = term.and_then(|term| unit.mul(&term)) else {
+                break;
+            };
+            if !scale.is_one() {
+                break;
+            }
+            unit = product;
+            end = next;
+        }
+        Some((unit, end))
+    }
+
+    fn scan_unit_term(&self, at: usize, compact: bool) -> Option<(Unit, usize)> {
+        let Some(TokenKind::Ident(name)) = self.kind_at(at) else {
+            return None;
+        };
+        if self.kind_at(at + 1) == Some(&TokenKind::LParen) {
+            return None;
+        }
+        let unit = Unit::lookup(name)?;
+        if self.kind_at(at + 1) != Some(&TokenKind::Caret) {
+            return Some((unit, at + 1));
+        }
+        let (sign, digits_at) = match self.kind_at(at + 2) {
+            Some(TokenKind::Minus) => (-1, at + 3),
+            _ => (1, at + 2),
+        };
+        let exponent = match self.kind_at(digits_at) {
+            Some(TokenKind::Number(value)) => value.to_rational(),
+            _ => return Some((unit, at + 1)),
+        };
+        let touching = (at + 1..=digits_at).all(|index| self.touches_previous(index));
+        let power = exponent
+            .is_integer()
+            .then(|| exponent.to_integer().to_i32())
+            .flatten()
+            .filter(|_| touching || !compact)
+            .and_then(|n| unit.powi(sign * n));
+        match power {
+            Some(power) => Some((power, digits_at + 1)),
+            None => Some((unit, at + 1)),
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CalcError> {
+        let op = match self.peek_kind() {
+            Some(TokenKind::Minus) => UnaryOp::Neg,
+            Some(TokenKind::Plus) => UnaryOp::Plus,
+            Some(TokenKind::Tilde) => UnaryOp::BitNot,
+            _ => return self.power(),
+        };
+        let op_span = self.next()?.span;
+        let operand = self.nested(op_span, Self::unary)?;
+        Ok(Expr::unary(op, operand, op_span))
+    }
+
+    // The exponent is parsed as a unary so that `2 ^ -1` works and so that
+    // `2 ^ 3 ^ 2` nests to the right, while the base stays a primary which
+    // makes `-2 ^ 2` read as `-(2 ^ 2)`.
+    fn power(&mut self) -> Result<Expr, CalcError> {
+        let primary = self.primary()?;
+        let base = self.percent(primary);
+        if let Some(caret) = self
+            .peek()
+            .filter(|token| token.kind == TokenKind::Caret)
+            .map(|token| token.span)
+        {
+            self.index += 1;
+            let exponent = self.nested(caret, Self::unary)?;
+            return Ok(Expr::binary(BinaryOp::Pow, base, exponent));
+        }
+        Ok(base)
+    }
+
+    // A `%` is a percent sign unless an operand follows it, which makes it
+    // the remainder: `200 + 15% * 2` but `10 % 3`. A sign after it is read
+    // by where the `%` stands: written against its operand, as in `15% - 1`,
+    // it is a percent less one; spaced out, as in `7 % -3`, a remainder.
+    fn percent(&mut self, expr: Expr) -> Expr {
+        if self.peek_kind() != Some(&TokenKind::Percent) {
+            return expr;
+        }
+        let operand_follows = match self.kind_at(self.index + 1) {
+            Some(TokenKind::Ident(word)) => !INFIX_WORDS.contains(&word.as_str()),
+            Some(TokenKind::Plus | TokenKind::Minus) => !self.touches_previous(self.index),
+            Some(kind) => matches!(
+                kind,
+                TokenKind::Number(_)
+                    | TokenKind::Text(_)
+                    | TokenKind::History(_)
+                    | TokenKind::LParen
+                    | TokenKind::LBracket
+                    | TokenKind::Tilde
+            ),
+            None => false,
+        };
+        if operand_follows {
+            return expr;
+        }
+        let span = expr.span.to(self.tokens[self.index].span);
+        self.index += 1;
+        Expr::new(
+            ExprKind::Unary {
+                op: UnaryOp::Percent,
+                operand: Box::new(expr),
+            },
+            span,
+        )
+    }
+
+    fn primary(&mut self) -> Result<Expr, CalcError> {
+        let token = self.next()?.clone();
+        match &token.kind {
+            TokenKind::Number(value) => {
+                let number = Expr::new(ExprKind::Number(value.clone()), token.span);
+                Ok(self.unit_suffix(number))
+            }
+            TokenKind::Ident(name) if self.peek_kind() == Some(&TokenKind::LParen) => {
+                self.nested(token.span, |parser| parser.call(name.clone(), token.span))
+            }
+            TokenKind::Ident(name) => Ok(Expr::new(ExprKind::Variable(name.clone()), token.span)),
+            TokenKind::History(number) => Ok(Expr::new(ExprKind::History(*number), token.span)),
+            TokenKind::Text(text) => Ok(Expr::new(ExprKind::Text(text.clone()), token.span)),
+            TokenKind::LBracket => {
+                let list = self.nested(token.span, |parser| parser.list(token.span))?;
+                Ok(self.unit_suffix(list))
+            }
+            TokenKind::LParen => {
+                let mut expr = self.nested(token.span, Self::expression)?;
+                match self.peek() {
+                    Some(close) if close.kind == TokenKind::RParen => {
+                        expr.span = token.span.to(close.span);
+                        self.index += 1;
+                        Ok(self.unit_suffix(expr))
+                    }
+                    Some(other) => Err(unexpected(other)),
+                    None => Err(CalcError::UnbalancedParen { span: token.span }),
+                }
+            }
+            _ => Err(unexpected(&token)),
+        }
+    }
+
+    // `[a, b, c]`, whose elements may themselves be lists: the rows of a
+    // matrix.
+    fn list(&mut self, open_span: Span) -> Result<Expr, CalcError> {
+        let mut items = Vec::new();
+        if self.peek_kind() != Some(&TokenKind::RBracket) {
+            items.push(self.expression()?);
+            while self.peek_kind() == Some(&TokenKind::Comma) {
+                self.index += 1;
+                items.push(self.expression()?);
+            }
+        }
+        match self.peek() {
+            Some(close) if close.kind == TokenKind::RBracket => {
+                let span = open_span.to(close.span);
+                self.index += 1;
+                Ok(Expr::new(ExprKind::List(items), span))
+            }
+            Some(other) => Err(unexpected(other)),
+            None => Err(CalcError::UnbalancedParen { span: open_span }),
+        }
+    }
+
+    fn call(&mut self, name: String, name_span: Span) -> Result<Expr, CalcError> {
+        let open_span = self.next()?.span;
+        let mut args = Vec::new();
+        if self.peek_kind() != Some(&TokenKind::RParen) {
+            args.push(self.expression()?);
+            while self.peek_kind() == Some(&TokenKind::Comma) {
+                self.index += 1;
+                args.push(self.expression()?);
+            }
+        }
+        match self.peek() {
+            Some(close) if close.kind == TokenKind::RParen => {
+                let span = name_span.to(close.span);
+                self.index += 1;
+                Ok(Expr::new(ExprKind::Call { name, args }, span))
+            }
+            Some(other) => Err(unexpected(other)),
+            None => Err(CalcError::UnbalancedParen { span: open_span }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryOp;
+    use crate::number::Number;
+    use crate::token::tokenize;
+    use num_rational::BigRational;
+
+    fn parse_str(input: &str) -> Result<Expr, CalcError> {
+        parse(&tokenize(input).unwrap())
+    }
+
+    fn number(value: f64) -> Expr {
+        let value = Number::Rational(BigRational::from_float(value).unwrap());
+        Expr::new(ExprKind::Number(value), Span::new(0, 0))
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        let expected = Expr::binary(
+            BinaryOp::Sub,
+            Expr::binary(BinaryOp::Sub, number(1.0), number(2.0)),
+            Expr::binary(BinaryOp::Mul, number(3.0), number(4.0)),
+        );
+        assert_eq!(parse_str("1 - 2 - 3 * 4"), Ok(expected));
+
+        let expected = Expr::unary(
+            UnaryOp::Neg,
+            Expr::binary(
+                BinaryOp::Pow,
+                number(2.0),
+                Expr::binary(BinaryOp::Pow, number(3.0), number(2.0)),
+            ),
+            Span::new(0, 0),
+        );
+        assert_eq!(parse_str("-2 ^ 3 ^ 2"), Ok(expected));
+
+        let integer = |value| Expr::new(ExprKind::Number(Number::Integer(value)), Span::new(0, 0));
+        let expected = Expr::binary(
+            BinaryOp::BitOr,
+            Expr::binary(
+                BinaryOp::BitAnd,
+                integer(0xff),
+                Expr::binary(BinaryOp::Shl, number(1.0), number(4.0)),
+            ),
+            Expr::binary(
+                BinaryOp::BitXor,
+                number(1.0),
+                Expr::unary(UnaryOp::BitNot, number(2.0), Span::new(0, 0)),
+            ),
+        );
+        assert_eq!(parse_str("0xff & 1 << 4 | 1 xor ~2"), Ok(expected));
+
+        let variable =
+            |name: &str| Expr::new(ExprKind::Variable(name.to_string()), Span::new(0, 0));
+        let expected = Expr::binary(
+            BinaryOp::Or,
+            Expr::binary(
+                BinaryOp::And,
+                Expr::unary(
+                    UnaryOp::Not,
+                    Expr::binary(BinaryOp::Lt, variable("a"), number(1.0)),
+                    Span::new(0, 0),
+                ),
+                Expr::binary(
+                    BinaryOp::Eq,
+                    Expr::binary(BinaryOp::Add, variable("b"), number(1.0)),
+                    number(2.0),
+                ),
+            ),
+            variable("c"),
+        );
+        assert_eq!(parse_str("not a < 1 and b + 1 == 2 or c"), Ok(expected));
+    }
+
+    #[test]
+    fn test_calls() {
+        let call = |name: &str, args: Vec<Expr>| {
+            Expr::new(
+                ExprKind::Call {
+                    name: name.to_string(),
+                    args,
+                },
+                Span::new(0, 0),
+            )
+        };
+        assert_eq!(parse_str("pi()"), Ok(call("pi", vec![])));
+        assert_eq!(
+            parse_str("max(1, 2 * 3, sqrt(4))"),
+            Ok(call(
+                "max",
+                vec![
+                    number(1.0),
+                    Expr::binary(BinaryOp::Mul, number(2.0), number(3.0)),
+                    call("sqrt", vec![number(4.0)]),
+                ]
+            ))
+        );
+        assert_eq!(
+            parse_str("max(1, 2"),
+            Err(CalcError::UnbalancedParen {
+                span: Span::new(3, 4)
+            })
+        );
+        let list = |items| Expr::new(ExprKind::List(items), Span::new(0, 0));
+        assert_eq!(
+            parse_str("[1, [2, 3], []]"),
+            Ok(list(vec![
+                number(1.0),
+                list(vec![number(2.0), number(3.0)]),
+                list(vec![])
+            ]))
+        );
+        assert_eq!(
+            parse_str("[1, 2"),
+            Err(CalcError::UnbalancedParen {
+                span: Span::new(0, 1)
+            })
+        );
+        assert_eq!(
+            parse_str("max(1,)"),
+            Err(CalcError::UnexpectedToken {
+                found: ")".to_string(),
+                span: Span::new(6, 7)
+            })
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        let statement = |input: &str| parse_statement(&tokenize(input).unwrap());
+        assert_eq!(
+            statement("let x = 3 * 4"),
+            Ok(Statement::Let {
+                name: "x".to_string(),
+                value: Expr::binary(BinaryOp::Mul, number(3.0), number(4.0)),
+            })
+        );
+        assert_eq!(
+            statement("x = 3 * 4"),
+            Ok(Statement::Let {
+                name: "x".to_string(),
+                value: Expr::binary(BinaryOp::Mul, number(3.0), number(4.0)),
+            })
+        );
+        let variable =
+            |name: &str| Expr::new(ExprKind::Variable(name.to_string()), Span::new(0, 0));
+        assert_eq!(
+            statement("x == 3"),
+            Ok(Statement::Expr(Expr::binary(
+                BinaryOp::Eq,
+                variable("x"),
+                number(3.0)
+            )))
+        );
+        assert_eq!(
+            statement("fn area(w, h) = w * h"),
+            Ok(Statement::FnDef {
+                name: "area".to_string(),
+                function: UserFunction {
+                    params: vec!["w".to_string(), "h".to_string()],
+                    body: Expr::binary(BinaryOp::Mul, variable("w"), variable("h")),
+                },
+            })
+        );
+        assert_eq!(statement("let + 1").map(|_| ()), Ok(()));
+        assert_eq!(
+            statement("let x 1"),
+            Err(CalcError::UnexpectedToken {
+                found: "1".to_string(),
+                span: Span::new(6, 7)
+            })
+        );
+        assert_eq!(
+            statement("fn f(x, 1) = x"),
+            Err(CalcError::UnexpectedToken {
+                found: "1".to_string(),
+                span: Span::new(8, 9)
+            })
+        );
+        assert!(matches!(
+            statement("fn f(x) ="),
+            Err(CalcError::UnexpectedEnd { .. })
+        ));
+    }
+
+    #[test]
+    fn test_units() {
+        let unit = |name: &str| Unit::lookup(name).unwrap();
+        let quantity = |value: f64, unit: Unit| {
+            Expr::new(
+                ExprKind::Quantity {
+                    value: Box::new(number(value)),
+                    unit,
+                },
+                Span::new(0, 0),
+            )
+        };
+        let rate = unit("MB").mul(&unit("s").powi(-1).unwrap()).unwrap().0;
+        assert_eq!(
+            parse_str("3 GiB / 200 MB/s"),
+            Ok(Expr::binary(
+                BinaryOp::Div,
+                quantity(3.0, unit("GiB")),
+                quantity(200.0, rate)
+            ))
+        );
+        assert_eq!(
+            parse_str("4 m ^ 2"),
+            Ok(Expr::binary(
+                BinaryOp::Pow,
+                quantity(4.0, unit("m")),
+                number(2.0)
+            ))
+        );
+        assert_eq!(
+            parse_str("4 m^2"),
+            Ok(quantity(4.0, unit("m").powi(2).unwrap()))
+        );
+        assert_eq!(
+            parse_str("90 min in h"),
+            Ok(Expr::new(
+                ExprKind::Convert {
+                    expr: Box::new(quantity(90.0, unit("min"))),
+                    unit: unit("h"),
+                },
+                Span::new(0, 0)
+            ))
+        );
+        assert_eq!(
+            parse_str("2 m to parsec"),
+            Err(CalcError::UnknownUnit {
+                name: "parsec".to_string(),
+                span: Span::new(7, 13)
+            })
+        );
+        assert!(matches!(
+            parse_str("5 min(1, 2)"),
+            Err(CalcError::UnexpectedToken { .. })
+        ));
+        let compared = parse_str("1 h + 30 min to min < 2 h to min").unwrap();
+        assert!(matches!(
+            compared.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Lt,
+                ..
+            }
+        ));
+        assert_eq!(compared.to_string(), "1 h + 30 min to min < 2 h to min");
+        assert!(matches!(
+            parse_str("1 km to m + 1"),
+            Err(CalcError::UnexpectedToken { .. })
+        ));
+    }
+
+    #[test]
+    fn test_memory_statements() {
+        let statement = |input: &str| parse_statement(&tokenize(input).unwrap());
+        assert_eq!(
+            statement("memA+ 3 * 4"),
+            Ok(Statement::Memory {
+                name: "A".to_string(),
+                op: MemoryOp::Add,
+                operand: Some(Expr::binary(BinaryOp::Mul, number(3.0), number(4.0))),
+                span: Span::new(0, 5),
+            })
+        );
+        assert_eq!(
+            statement("memA<>B"),
+            Ok(Statement::Memory {
+                name: "A".to_string(),
+                op: MemoryOp::Swap("B".to_string()),
+                operand: None,
+                span: Span::new(0, 7),
+            })
+        );
+        assert_eq!(
+            statement("memA? 1"),
+            Err(CalcError::UnexpectedToken {
+                found: "1".to_string(),
+                span: Span::new(6, 7)
+            })
+        );
+        assert!(statement("memA= )").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_str("1 +"),
+            Err(CalcError::UnexpectedEnd {
+                span: Span::new(3, 3)
+            })
+        );
+        assert_eq!(
+            parse_str("(1 + 2"),
+            Err(CalcError::UnbalancedParen {
+                span: Span::new(0, 1)
+            })
+        );
+        assert_eq!(
+            parse_str("1 + 2)"),
+            Err(CalcError::UnbalancedParen {
+                span: Span::new(5, 6)
+            })
+        );
+        assert_eq!(
+            parse_str("1 2"),
+            Err(CalcError::UnexpectedToken {
+                found: "2".to_string(),
+                span: Span::new(2, 3)
+            })
+        );
+        assert_eq!(
+            parse_str("1 / * 2"),
+            Err(CalcError::UnexpectedToken {
+                found: "*".to_string(),
+                span: Span::new(4, 5)
+            })
+        );
+        assert_eq!(
+            parse_str(""),
+            Err(CalcError::UnexpectedEnd {
+                span: Span::new(0, 0)
+            })
+        );
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let too_deep = |at: usize| {
+            Err(CalcError::TooDeep {
+                span: Span::new(at, at + 1),
+            })
+        };
+        let parens = |n: usize| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse_str(&parens(MAX_NESTING)).is_ok());
+        assert_eq!(parse_str(&parens(MAX_NESTING + 1)), too_deep(MAX_NESTING));
+        assert_eq!(parse_str(&"(".repeat(100_000)), too_deep(MAX_NESTING));
+        assert_eq!(parse_str(&"[".repeat(100_000)), too_deep(MAX_NESTING));
+        assert_eq!(parse_str(&"f(".repeat(100_000)), too_deep(2 * MAX_NESTING));
+        assert_eq!(
+            parse_str(&format!("{}1", "-".repeat(100_000))),
+            too_deep(MAX_NESTING)
+        );
+        assert_eq!(
+            parse_str(&format!("{}1", "2^".repeat(100_000))),
+            too_deep(2 * MAX_NESTING + 1)
+        );
+        let sum = |n: usize| {
+            format!(
+                "0{}",
+                (1..n).map(|i| format!(" + {}", i)).collect::<String>()
+            )
+        };
+        assert!(parse_str(&sum(70)).is_ok());
+        let flat = parse_str(&sum(500)).unwrap();
+        assert_eq!(flat.to_string(), sum(500));
+        assert!(parse_str(&format!("({})", sum(500))).is_ok());
+        assert!(parse_str(&sum(MAX_OPERATORS + 1)).is_ok());
+        assert!(matches!(
+            parse_str(&sum(100_000)),
+            Err(CalcError::TooLong { .. })
+        ));
+        let conversions = format!("1 m{}", " to m".repeat(MAX_OPERATORS + 1));
+        assert!(matches!(
+            parse_str(&conversions),
+            Err(CalcError::TooLong { .. })
+        ));
+        assert!(parse_str(&format!("{}x", "not ".repeat(100_000))).is_err());
+    }
+}
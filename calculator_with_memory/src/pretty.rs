@@ -0,0 +1,325 @@
+//! Writes an expression back out as text. The canonical style is the
+//! expression's `Display`: infix with only the parentheses the parser needs,
+//! so that parsing it gives back the same expression and saved formulas can
+//! be compared as text. Literals are written in decimal, so `0xff` comes
+//! back as the literal `255`: the same value, though no longer an integer
+//! literal.
+
+use crate::expression::{Expr, ExprKind, UnaryOp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// `(1 + 2) * 3`
+    Canonical,
+    /// `((1 + 2) * 3)`: every operation in parentheses.
+    Parenthesized,
+    /// `1 2 + 3 *`: operands first, each operator after them. Calls and
+    /// lists end in their name and length, as `max/2` and `list/3`.
+    Rpn,
+}
+
+/// ```
+/// use calculator_with_memory::parser::parse;
+/// use calculator_with_memory::pretty::{pretty, Style};
+/// use calculator_with_memory::token::tokenize;
+///
+/// let expr = parse(&tokenize("-(1+2)*3 ^ 2").unwrap()).unwrap();
+/// assert_eq!(pretty(&expr, Style::Canonical), "-(1 + 2) * 3 ^ 2");
+/// assert_eq!(pretty(&expr, Style::Parenthesized), "((-(1 + 2)) * (3 ^ 2))");
+/// assert_eq!(pretty(&expr, Style::Rpn), "1 2 + neg 3 2 ^ *");
+/// ```
+pub fn pretty(expr: &Expr, style: Style) -> String {
+    match style {
+        Style::Canonical => expr.to_string(),
+        Style::Parenthesized => parenthesized(expr),
+        Style::Rpn => {
+            let mut words = Vec::new();
+            rpn(expr, &mut words);
+            words.join(" ")
+        }
+    }
+}
+
+fn parenthesized(expr: &Expr) -> String {
+    let join = |items: &[Expr]| {
+        let items: Vec<String> = items.iter().map(parenthesized).collect();
+        items.join(", ")
+    };
+    match &expr.kind {
+        ExprKind::Number(value) => {
+            let text = value.to_string();
+            if value.is_negative() || text.contains('/') {
+                format!("({})", text)
+            } else {
+                text
+            }
+        }
+        ExprKind::Variable(_) | ExprKind::Text(_) | ExprKind::History(_) => expr.to_string(),
+        ExprKind::Call { name, args } => format!("{}({})", name, join(args)),
+        ExprKind::List(items) => format!("[{}]", join(items)),
+        // A unit only attaches to a number, a list or a closing parenthesis.
+        ExprKind::Quantity { value, unit } => match value.kind {
+            ExprKind::Variable(_)
+            | ExprKind::Text(_)
+            | ExprKind::History(_)
+            | ExprKind::Call { .. } => format!("(({}) {})", value, unit),
+            _ => format!("({} {})", parenthesized(value), unit),
+        },
+        ExprKind::Convert { expr, unit } => format!("({} to {})", parenthesized(expr), unit),
+        ExprKind::Unary {
+            op: UnaryOp::Percent,
+            operand,
+        } => format!("({}%)", parenthesized(operand)),
+        ExprKind::Unary {
+            op: UnaryOp::Not,
+            operand,
+        } => format!("(not {})", parenthesized(operand)),
+        ExprKind::Unary { op, operand } => format!("({}{})", op.symbol(), parenthesized(operand)),
+        ExprKind::Binary { op, lhs, rhs } => format!(
+            "({} {} {})",
+            parenthesized(lhs),
+            op.symbol(),
+            parenthesized(rhs)
+        ),
+    }
+}
+
+// `+`, `-` and `%` are both unary and binary in infix, so in RPN the unary
+// ones get names of their own.
+fn rpn(expr: &Expr, words: &mut Vec<String>) {
+    match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Variable(_) | ExprKind::Text(_) | ExprKind::History(_) => {
+            words.push(expr.to_string())
+        }
+        ExprKind::Call { name, args } => {
+            args.iter().for_each(|arg| rpn(arg, words));
+            words.push(format!("{}/{}", name, args.len()));
+        }
+        ExprKind::List(items) => {
+            items.iter().for_each(|item| rpn(item, words));
+            words.push(format!("list/{}", items.len()));
+        }
+        ExprKind::Quantity { value, unit } => {
+            rpn(value, words);
+            words.push(unit.to_string());
+        }
+        ExprKind::Convert { expr, unit } => {
+            rpn(expr, words);
+            words.push(format!("to:{}", unit));
+        }
+        ExprKind::Unary { op, operand } => {
+            rpn(operand, words);
+            let word = match op {
+                UnaryOp::Neg => "neg",
+                UnaryOp::Plus => "pos",
+                UnaryOp::Percent => "pct",
+                UnaryOp::BitNot | UnaryOp::Not => op.symbol(),
+            };
+            words.push(word.to_string());
+        }
+        ExprKind::Binary { op, lhs, rhs } => {
+            rpn(lhs, words);
+            rpn(rhs, words);
+            words.push(op.symbol().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::BinaryOp;
+    use crate::number::{parse_literal, Number};
+    use crate::parser::parse;
+    use crate::token::{tokenize, Span};
+    use crate::units::Unit;
+    use proptest::prelude::*;
+
+    fn parse_str(input: &str) -> Expr {
+        parse(&tokenize(input).unwrap()).unwrap()
+    }
+
+    fn styles(input: &str) -> [String; 3] {
+        let expr = parse_str(input);
+        [Style::Canonical, Style::Parenthesized, Style::Rpn].map(|style| pretty(&expr, style))
+    }
+
+    #[test]
+    fn test_styles() {
+        assert_eq!(
+            styles("1 - (2 - 3) - 4"),
+            ["1 - (2 - 3) - 4", "((1 - (2 - 3)) - 4)", "1 2 3 - - 4 -"]
+        );
+        assert_eq!(
+            styles("not a < 1 and b"),
+            [
+                "not a < 1 and b",
+                "((not (a < 1)) and b)",
+                "a 1 < not b and"
+            ]
+        );
+        assert_eq!(
+            styles("max(x, 2 ^ -1, [1, 2])"),
+            [
+                "max(x, 2 ^ -1, [1, 2])",
+                "max(x, (2 ^ (-1)), [1, 2])",
+                "x 2 1 neg ^ 1 2 list/2 max/3"
+            ]
+        );
+        assert_eq!(
+            styles("200 + 15% - x % 3"),
+            [
+                "200 + 15% - x % 3",
+                "((200 + (15%)) - (x % 3))",
+                "200 15 pct + x 3 % -"
+            ]
+        );
+        assert_eq!(
+            styles("(x) km + 2 m to cm"),
+            [
+                "(x) km + 2 m to cm",
+                "((((x) km) + (2 m)) to cm)",
+                "x km 2 m + to:cm"
+            ]
+        );
+        let masked = parse_str("0xff & x");
+        assert_eq!(pretty(&masked, Style::Canonical), "255 & x");
+        assert_ne!(parse_str("255 & x"), masked);
+        assert_eq!(exact(&parse_str("255 & x")), exact(&masked));
+    }
+
+    // Integer literals such as `0xff` are written back in decimal and read
+    // as rationals, so the round trips compare with every exact literal
+    // turned into a rational.
+    fn exact(expr: &Expr) -> Expr {
+        let boxed = |expr: &Expr| Box::new(exact(expr));
+        let kind = match &expr.kind {
+            ExprKind::Number(value @ Number::Integer(_)) => {
+                ExprKind::Number(Number::Rational(value.to_rational()))
+            }
+            ExprKind::Unary { op, operand } => ExprKind::Unary {
+                op: *op,
+                operand: boxed(operand),
+            },
+            ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+                op: *op,
+                lhs: boxed(lhs),
+                rhs: boxed(rhs),
+            },
+            ExprKind::Call { name, args } => ExprKind::Call {
+                name: name.clone(),
+                args: args.iter().map(exact).collect(),
+            },
+            ExprKind::Quantity { value, unit } => ExprKind::Quantity {
+                value: boxed(value),
+                unit: unit.clone(),
+            },
+            ExprKind::Convert { expr, unit } => ExprKind::Convert {
+                expr: boxed(expr),
+                unit: unit.clone(),
+            },
+            ExprKind::List(items) => ExprKind::List(items.iter().map(exact).collect()),
+            kind => kind.clone(),
+        };
+        Expr::new(kind, expr.span)
+    }
+
+    fn leaf() -> impl Strategy<Value = ExprKind> {
+        prop_oneof![
+            "[0-9]{1,3}(\\.[0-9]{1,2})?".prop_map(|text| {
+                ExprKind::Number(Number::Rational(parse_literal(&text).unwrap()))
+            }),
+            (0..=i64::MAX).prop_map(|n| ExprKind::Number(Number::Integer(n))),
+            prop::sample::select(vec!["x", "y", "rate", "ans"])
+                .prop_map(|name| ExprKind::Variable(name.to_string())),
+            (1usize..10).prop_map(ExprKind::History),
+            "[a-z .]{0,4}".prop_map(ExprKind::Text),
+        ]
+    }
+
+    fn expr() -> impl Strategy<Value = Expr> {
+        let unary = [
+            UnaryOp::Neg,
+            UnaryOp::Plus,
+            UnaryOp::BitNot,
+            UnaryOp::Not,
+            UnaryOp::Percent,
+        ];
+        let binary = [
+            BinaryOp::Or,
+            BinaryOp::And,
+            BinaryOp::Eq,
+            BinaryOp::Ne,
+            BinaryOp::Lt,
+            BinaryOp::Le,
+            BinaryOp::Gt,
+            BinaryOp::Ge,
+            BinaryOp::BitOr,
+            BinaryOp::BitXor,
+            BinaryOp::BitAnd,
+            BinaryOp::Shl,
+            BinaryOp::Shr,
+            BinaryOp::Add,
+            BinaryOp::Sub,
+            BinaryOp::Mul,
+            BinaryOp::Div,
+            BinaryOp::Rem,
+            BinaryOp::Pow,
+        ];
+        let unit = prop::sample::select(vec!["km", "s", "h", "GiB"])
+            .prop_map(|name| Unit::lookup(name).unwrap());
+        let at = |kind| Expr::new(kind, Span::new(0, 0));
+        leaf().prop_map(at).prop_recursive(5, 48, 3, move |inner| {
+            prop_oneof![
+                (prop::sample::select(unary.to_vec()), inner.clone()).prop_map(
+                    move |(op, operand)| at(ExprKind::Unary {
+                        op,
+                        operand: Box::new(operand),
+                    })
+                ),
+                (
+                    prop::sample::select(binary.to_vec()),
+                    inner.clone(),
+                    inner.clone()
+                )
+                    .prop_map(|(op, lhs, rhs)| Expr::binary(op, lhs, rhs)),
+                (
+                    prop::sample::select(vec!["f", "max", "sqrt"]),
+                    prop::collection::vec(inner.clone(), 0..3)
+                )
+                    .prop_map(move |(name, args)| at(ExprKind::Call {
+                        name: name.to_string(),
+                        args,
+                    })),
+                prop::collection::vec(inner.clone(), 0..3)
+                    .prop_map(move |items| at(ExprKind::List(items))),
+                (inner.clone(), unit.clone()).prop_map(move |(value, unit)| at(
+                    ExprKind::Quantity {
+                        value: Box::new(value),
+                        unit,
+                    }
+                )),
+                (inner, unit.clone()).prop_map(move |(expr, unit)| at(ExprKind::Convert {
+                    expr: Box::new(expr),
+                    unit,
+                })),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_canonical_form_parses_back(expr in expr()) {
+            let text = pretty(&expr, Style::Canonical);
+            let parsed = tokenize(&text).and_then(|tokens| parse(&tokens));
+            prop_assert_eq!(parsed.ok().as_ref().map(exact), Some(exact(&expr)), "canonical form {}", text);
+        }
+
+        #[test]
+        fn test_parenthesized_form_parses_back(expr in expr()) {
+            let text = pretty(&expr, Style::Parenthesized);
+            let parsed = tokenize(&text).and_then(|tokens| parse(&tokens));
+            prop_assert_eq!(parsed.ok().as_ref().map(exact), Some(exact(&expr)), "parenthesized form {}", text);
+        }
+    }
+}
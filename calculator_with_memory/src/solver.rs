@@ -0,0 +1,258 @@
+//! Numeric root finding for `solve(expr, x, guess)`. The functions here see
+//! the expression as `f(x)`, which is `None` wherever it is undefined.
+
+/// When a root search stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolverSettings {
+    /// A root is accepted once it is known to within this much, relative
+    /// to its size when that is above one.
+    pub tolerance: f64,
+    /// The most steps each phase of the search may take.
+    pub max_iterations: u32,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            tolerance: 1e-12,
+            max_iterations: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SolveError {
+    /// The function is undefined at the starting guess.
+    UndefinedAtGuess,
+    /// No sign change was found and Newton's method ran into a flat spot or
+    /// left the function's domain.
+    NoRoot,
+    /// The sign changes across a pole or jump rather than a root.
+    Discontinuity(f64),
+    /// The iteration cap was reached without settling on a root.
+    NotConverged(u32),
+}
+
+impl SolveError {
+    pub fn message(&self) -> String {
+        match self {
+            SolveError::UndefinedAtGuess => "the expression is undefined at the guess".to_string(),
+            SolveError::NoRoot => "no root found near the guess".to_string(),
+            SolveError::Discontinuity(at) => {
+                format!(
+                    "the sign changes at a discontinuity near {}, not a root",
+                    at
+                )
+            }
+            SolveError::NotConverged(iterations) => {
+                format!("no convergence after {} iterations", iterations)
+            }
+        }
+    }
+}
+
+/// Finds `x` with `f(x) = 0` near `guess`. The search first steps outward
+/// from the guess looking for a sign change and narrows it down; when there
+/// is none, as at a double root, it runs Newton's method from the guess
+/// using `slope` for the derivative, or secant steps without one.
+pub fn find_root(
+    f: &mut dyn FnMut(f64) -> Option<f64>,
+    slope: Option<&mut dyn FnMut(f64) -> Option<f64>>,
+    guess: f64,
+    settings: &SolverSettings,
+) -> Result<f64, SolveError> {
+    let at_guess = f(guess).ok_or(SolveError::UndefinedAtGuess)?;
+    if at_guess == 0.0 {
+        return Ok(guess);
+    }
+    match bracket(f, guess, at_guess, settings) {
+        Some((a, b)) => refine(f, a, b, settings),
+        None => newton(f, slope, guess, settings),
+    }
+}
+
+fn converged(step: f64, x: f64, settings: &SolverSettings) -> bool {
+    step.abs() <= settings.tolerance * x.abs().max(1.0)
+}
+
+type Point = (f64, f64);
+
+// Steps grow geometrically on both sides of the guess, so nearby roots are
+// found first while distant ones are still reached. Points where the
+// function is undefined are stepped over, and so are exact zeros, which far
+// out are more likely underflow than roots; a genuine one still shows up as
+// a sign change a step later.
+fn bracket(
+    f: &mut dyn FnMut(f64) -> Option<f64>,
+    guess: f64,
+    at_guess: f64,
+    settings: &SolverSettings,
+) -> Option<(Point, Point)> {
+    let mut step = 0.01 * guess.abs().max(1.0);
+    let mut sides = [(guess, at_guess), (guess, at_guess)];
+    for _ in 0..settings.max_iterations {
+        if !step.is_finite() {
+            break;
+        }
+        for (side, direction) in sides.iter_mut().zip([1.0, -1.0]) {
+            let x = guess + direction * step;
+            let Some(y) = f(x).filter(|y| y.is_finite() && *y != 0.0) else {
+                continue;
+            };
+            if y.signum() != side.1.signum() {
+                return Some((*side, (x, y)));
+            }
+            *side = (x, y);
+        }
+        step *= 1.6;
+    }
+    None
+}
+
+// Regula falsi keeps the root bracketed; whenever a step fails to halve the
+// bracket, the next one bisects, so the bracket always closes.
+fn refine(
+    f: &mut dyn FnMut(f64) -> Option<f64>,
+    (mut a, mut fa): Point,
+    (mut b, mut fb): Point,
+    settings: &SolverSettings,
+) -> Result<f64, SolveError> {
+    let edge = fa.abs().max(fb.abs());
+    let mut bisect = false;
+    for _ in 0..settings.max_iterations {
+        let width = (b - a).abs();
+        let midpoint = a + (b - a) / 2.0;
+        let falsi = (a * fb - b * fa) / (fb - fa);
+        let x = if bisect || !(a.min(b) < falsi && falsi < a.max(b)) {
+            midpoint
+        } else {
+            falsi
+        };
+        let y = f(x).ok_or(SolveError::NoRoot)?;
+        if y == 0.0 {
+            return Ok(x);
+        }
+        if y.signum() == fa.signum() {
+            (a, fa) = (x, y);
+        } else {
+            (b, fb) = (x, y);
+        }
+        if converged(b - a, x, settings) {
+            let (x, y) = if fa.abs() < fb.abs() {
+                (a, fa)
+            } else {
+                (b, fb)
+            };
+            // Narrowing in on a pole makes the function grow, not shrink.
+            if y.abs() > edge {
+                return Err(SolveError::Discontinuity(x));
+            }
+            return Ok(x);
+        }
+        bisect = (b - a).abs() > width / 2.0;
+    }
+    Err(SolveError::NotConverged(settings.max_iterations))
+}
+
+fn newton(
+    f: &mut dyn FnMut(f64) -> Option<f64>,
+    mut slope: Option<&mut dyn FnMut(f64) -> Option<f64>>,
+    guess: f64,
+    settings: &SolverSettings,
+) -> Result<f64, SolveError> {
+    let mut x = guess;
+    let mut y = f(x).ok_or(SolveError::NoRoot)?;
+    let mut previous: Option<Point> = None;
+    for _ in 0..settings.max_iterations {
+        if y == 0.0 {
+            return Ok(x);
+        }
+        let gradient = match (&mut slope, previous) {
+            (Some(slope), _) => slope(x),
+            (None, Some((px, py))) => Some((y - py) / (x - px)),
+            (None, None) => {
+                let h = 1e-7 * x.abs().max(1.0);
+                f(x + h).map(|next| (next - y) / h)
+            }
+        };
+        let gradient = gradient
+            .filter(|gradient| gradient.is_finite() && *gradient != 0.0)
+            .ok_or(SolveError::NoRoot)?;
+        let step = y / gradient;
+        previous = Some((x, y));
+        x -= step;
+        if !x.is_finite() {
+            return Err(SolveError::NoRoot);
+        }
+        if converged(step, x, settings) {
+            return Ok(x);
+        }
+        y = f(x).ok_or(SolveError::NoRoot)?;
+    }
+    Err(SolveError::NotConverged(settings.max_iterations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(f: fn(f64) -> f64, guess: f64) -> Result<f64, SolveError> {
+        let mut f = |x: f64| Some(f(x)).filter(|y| !y.is_nan());
+        find_root(&mut f, None, guess, &SolverSettings::default())
+    }
+
+    #[test]
+    fn test_bracketed_roots() {
+        let root = solve(|x| x * x - 2.0, 1.0).unwrap();
+        assert!((root - 2f64.sqrt()).abs() < 1e-12);
+        let root = solve(|x| x.cos() - x, 0.0).unwrap();
+        assert!((root - 0.739_085_133_215_160_6).abs() < 1e-12);
+        // The nearest root to the guess, not the first one in some order.
+        let root = solve(|x| x.sin(), 3.0).unwrap();
+        assert!((root - std::f64::consts::PI).abs() < 1e-12);
+        let root = solve(|x| x.ln() - 1.0, 1.0).unwrap();
+        assert!((root - std::f64::consts::E).abs() < 1e-12);
+        assert_eq!(solve(|x| x - 4.0, 4.0), Ok(4.0));
+    }
+
+    #[test]
+    fn test_roots_without_a_sign_change() {
+        let root = solve(|x| (x - 3.0) * (x - 3.0), 1.0).unwrap();
+        assert!((root - 3.0).abs() < 1e-6);
+        let mut f = |x: f64| Some(x * x);
+        let mut slope = |x: f64| Some(2.0 * x);
+        let settings = SolverSettings::default();
+        let root = find_root(&mut f, Some(&mut slope), 5.0, &settings).unwrap();
+        assert!(root.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_failures() {
+        assert_eq!(
+            solve(|x| x * x + 1.0, 0.0),
+            Err(SolveError::NotConverged(200))
+        );
+        let mut f = |x: f64| Some(x * x + 1.0);
+        let mut slope = |x: f64| Some(2.0 * x);
+        let settings = SolverSettings::default();
+        assert_eq!(
+            find_root(&mut f, Some(&mut slope), 0.0, &settings),
+            Err(SolveError::NoRoot)
+        );
+        assert_eq!(solve(|x| x.exp(), 0.0), Err(SolveError::NotConverged(200)));
+        assert_eq!(solve(|x| x.sqrt(), -1.0), Err(SolveError::UndefinedAtGuess));
+        assert!(matches!(
+            solve(|x| 1.0 / x, 0.5),
+            Err(SolveError::Discontinuity(_))
+        ));
+        let settings = SolverSettings {
+            tolerance: 1e-15,
+            max_iterations: 3,
+        };
+        let mut f = |x: f64| Some(x.cos() - x);
+        assert_eq!(
+            find_root(&mut f, None, 0.0, &settings),
+            Err(SolveError::NotConverged(3))
+        );
+    }
+}
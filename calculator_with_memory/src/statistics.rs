@@ -0,0 +1,403 @@
+//! Statistics over a dataset: a list of numbers typed in, or read from a
+//! column of a CSV file with `load` and kept in a memory slot like any
+//! other value. Lists of quantities work too, and results keep the unit.
+
+use crate::matrix::{divisible, elements, function, sum, vector, ListFunction};
+use crate::number::{parse_literal, Number, NumericMode};
+use crate::value::{Value, ValueError};
+use std::cmp::Ordering;
+use std::path::Path;
+
+pub const STATISTICS: &[ListFunction] = &[
+    function("mean", 1, |args, mode| mean(&elements(&args[0])?, mode)),
+    function("median", 1, |args, mode| {
+        percentile(&elements(&args[0])?, &Number::Integer(50), mode)
+    }),
+    function("percentile", 2, |args, mode| {
+        percentile(&elements(&args[0])?, args[1].plain_number()?, mode)
+    }),
+    function("variance", 1, |args, mode| {
+        variance(&elements(&args[0])?, mode)
+    }),
+    function("stddev", 1, |args, mode| stddev(&elements(&args[0])?, mode)),
+    function("linreg", 2, |args, mode| {
+        linreg(vector(&args[0])?, vector(&args[1])?, mode)
+    }),
+];
+
+fn count(n: usize, mode: NumericMode) -> Value {
+    Value::Number(mode.from_f64(n as f64))
+}
+
+fn mean(values: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    if values.is_empty() {
+        return Err(ValueError::Domain("the list is empty"));
+    }
+    divisible(&sum(values, mode)?, mode).div(&count(values.len(), mode))
+}
+
+fn deviations(values: &[Value], mode: NumericMode) -> Result<Vec<Value>, ValueError> {
+    let mean = mean(values, mode)?;
+    values.iter().map(|value| value.sub(&mean)).collect()
+}
+
+/// The sample variance, which divides by one less than the number of
+/// values. It comes out in the square of the values' unit.
+fn variance(values: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    if values.len() < 2 {
+        return Err(ValueError::Domain("at least two values are needed"));
+    }
+    let squares = deviations(values, mode)?
+        .iter()
+        .map(|deviation| deviation.mul(deviation))
+        .collect::<Result<Vec<_>, _>>()?;
+    divisible(&sum(&squares, mode)?, mode).div(&count(values.len() - 1, mode))
+}
+
+/// The sample standard deviation, in the unit of the values. The variance
+/// is exact in the exact modes; only its square root is taken in floating
+/// point, and converted back to the kind of the variance.
+fn stddev(values: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    let variance = variance(values, mode)?;
+    let deviation = variance
+        .magnitude()?
+        .map_f64(f64::sqrt)
+        .map_err(ValueError::Domain)?;
+    Ok(Value::quantity(deviation, values[0].unit()))
+}
+
+// Every value is compared with the first before sorting, so that a unit
+// mismatch or a NaN is reported rather than giving an arbitrary order.
+fn sorted(values: &[Value]) -> Result<Vec<Value>, ValueError> {
+    let Some(first) = values.first() else {
+        return Err(ValueError::Domain("the list is empty"));
+    };
+    for value in values {
+        if first.compare(value)?.is_none() {
+            return Err(ValueError::Domain("the list contains NaN"));
+        }
+    }
+    let mut values = values.to_vec();
+    values.sort_by(|a, b| a.compare(b).ok().flatten().unwrap_or(Ordering::Equal));
+    Ok(values)
+}
+
+/// The value below which `p` percent of the values lie, interpolating
+/// linearly between the two nearest ones; the median is the 50th.
+fn percentile(values: &[Value], p: &Number, mode: NumericMode) -> Result<Value, ValueError> {
+    if !(0.0..=100.0).contains(&p.to_f64()) {
+        return Err(ValueError::Domain(
+            "the percentile must be between 0 and 100",
+        ));
+    }
+    let values = sorted(values)?;
+    let p = divisible(&Value::Number(p.clone()), mode);
+    let rank = p
+        .mul(&count(values.len() - 1, mode))?
+        .div(&count(100, mode))?;
+    let below = rank.magnitude()?.floor();
+    let index = below.to_i64().unwrap_or(0) as usize;
+    let fraction = rank.sub(&Value::Number(below))?;
+    if fraction.is_zero() {
+        return Ok(values[index].clone());
+    }
+    let step = values[index + 1].sub(&values[index])?;
+    values[index].add(&step.mul(&fraction)?)
+}
+
+/// The least-squares line through the points `(xs[i], ys[i])`, as the
+/// list `[slope, intercept]`.
+fn linreg(xs: &[Value], ys: &[Value], mode: NumericMode) -> Result<Value, ValueError> {
+    if xs.len() != ys.len() {
+        return Err(ValueError::Shape(
+            Value::List(xs.to_vec()).shape(),
+            Value::List(ys.to_vec()).shape(),
+        ));
+    }
+    if xs.len() < 2 {
+        return Err(ValueError::Domain("at least two points are needed"));
+    }
+    let (dx, dy) = (deviations(xs, mode)?, deviations(ys, mode)?);
+    let products = |a: &[Value], b: &[Value]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.mul(b))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let spread = sum(&products(&dx, &dx)?, mode)?;
+    if spread.is_zero() {
+        return Err(ValueError::Domain("the x values are all the same"));
+    }
+    let slope = sum(&products(&dx, &dy)?, mode)?.div(&spread)?;
+    let intercept = mean(ys, mode)?.sub(&slope.mul(&mean(xs, mode)?)?)?;
+    Ok(Value::List(vec![slope, intercept]))
+}
+
+/// Which column of a CSV file `load` reads.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    /// The column with this heading in the first row.
+    Name(String),
+    /// The column at this position, counting from 1.
+    Number(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataError {
+    /// The file could not be read or is not valid CSV.
+    Read(String),
+    NoColumn(Column),
+    NotANumber {
+        line: u64,
+        text: String,
+    },
+    /// Every cell of the column is empty.
+    NoData,
+}
+
+impl DataError {
+    pub fn message(&self) -> String {
+        match self {
+            DataError::Read(message) => message.clone(),
+            DataError::NoColumn(Column::Name(name)) => format!("no column is headed '{}'", name),
+            DataError::NoColumn(Column::Number(number)) => format!("there is no column {}", number),
+            DataError::NotANumber { line, text } => {
+                format!("line {}: '{}' is not a number", line, text)
+            }
+            DataError::NoData => "the column holds no numbers".to_string(),
+        }
+    }
+}
+
+fn parse_cell(text: &str) -> Option<Number> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = parse_literal(digits)?;
+    Some(Number::Rational(if negative { -value } else { value }))
+}
+
+/// Reads the numbers in one column of a CSV file. Empty cells are skipped,
+/// as missing data. A column picked by position still skips a first row
+/// that is not a number, taking it for the heading.
+pub fn load_column(
+    path: &Path,
+    column: &Column,
+    mode: NumericMode,
+) -> Result<Vec<Value>, DataError> {
+    let read = |error: csv::Error| DataError::Read(error.to_string());
+    let records = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(read)?
+        .into_records()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read)?;
+    let no_column = || DataError::NoColumn(column.clone());
+    let (index, rows) = match column {
+        Column::Name(name) => {
+            let heading = records.first().ok_or_else(no_column)?;
+            let index = heading
+                .iter()
+                .position(|cell| cell == name)
+                .ok_or_else(no_column)?;
+            (index, &records[1..])
+        }
+        Column::Number(number) => {
+            let index = number.checked_sub(1).ok_or_else(no_column)?;
+            if records.iter().all(|record| record.get(index).is_none()) {
+                return Err(no_column());
+            }
+            let heading = records[0].get(index).unwrap_or("");
+            let skip = usize::from(!heading.is_empty() && parse_cell(heading).is_none());
+            (index, &records[skip..])
+        }
+    };
+    let mut values = Vec::new();
+    for record in rows {
+        let cell = record.get(index).unwrap_or("");
+        if cell.is_empty() {
+            continue;
+        }
+        let number = parse_cell(cell).ok_or_else(|| DataError::NotANumber {
+            line: record.position().map_or(0, |position| position.line()),
+            text: cell.to_string(),
+        })?;
+        values.push(Value::Number(mode.convert(&number)));
+    }
+    if values.is_empty() {
+        return Err(DataError::NoData);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::eval_expression;
+    use crate::memory::Memory;
+    use crate::token::tokenize;
+    use std::path::PathBuf;
+
+    fn eval(input: &str, mode: NumericMode) -> Result<Value, crate::error::CalcError> {
+        eval_expression(&tokenize(input).unwrap(), &Memory::with_mode(mode))
+    }
+
+    fn show(input: &str) -> String {
+        eval(input, NumericMode::Float).unwrap().to_string()
+    }
+
+    fn error(input: &str) -> String {
+        eval(input, NumericMode::Float).unwrap_err().to_string()
+    }
+
+    // Each test writes its own file so that they can run in parallel.
+    fn csv_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "calculator_statistics_{}_{}.csv",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_summaries() {
+        assert_eq!(show("mean([1, 2, 3, 4])"), "2.5");
+        assert_eq!(show("median([3, 1, 2])"), "2");
+        assert_eq!(show("median([4, 1, 3, 2])"), "2.5");
+        assert_eq!(show("percentile([1, 2, 3, 4, 5], 90)"), "4.6");
+        assert_eq!(show("percentile([5, 1], 0)"), "1");
+        assert_eq!(show("percentile([5, 1], 100)"), "5");
+        assert_eq!(
+            show("variance([2, 4, 4, 4, 5, 5, 7, 9])"),
+            "4.571428571428571"
+        );
+        assert_eq!(
+            show("stddev([2, 4, 4, 4, 5, 5, 7, 9])"),
+            "2.138089935299395"
+        );
+        assert_eq!(show("stddev([1 m, 3 m])"), "1.4142135623730951 m");
+        assert_eq!(show("median([1 m, 300 cm, 2 m])"), "2 m");
+        assert_eq!(show("variance([1 s, 3 s])"), "2 s^2");
+        let median = eval("median([1, 2])", NumericMode::Integer).unwrap();
+        assert_eq!(median.to_string(), "1.5");
+        let quartile = eval("percentile([1, 2, 3, 4], 25)", NumericMode::Rational).unwrap();
+        assert_eq!(quartile.to_string(), "1.75");
+    }
+
+    #[test]
+    fn test_stddev_keeps_the_mode() {
+        let exact = eval("stddev([1, 3, 5])", NumericMode::Rational).unwrap();
+        assert!(matches!(exact, Value::Number(Number::Rational(_))));
+        assert_eq!(exact.to_string(), "2");
+        let decimal = eval("stddev([1 m, 3 m])", NumericMode::Decimal(4)).unwrap();
+        assert!(matches!(decimal.magnitude(), Ok(Number::Decimal(_))));
+        assert_eq!(decimal.to_string(), "1.4142 m");
+        let integer = eval("stddev([1, 3])", NumericMode::Integer).unwrap();
+        assert!(matches!(integer, Value::Number(Number::Float(_))));
+    }
+
+    #[test]
+    fn test_linreg() {
+        assert_eq!(show("linreg([1, 2, 3], [3, 5, 7])"), "[2, 1]");
+        let line = eval("linreg([0, 1, 2, 3], [1, 2, 2, 4])", NumericMode::Rational);
+        assert_eq!(line.unwrap().to_string(), "[0.9, 0.9]");
+        assert_eq!(show("linreg([0 s, 2 s], [1 m, 5 m])"), "[2 m/s, 1 m]");
+        assert_eq!(
+            error("linreg([1, 2], [1, 2, 3])"),
+            "Shape mismatch in 'linreg': list of 2 and list of 3"
+        );
+        assert_eq!(
+            error("linreg([2, 2], [1, 3])"),
+            "Domain error in 'linreg': the x values are all the same"
+        );
+    }
+
+    #[test]
+    fn test_summary_errors() {
+        assert_eq!(
+            error("mean([])"),
+            "Domain error in 'mean': the list is empty"
+        );
+        assert_eq!(
+            error("median([])"),
+            "Domain error in 'median': the list is empty"
+        );
+        assert_eq!(
+            error("variance([1])"),
+            "Domain error in 'variance': at least two values are needed"
+        );
+        assert_eq!(
+            error("percentile([1, 2], 101)"),
+            "Domain error in 'percentile': the percentile must be between 0 and 100"
+        );
+        assert_eq!(
+            error("median([1 m, 2 s])"),
+            "Incompatible dimensions: length and time"
+        );
+        let values = [Value::from(1.0), Value::from(f64::NAN)];
+        assert_eq!(
+            percentile(&values, &Number::Integer(50), NumericMode::Float),
+            Err(ValueError::Domain("the list contains NaN"))
+        );
+    }
+
+    #[test]
+    fn test_load_column() {
+        let path = csv_file("load", "day,latency ms\n1, 12.5\n2,\n3,-7\n4,20\n");
+        let load = |column: &Column| load_column(&path, column, NumericMode::Float);
+        let column = load(&Column::Name("latency ms".to_string())).unwrap();
+        assert_eq!(Value::List(column).to_string(), "[12.5, -7, 20]");
+        let column = load(&Column::Number(1)).unwrap();
+        assert_eq!(Value::List(column).to_string(), "[1, 2, 3, 4]");
+        assert_eq!(
+            load(&Column::Name("latency".to_string())),
+            Err(DataError::NoColumn(Column::Name("latency".to_string())))
+        );
+        assert_eq!(
+            load(&Column::Number(3)),
+            Err(DataError::NoColumn(Column::Number(3)))
+        );
+        std::fs::write(&path, "x\n1\nmany\n").unwrap();
+        assert_eq!(
+            load(&Column::Number(1)).map_err(|e| e.message()),
+            Err("line 3: 'many' is not a number".to_string())
+        );
+        std::fs::write(&path, "x\n\n").unwrap();
+        assert_eq!(load(&Column::Number(1)), Err(DataError::NoData));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(load(&Column::Number(1)), Err(DataError::Read(_))));
+    }
+
+    #[test]
+    fn test_load_in_expressions() {
+        let path = csv_file("expressions", "a,b\n1,2\n2,4\n3,9\n");
+        let path = path.display();
+        assert_eq!(show(&format!("load(\"{}\", \"b\")", path)), "[2, 4, 9]");
+        assert_eq!(show(&format!("median(load(\"{}\", 2))", path)), "4");
+        assert_eq!(
+            show(&format!(
+                "linreg(load(\"{0}\", \"a\"), load(\"{0}\", \"b\"))",
+                path
+            )),
+            "[3.5, -2]"
+        );
+        assert_eq!(
+            error(&format!("load(\"{}\", 1.5)", path)),
+            "Domain error in 'load': the column number must be a whole number from 1"
+        );
+        assert_eq!(
+            error("load(1, 1)"),
+            "Type mismatch in 'load': expected a string but found a number"
+        );
+        assert!(
+            error(&format!("load(\"{}\", \"c\")", path)).ends_with("': no column is headed 'c'")
+        );
+        std::fs::remove_file(path.to_string()).unwrap();
+    }
+}
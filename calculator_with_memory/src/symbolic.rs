@@ -0,0 +1,755 @@
+use crate::error::CalcError;
+use crate::expression::{on_eval_stack, BinaryOp, Budget, Expr, ExprKind, UnaryOp, MAX_CALL_DEPTH};
+use crate::memory::Memory;
+use crate::number::Number;
+use crate::token::Span;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+
+// Rewriting stops after this many passes even if a rule keeps firing.
+const MAX_SIMPLIFY_PASSES: usize = 32;
+
+fn constant(n: i64, span: Span) -> Expr {
+    let value = Number::Rational(BigRational::from_integer(BigInt::from(n)));
+    Expr::new(ExprKind::Number(value), span)
+}
+
+fn number(value: Number, span: Span) -> Expr {
+    Expr::new(ExprKind::Number(value), span)
+}
+
+fn as_number(expr: &Expr) -> Option<&Number> {
+    match &expr.kind {
+        ExprKind::Number(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn is_constant(expr: &Expr, n: i64) -> bool {
+    as_number(expr).is_some_and(|value| value.to_i64() == Some(n))
+}
+
+fn call(name: &str, arg: Expr) -> Expr {
+    let span = arg.span;
+    Expr::new(
+        ExprKind::Call {
+            name: name.to_string(),
+            args: vec![arg],
+        },
+        span,
+    )
+}
+
+fn neg(expr: Expr) -> Expr {
+    let span = expr.span;
+    Expr::unary(UnaryOp::Neg, expr, span)
+}
+
+fn add(lhs: Expr, rhs: Expr) -> Expr {
+    Expr::binary(BinaryOp::Add, lhs, rhs)
+}
+
+fn sub(lhs: Expr, rhs: Expr) -> Expr {
+    Expr::binary(BinaryOp::Sub, lhs, rhs)
+}
+
+fn mul(lhs: Expr, rhs: Expr) -> Expr {
+    Expr::binary(BinaryOp::Mul, lhs, rhs)
+}
+
+fn div(lhs: Expr, rhs: Expr) -> Expr {
+    Expr::binary(BinaryOp::Div, lhs, rhs)
+}
+
+fn pow(lhs: Expr, rhs: Expr) -> Expr {
+    Expr::binary(BinaryOp::Pow, lhs, rhs)
+}
+
+/// Whether `expr` mentions the variable `var` anywhere, including in the
+/// arguments of a call. Function bodies cannot see the caller's variables,
+/// so a call depends on `var` only through its arguments.
+pub fn depends_on(expr: &Expr, var: &str) -> bool {
+    match &expr.kind {
+        ExprKind::Variable(name) => name == var,
+        ExprKind::Number(_) | ExprKind::History(_) | ExprKind::Text(_) => false,
+        ExprKind::Unary { operand, .. } => depends_on(operand, var),
+        ExprKind::Binary { lhs, rhs, .. } => depends_on(lhs, var) || depends_on(rhs, var),
+        ExprKind::Call { args, .. } | ExprKind::List(args) => {
+            args.iter().any(|arg| depends_on(arg, var))
+        }
+        ExprKind::Quantity { value: expr, .. } | ExprKind::Convert { expr, .. } => {
+            depends_on(expr, var)
+        }
+    }
+}
+
+/// Replaces every variable for which `lookup` has an expression. The
+/// replacement takes the span of the variable it stands in for, so errors
+/// inside it still point into the line being evaluated.
+pub fn substitute(expr: &Expr, lookup: &dyn Fn(&str) -> Option<Expr>) -> Expr {
+    let kind = match &expr.kind {
+        ExprKind::Variable(name) => match lookup(name) {
+            Some(replacement) => return respan(&replacement, expr.span),
+            None => return expr.clone(),
+        },
+        ExprKind::Number(_) | ExprKind::History(_) | ExprKind::Text(_) => return expr.clone(),
+        ExprKind::Unary { op, operand } => ExprKind::Unary {
+            op: *op,
+            operand: Box::new(substitute(operand, lookup)),
+        },
+        ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+            op: *op,
+            lhs: Box::new(substitute(lhs, lookup)),
+            rhs: Box::new(substitute(rhs, lookup)),
+        },
+        ExprKind::Call { name, args } => ExprKind::Call {
+            name: name.clone(),
+            args: args.iter().map(|arg| substitute(arg, lookup)).collect(),
+        },
+        ExprKind::Quantity { value, unit } => ExprKind::Quantity {
+            value: Box::new(substitute(value, lookup)),
+            unit: unit.clone(),
+        },
+        ExprKind::Convert { expr: inner, unit } => ExprKind::Convert {
+            expr: Box::new(substitute(inner, lookup)),
+            unit: unit.clone(),
+        },
+        ExprKind::List(items) => {
+            ExprKind::List(items.iter().map(|item| substitute(item, lookup)).collect())
+        }
+    };
+    Expr::new(kind, expr.span)
+}
+
+fn respan(expr: &Expr, span: Span) -> Expr {
+    let mut expr = substitute(expr, &|_| None);
+    set_spans(&mut expr, span);
+    expr
+}
+
+fn set_spans(expr: &mut Expr, span: Span) {
+    expr.span = span;
+    match &mut expr.kind {
+        ExprKind::Unary { operand, .. } => set_spans(operand, span),
+        ExprKind::Binary { lhs, rhs, .. } => {
+            set_spans(lhs, span);
+            set_spans(rhs, span);
+        }
+        ExprKind::Call { args, .. } | ExprKind::List(args) => {
+            args.iter_mut().for_each(|arg| set_spans(arg, span))
+        }
+        ExprKind::Quantity { value: expr, .. } | ExprKind::Convert { expr, .. } => {
+            set_spans(expr, span)
+        }
+        ExprKind::Number(_) | ExprKind::Variable(_) | ExprKind::History(_) | ExprKind::Text(_) => {}
+    }
+}
+
+fn not_differentiable(what: &str, span: Span) -> CalcError {
+    CalcError::Domain {
+        name: "diff".to_string(),
+        message: format!("cannot differentiate '{}'", what),
+        span,
+    }
+}
+
+// `a + p%` only means `a * (1 + p / 100)` where it is written, so the
+// rules below see it in that form; a lone `p%` is `p / 100`.
+fn expand_percent(expr: &Expr) -> Option<Expr> {
+    let span = expr.span;
+    let hundred = || constant(100, span);
+    match &expr.kind {
+        ExprKind::Unary {
+            op: UnaryOp::Percent,
+            operand,
+        } => Some(div((**operand).clone(), hundred())),
+        ExprKind::Binary {
+            op: op @ (BinaryOp::Add | BinaryOp::Sub),
+            lhs,
+            rhs,
+        } if rhs.is_percent() => {
+            let ExprKind::Unary { operand, .. } = &rhs.kind else {
+                unreachable!("checked by is_percent")
+            };
+            let factor = Expr::binary(*op, constant(1, span), div((**operand).clone(), hundred()));
+            Some(mul((**lhs).clone(), factor))
+        }
+        _ => None,
+    }
+}
+
+/// The derivative of `expr` with respect to `var`, unsimplified. Calls to
+/// user functions are expanded into their bodies first.
+pub fn derivative(expr: &Expr, var: &str, memory: &Memory) -> Result<Expr, CalcError> {
+    on_eval_stack(|| derivative_in(expr, var, memory, &Budget::new()))
+}
+
+// Within an evaluation, each step of the derivative counts against the
+// evaluation's own depth limit.
+pub(crate) fn derivative_in(
+    expr: &Expr,
+    var: &str,
+    memory: &Memory,
+    budget: &Budget,
+) -> Result<Expr, CalcError> {
+    derivative_at(expr, var, memory, 0, budget)
+}
+
+// `depth` counts the user functions being expanded, which like calls stop
+// at `MAX_CALL_DEPTH` so that a recursive definition fails cleanly.
+fn derivative_at(
+    expr: &Expr,
+    var: &str,
+    memory: &Memory,
+    depth: usize,
+    budget: &Budget,
+) -> Result<Expr, CalcError> {
+    budget.nested(expr.span, || {
+        derivative_step(expr, var, memory, depth, budget)
+    })
+}
+
+fn derivative_step(
+    expr: &Expr,
+    var: &str,
+    memory: &Memory,
+    depth: usize,
+    budget: &Budget,
+) -> Result<Expr, CalcError> {
+    let span = expr.span;
+    if !depends_on(expr, var) {
+        return Ok(constant(0, span));
+    }
+    let d = |expr: &Expr| derivative_at(expr, var, memory, depth, budget);
+    if let Some(expanded) = expand_percent(expr) {
+        return d(&expanded);
+    }
+    match &expr.kind {
+        ExprKind::Variable(_) => Ok(constant(1, span)),
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => Ok(neg(d(operand)?)),
+        ExprKind::Unary {
+            op: UnaryOp::Plus,
+            operand,
+        } => d(operand),
+        ExprKind::Binary { op, lhs, rhs } => binary_derivative(expr, *op, lhs, rhs, var, &d),
+        ExprKind::Call { name, args } => {
+            call_derivative(name, args, span, var, memory, depth, budget)
+        }
+        ExprKind::Unary { op, .. } => Err(not_differentiable(op.symbol(), span)),
+        ExprKind::Quantity { .. } | ExprKind::Convert { .. } => {
+            Err(not_differentiable("a quantity with units", span))
+        }
+        ExprKind::Number(_) | ExprKind::History(_) => Ok(constant(0, span)),
+        ExprKind::Text(_) => Err(not_differentiable("text", span)),
+        ExprKind::List(items) => {
+            let items = items.iter().map(d).collect::<Result<_, _>>()?;
+            Ok(Expr::new(ExprKind::List(items), span))
+        }
+    }
+}
+
+// The product, quotient and power rules, split out of `derivative_at` to
+// keep its stack frame small through deep expansions.
+fn binary_derivative(
+    expr: &Expr,
+    op: BinaryOp,
+    lhs: &Expr,
+    rhs: &Expr,
+    var: &str,
+    d: &dyn Fn(&Expr) -> Result<Expr, CalcError>,
+) -> Result<Expr, CalcError> {
+    let span = expr.span;
+    let (l, r) = (lhs.clone(), rhs.clone());
+    match op {
+        BinaryOp::Add => Ok(add(d(lhs)?, d(rhs)?)),
+        BinaryOp::Sub => Ok(sub(d(lhs)?, d(rhs)?)),
+        BinaryOp::Mul if !depends_on(lhs, var) => Ok(mul(l, d(rhs)?)),
+        BinaryOp::Mul if !depends_on(rhs, var) => Ok(mul(d(lhs)?, r)),
+        BinaryOp::Mul => Ok(add(mul(d(lhs)?, r.clone()), mul(l, d(rhs)?))),
+        BinaryOp::Div if !depends_on(rhs, var) => Ok(div(d(lhs)?, r)),
+        BinaryOp::Div => {
+            let numerator = sub(mul(d(lhs)?, r.clone()), mul(l, d(rhs)?));
+            Ok(div(numerator, pow(r, constant(2, span))))
+        }
+        // d(u^n) = n * u^(n - 1) * u'
+        BinaryOp::Pow if !depends_on(rhs, var) => {
+            let lowered = pow(l, sub(r.clone(), constant(1, span)));
+            Ok(mul(mul(r, lowered), d(lhs)?))
+        }
+        // d(a^v) = a^v * ln(a) * v'
+        BinaryOp::Pow if !depends_on(lhs, var) => {
+            Ok(mul(mul(expr.clone(), call("ln", l)), d(rhs)?))
+        }
+        // d(u^v) = u^v * (v' * ln(u) + v * u' / u)
+        BinaryOp::Pow => {
+            let inner = add(mul(d(rhs)?, call("ln", l.clone())), div(mul(r, d(lhs)?), l));
+            Ok(mul(expr.clone(), inner))
+        }
+        _ => Err(not_differentiable(op.symbol(), span)),
+    }
+}
+
+fn call_derivative(
+    name: &str,
+    args: &[Expr],
+    span: Span,
+    var: &str,
+    memory: &Memory,
+    depth: usize,
+    budget: &Budget,
+) -> Result<Expr, CalcError> {
+    let d = |expr: &Expr| derivative_at(expr, var, memory, depth, budget);
+    if let Some(function) = memory.function(name) {
+        if function.params.len() == args.len() {
+            if depth >= MAX_CALL_DEPTH {
+                return Err(CalcError::RecursionLimit {
+                    name: name.to_string(),
+                    span,
+                });
+            }
+            let body = substitute(&function.body, &|param| {
+                let at = function.params.iter().position(|p| p == param)?;
+                Some(args[at].clone())
+            });
+            return derivative_at(&body, var, memory, depth + 1, budget)
+                .map_err(|e| e.with_span(span));
+        }
+    }
+    let [arg] = args else {
+        return Err(not_differentiable(name, span));
+    };
+    let u = arg.clone();
+    let outer = match name {
+        "sin" => call("cos", u),
+        "cos" => neg(call("sin", u)),
+        "tan" => div(constant(1, span), pow(call("cos", u), constant(2, span))),
+        "exp" => call("exp", u),
+        "ln" => div(constant(1, span), u),
+        "log10" => div(constant(1, span), mul(u, call("ln", constant(10, span)))),
+        "sqrt" => div(constant(1, span), mul(constant(2, span), call("sqrt", u))),
+        "abs" => div(u.clone(), call("abs", u)),
+        _ => return Err(not_differentiable(name, span)),
+    };
+    Ok(mul(outer, d(arg)?))
+}
+
+/// Applies algebraic identities until none matches: folds constants,
+/// drops `+ 0`, `* 1` and `^ 1`, collects like terms and powers of the
+/// same base. Values are not looked up, so `x` stays `x`; `is_builtin`
+/// says whether a name such as `e` still means the built-in constant, so
+/// that `ln(e)` is only folded when it has not been shadowed.
+pub fn simplify(expr: &Expr, is_builtin: &dyn Fn(&str) -> bool) -> Expr {
+    let mut expr = expr.clone();
+    for _ in 0..MAX_SIMPLIFY_PASSES {
+        let next = simplify_pass(&expr, is_builtin);
+        if next == expr {
+            break;
+        }
+        expr = next;
+    }
+    expr
+}
+
+fn simplify_pass(expr: &Expr, is_builtin: &dyn Fn(&str) -> bool) -> Expr {
+    if let Some(expanded) = expand_percent(expr) {
+        return simplify_pass(&expanded, is_builtin);
+    }
+    let span = expr.span;
+    let pass = |expr: &Expr| simplify_pass(expr, is_builtin);
+    match &expr.kind {
+        ExprKind::Unary { op, operand } => simplify_unary(*op, pass(operand), span),
+        ExprKind::Binary { op, lhs, rhs } => simplify_binary(*op, pass(lhs), pass(rhs)),
+        ExprKind::Call { name, args } => {
+            let args: Vec<Expr> = args.iter().map(pass).collect();
+            simplify_call(name, args, span, is_builtin)
+        }
+        ExprKind::List(items) => Expr::new(ExprKind::List(items.iter().map(pass).collect()), span),
+        _ => expr.clone(),
+    }
+}
+
+fn simplify_unary(op: UnaryOp, operand: Expr, span: Span) -> Expr {
+    match (op, &operand.kind) {
+        (UnaryOp::Plus, _) => operand,
+        (UnaryOp::Neg, ExprKind::Number(value)) => match value.neg() {
+            Ok(value) => number(value, span),
+            Err(_) => Expr::unary(op, operand, span),
+        },
+        (
+            UnaryOp::Neg,
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                operand: inner,
+            },
+        ) => (**inner).clone(),
+        _ => Expr::unary(op, operand, span),
+    }
+}
+
+fn fold(op: BinaryOp, a: &Number, b: &Number) -> Option<Number> {
+    match op {
+        BinaryOp::Add => a.add(b).ok(),
+        BinaryOp::Sub => a.sub(b).ok(),
+        BinaryOp::Mul => a.mul(b).ok(),
+        BinaryOp::Div if !b.is_zero() => a.div(b).ok(),
+        BinaryOp::Pow if b.to_i64().is_some() => a.pow(b).ok(),
+        _ => None,
+    }
+}
+
+// Splits a term into its numeric coefficient and the rest: `3 * x` is
+// (3, x), `-x` is (-1, x) and `x` is (1, x).
+fn coefficient(expr: &Expr) -> (Number, Expr) {
+    let one = || Number::Rational(BigRational::from_integer(BigInt::from(1)));
+    match &expr.kind {
+        ExprKind::Binary {
+            op: BinaryOp::Mul,
+            lhs,
+            rhs,
+        } => match as_number(lhs) {
+            Some(value) => (value.clone(), (**rhs).clone()),
+            None => (one(), expr.clone()),
+        },
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => (one().neg().unwrap_or_else(|_| one()), (**operand).clone()),
+        _ => (one(), expr.clone()),
+    }
+}
+
+// Splits a factor into base and exponent: `x ^ 3` is (x, 3), `x` is (x, 1).
+fn power(expr: &Expr) -> (Expr, Expr) {
+    match &expr.kind {
+        ExprKind::Binary {
+            op: BinaryOp::Pow,
+            lhs,
+            rhs,
+        } => ((**lhs).clone(), (**rhs).clone()),
+        _ => (expr.clone(), constant(1, expr.span)),
+    }
+}
+
+fn like_terms(op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Option<Expr> {
+    let (a, x) = coefficient(lhs);
+    let (b, y) = coefficient(rhs);
+    if x != y || as_number(&x).is_some() {
+        return None;
+    }
+    let sum = fold(op, &a, &b)?;
+    Some(mul(number(sum, lhs.span), x))
+}
+
+fn same_base(op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Option<Expr> {
+    let (base, a) = power(lhs);
+    let (other, b) = power(rhs);
+    if base != other || as_number(&base).is_some() {
+        return None;
+    }
+    let exponent = if op == BinaryOp::Mul {
+        add(a, b)
+    } else {
+        sub(a, b)
+    };
+    Some(pow(base, exponent))
+}
+
+// Whether `expr` is defined wherever its variables are: `0 * ln(x)` is
+// not `0` for x <= 0, so the term may only be dropped when this holds.
+fn is_total(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Number(_) | ExprKind::Variable(_) => true,
+        ExprKind::Unary {
+            op: UnaryOp::Neg | UnaryOp::Plus,
+            operand,
+        } => is_total(operand),
+        ExprKind::Binary {
+            op: BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul,
+            lhs,
+            rhs,
+        } => is_total(lhs) && is_total(rhs),
+        ExprKind::Binary {
+            op: BinaryOp::Pow,
+            lhs,
+            rhs,
+        } => {
+            is_total(lhs)
+                && as_number(rhs)
+                    .and_then(Number::to_i64)
+                    .is_some_and(|n| n >= 0)
+        }
+        ExprKind::Call { name, args } => {
+            matches!(name.as_str(), "sin" | "cos" | "exp" | "abs") && args.iter().all(is_total)
+        }
+        _ => false,
+    }
+}
+
+// `(x ^ a) ^ b` is `x ^ (a * b)` when b is whole, or when a is one over an
+// odd number so that x ^ a is defined for negative x too. Otherwise the
+// rewrite is wrong: `(x ^ 2) ^ 0.5` is |x|, not x.
+fn powers_combine(a: &Number, b: &Number) -> bool {
+    if b.to_i64().is_some() {
+        return true;
+    }
+    let a = a.to_rational();
+    a.numer().magnitude().is_one() && !(a.denom() % BigInt::from(2)).is_zero()
+}
+
+fn simplify_binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    if let (Some(a), Some(b)) = (as_number(&lhs), as_number(&rhs)) {
+        if let Some(value) = fold(op, a, b) {
+            return number(value, lhs.span.to(rhs.span));
+        }
+    }
+    let span = lhs.span.to(rhs.span);
+    let negated = |expr: &Expr| match &expr.kind {
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => Some((**operand).clone()),
+        _ => None,
+    };
+    match op {
+        BinaryOp::Add if is_constant(&lhs, 0) => rhs,
+        BinaryOp::Add | BinaryOp::Sub if is_constant(&rhs, 0) => lhs,
+        BinaryOp::Sub if is_constant(&lhs, 0) => neg(rhs),
+        BinaryOp::Sub if lhs == rhs => constant(0, span),
+        BinaryOp::Add => match negated(&rhs) {
+            Some(rhs) => sub(lhs, rhs),
+            None => like_terms(op, &lhs, &rhs).unwrap_or_else(|| add(lhs, rhs)),
+        },
+        BinaryOp::Sub => match negated(&rhs) {
+            Some(rhs) => add(lhs, rhs),
+            None => like_terms(op, &lhs, &rhs).unwrap_or_else(|| sub(lhs, rhs)),
+        },
+        BinaryOp::Mul if is_constant(&lhs, 0) && is_total(&rhs) => constant(0, span),
+        BinaryOp::Mul if is_constant(&rhs, 0) && is_total(&lhs) => constant(0, span),
+        BinaryOp::Mul if is_constant(&lhs, 1) => rhs,
+        BinaryOp::Mul if is_constant(&rhs, 1) => lhs,
+        BinaryOp::Mul if is_constant(&lhs, -1) => neg(rhs),
+        // Numbers move to the front, where they can meet other numbers.
+        BinaryOp::Mul if as_number(&rhs).is_some() => mul(rhs, lhs),
+        BinaryOp::Mul => simplify_product(lhs, rhs),
+        BinaryOp::Div if is_constant(&rhs, 1) => lhs,
+        BinaryOp::Div if is_constant(&lhs, 0) => constant(0, span),
+        BinaryOp::Div if lhs == rhs => constant(1, span),
+        BinaryOp::Div => same_base(op, &lhs, &rhs).unwrap_or_else(|| div(lhs, rhs)),
+        BinaryOp::Pow if is_constant(&rhs, 0) || is_constant(&lhs, 1) => constant(1, span),
+        BinaryOp::Pow if is_constant(&rhs, 1) => lhs,
+        BinaryOp::Pow => match &lhs.kind {
+            ExprKind::Binary {
+                op: BinaryOp::Pow,
+                lhs: base,
+                rhs: inner,
+            } if as_number(inner)
+                .zip(as_number(&rhs))
+                .is_some_and(|(a, b)| powers_combine(a, b)) =>
+            {
+                pow((**base).clone(), mul((**inner).clone(), rhs))
+            }
+            _ => pow(lhs, rhs),
+        },
+        _ => Expr::binary(op, lhs, rhs),
+    }
+}
+
+fn simplify_product(lhs: Expr, rhs: Expr) -> Expr {
+    if let Some(product) = same_base(BinaryOp::Mul, &lhs, &rhs) {
+        return product;
+    }
+    match (&lhs.kind, &rhs.kind) {
+        (
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            },
+            _,
+        ) => neg(mul((**operand).clone(), rhs)),
+        (
+            _,
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            },
+        ) => neg(mul(lhs, (**operand).clone())),
+        // n * (m * x) is (n * m) * x.
+        (
+            ExprKind::Number(_),
+            ExprKind::Binary {
+                op: BinaryOp::Mul,
+                lhs: inner,
+                rhs: x,
+            },
+        ) if as_number(inner).is_some() => mul(mul(lhs, (**inner).clone()), (**x).clone()),
+        // In a * b * c, c may be a power of b: 2 * x * x is 2 * x ^ 2.
+        (
+            ExprKind::Binary {
+                op: BinaryOp::Mul,
+                lhs: a,
+                rhs: b,
+            },
+            _,
+        ) => match same_base(BinaryOp::Mul, b, &rhs) {
+            Some(product) => mul((**a).clone(), product),
+            None => mul(lhs, rhs),
+        },
+        _ => mul(lhs, rhs),
+    }
+}
+
+fn simplify_call(
+    name: &str,
+    args: Vec<Expr>,
+    span: Span,
+    is_builtin: &dyn Fn(&str) -> bool,
+) -> Expr {
+    if let [arg] = args.as_slice() {
+        // `exp(ln(x))` is not x for x <= 0, so only this way round folds.
+        if let ExprKind::Call {
+            name: inner,
+            args: inner_args,
+        } = &arg.kind
+        {
+            if name == "ln" && inner == "exp" && inner_args.len() == 1 {
+                return inner_args[0].clone();
+            }
+        }
+        let known = match name {
+            "sin" | "tan" | "sqrt" if is_constant(arg, 0) => Some(0),
+            "cos" | "exp" if is_constant(arg, 0) => Some(1),
+            "ln" if is_constant(arg, 1) => Some(0),
+            "sqrt" if is_constant(arg, 1) => Some(1),
+            "ln" if matches!(&arg.kind, ExprKind::Variable(name) if name == "e" && is_builtin(name)) => {
+                Some(1)
+            }
+            _ => None,
+        };
+        if let Some(value) = known {
+            return constant(value, span);
+        }
+    }
+    Expr::new(
+        ExprKind::Call {
+            name: name.to_string(),
+            args,
+        },
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::token::tokenize;
+
+    fn parse_str(input: &str) -> Expr {
+        parse(&tokenize(input).unwrap()).unwrap()
+    }
+
+    fn builtin(name: &str) -> bool {
+        crate::functions::constant(name).is_some()
+    }
+
+    fn simplified(input: &str) -> String {
+        simplify(&parse_str(input), &builtin).to_string()
+    }
+
+    fn diff(input: &str) -> String {
+        let derivative = derivative(&parse_str(input), "x", &Memory::new()).unwrap();
+        simplify(&derivative, &builtin).to_string()
+    }
+
+    #[test]
+    fn test_simplify() {
+        assert_eq!(simplified("x * 1 + 0"), "x");
+        assert_eq!(simplified("2 * x + 3 * x"), "5 * x");
+        assert_eq!(simplified("x - x"), "0");
+        assert_eq!(simplified("x + x"), "2 * x");
+        assert_eq!(simplified("x * x * x"), "x ^ 3");
+        assert_eq!(simplified("(x ^ 2) ^ 3"), "x ^ 6");
+        assert_eq!(simplified("x * 2 * 3"), "6 * x");
+        assert_eq!(simplified("1 / 3 + 1 / 6"), "0.5");
+        assert_eq!(simplified("a - -b"), "a + b");
+        assert_eq!(simplified("ln(exp(y + 1))"), "y + 1");
+        assert_eq!(simplified("x ^ 3 / x"), "x ^ 2");
+        assert_eq!(simplified("sin(0) + cos(0) * y"), "y");
+    }
+
+    #[test]
+    fn test_simplify_keeps_domains() {
+        assert_eq!(simplified("(x ^ 2) ^ 0.5"), "(x ^ 2) ^ 0.5");
+        assert_eq!(simplified("(x ^ (1 / 3)) ^ 0.5"), "x ^ (1/6)");
+        assert_eq!(simplified("(x ^ 0.5) ^ 4"), "x ^ 2");
+        assert_eq!(simplified("exp(ln(x))"), "exp(ln(x))");
+        assert_eq!(simplified("0 * ln(x)"), "0 * ln(x)");
+        assert_eq!(simplified("sqrt(x) * 0"), "0 * sqrt(x)");
+        assert_eq!(simplified("0 * sin(x ^ 2 - y)"), "0");
+        assert_eq!(simplified("ln(e)"), "1");
+        let shadowed = simplify(&parse_str("ln(e)"), &|name| name != "e");
+        assert_eq!(shadowed.to_string(), "ln(e)");
+    }
+
+    #[test]
+    fn test_derivatives() {
+        assert_eq!(diff("x ^ 2 * sin(x)"), "2 * x * sin(x) + x ^ 2 * cos(x)");
+        assert_eq!(diff("3 * x ^ 2 + 2 * x + 1"), "6 * x + 2");
+        assert_eq!(diff("y * x"), "y");
+        assert_eq!(diff("exp(2 * x)"), "2 * exp(2 * x)");
+        assert_eq!(diff("ln(x)"), "1 / x");
+        assert_eq!(diff("1 / x"), "-1 / x ^ 2");
+        assert_eq!(diff("2 ^ x"), "2 ^ x * ln(2)");
+        assert_eq!(diff("-cos(x)"), "sin(x)");
+        assert_eq!(diff("y ^ 2"), "0");
+    }
+
+    #[test]
+    fn test_user_functions_are_expanded() {
+        let mut memory = Memory::new();
+        let statement = crate::parser::parse_statement(&tokenize("fn f(t) = t ^ 3").unwrap());
+        if let Ok(crate::expression::Statement::FnDef { name, function }) = statement {
+            memory.define(name, function);
+        }
+        let derivative = derivative(&parse_str("f(2 * x)"), "x", &memory).unwrap();
+        assert_eq!(
+            simplify(&derivative, &builtin).to_string(),
+            "6 * (2 * x) ^ 2"
+        );
+    }
+
+    #[test]
+    fn test_recursive_function_hits_the_limit() {
+        let mut memory = Memory::new();
+        let statement = crate::parser::parse_statement(&tokenize("fn f(x) = x + f(x)").unwrap());
+        if let Ok(crate::expression::Statement::FnDef { name, function }) = statement {
+            memory.define(name, function);
+        }
+        assert_eq!(
+            derivative(&parse_str("f(x)"), "x", &memory),
+            Err(CalcError::RecursionLimit {
+                name: "f".to_string(),
+                span: Span::new(0, 4)
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let memory = Memory::new();
+        assert_eq!(
+            derivative(&parse_str("1 + x % 2"), "x", &memory),
+            Err(CalcError::Domain {
+                name: "diff".to_string(),
+                message: "cannot differentiate '%'".to_string(),
+                span: Span::new(4, 9)
+            })
+        );
+        assert!(derivative(&parse_str("floor(x)"), "x", &memory).is_err());
+        assert!(derivative(&parse_str("(x) m"), "x", &memory).is_err());
+    }
+}
@@ -1,46 +1,599 @@
-use std::collections::HashMap;
-
-#[derive(Debug)]
-pub enum Token {
-    Number(f64),
-    MemoryRef(String),
-    MemoryPlus(String),
-    MemoryMinus(String),
+use crate::error::CalcError;
+use crate::memory::MemoryOp;
+use crate::number::{parse_literal, Number};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(Number),
+    Ident(String),
+    Text(String),
+    History(usize),
+    Memory { name: String, op: MemoryOp },
     Plus,
     Minus,
     Asterisk,
     Slash,
+    Percent,
+    Caret,
+    Ampersand,
+    Pipe,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+    DoubleEquals,
+    BangEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Equals,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Number(value) => write!(f, "{}", value),
+            TokenKind::Ident(name) => write!(f, "{}", name),
+            TokenKind::Text(text) => write!(f, "\"{}\"", text),
+            TokenKind::History(number) => write!(f, "${}", number),
+            TokenKind::Memory { name, op } => write!(f, "mem{}{}", name, op),
+            TokenKind::Plus => write!(f, "+"),
+            TokenKind::Minus => write!(f, "-"),
+            TokenKind::Asterisk => write!(f, "*"),
+            TokenKind::Slash => write!(f, "/"),
+            TokenKind::Percent => write!(f, "%"),
+            TokenKind::Caret => write!(f, "^"),
+            TokenKind::Ampersand => write!(f, "&"),
+            TokenKind::Pipe => write!(f, "|"),
+            TokenKind::Tilde => write!(f, "~"),
+            TokenKind::ShiftLeft => write!(f, "<<"),
+            TokenKind::ShiftRight => write!(f, ">>"),
+            TokenKind::DoubleEquals => write!(f, "=="),
+            TokenKind::BangEquals => write!(f, "!="),
+            TokenKind::Less => write!(f, "<"),
+            TokenKind::LessEquals => write!(f, "<="),
+            TokenKind::Greater => write!(f, ">"),
+            TokenKind::GreaterEquals => write!(f, ">="),
+            TokenKind::LParen => write!(f, "("),
+            TokenKind::RParen => write!(f, ")"),
+            TokenKind::LBracket => write!(f, "["),
+            TokenKind::RBracket => write!(f, "]"),
+            TokenKind::Comma => write!(f, ","),
+            TokenKind::Equals => write!(f, "="),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn parse(input: &str, memory: &HashMap<String, f64>) -> Result<Token, String> {
-        if let Ok(number) = input.parse::<f64>() {
-            return Ok(Token::Number(number));
+    pub fn new(kind: TokenKind, start: usize, end: usize) -> Self {
+        Self {
+            kind,
+            span: Span::new(start, end),
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, CalcError> {
+    Lexer::new(input).tokenize()
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    tokens: Vec<Token>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            bytes: input.as_bytes(),
+            pos: 0,
+            tokens: Vec::new(),
         }
+    }
 
-        if input.starts_with("mem") {
-            let name = input[3..input.len() - 1].trim().to_string();
-            if input.ends_with("+") {
-                return Ok(Token::MemoryPlus(name));
-            } else if input.ends_with("-") {
-                return Ok(Token::MemoryMinus(name));
+    fn tokenize(mut self) -> Result<Vec<Token>, CalcError> {
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            match c {
+                c if c.is_ascii_whitespace() => self.pos += 1,
+                b'0' if self.radix().is_some() => self.radix_number(start)?,
+                b'0'..=b'9' | b'.' => self.number(start)?,
+                b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.identifier(start),
+                b'$' if matches!(self.peek_at(1), Some(b'0'..=b'9')) => self.history(start)?,
+                b'"' => self.text(start)?,
+                b'+' => self.symbol(TokenKind::Plus),
+                b'-' => self.symbol(TokenKind::Minus),
+                b'*' => self.symbol(TokenKind::Asterisk),
+                b'/' => self.symbol(TokenKind::Slash),
+                b'%' => self.symbol(TokenKind::Percent),
+                b'^' => self.symbol(TokenKind::Caret),
+                b'&' => self.symbol(TokenKind::Ampersand),
+                b'|' => self.symbol(TokenKind::Pipe),
+                b'~' => self.symbol(TokenKind::Tilde),
+                b'<' if self.peek_at(1) == Some(b'<') => self.pair(TokenKind::ShiftLeft),
+                b'>' if self.peek_at(1) == Some(b'>') => self.pair(TokenKind::ShiftRight),
+                b'<' if self.peek_at(1) == Some(b'=') => self.pair(TokenKind::LessEquals),
+                b'>' if self.peek_at(1) == Some(b'=') => self.pair(TokenKind::GreaterEquals),
+                b'=' if self.peek_at(1) == Some(b'=') => self.pair(TokenKind::DoubleEquals),
+                b'!' if self.peek_at(1) == Some(b'=') => self.pair(TokenKind::BangEquals),
+                b'<' => self.symbol(TokenKind::Less),
+                b'>' => self.symbol(TokenKind::Greater),
+                b'(' => self.symbol(TokenKind::LParen),
+                b')' => self.symbol(TokenKind::RParen),
+                b'[' => self.symbol(TokenKind::LBracket),
+                b']' => self.symbol(TokenKind::RBracket),
+                b',' => self.symbol(TokenKind::Comma),
+                b'=' => self.symbol(TokenKind::Equals),
+                _ => {
+                    let ch = self.input[start..].chars().next().unwrap_or_default();
+                    return Err(CalcError::UnknownCharacter {
+                        ch,
+                        span: Span::new(start, start + ch.len_utf8()),
+                    });
+                }
             }
         }
+        Ok(self.tokens)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.pos + offset).copied()
+    }
+
+    fn push(&mut self, kind: TokenKind, start: usize) {
+        self.tokens.push(Token::new(kind, start, self.pos));
+    }
+
+    fn symbol(&mut self, kind: TokenKind) {
+        let start = self.pos;
+        self.pos += 1;
+        self.push(kind, start);
+    }
+
+    fn pair(&mut self, kind: TokenKind) {
+        let start = self.pos;
+        self.pos += 2;
+        self.push(kind, start);
+    }
+
+    // `0x`, `0o` and `0b` only start a literal when a digit of that base
+    // follows, so `0b` on its own is still zero bytes.
+    fn radix(&self) -> Option<u32> {
+        let radix = match self.peek_at(1)? {
+            b'x' | b'X' => 16,
+            b'o' | b'O' => 8,
+            b'b' | b'B' => 2,
+            _ => return None,
+        };
+        (self.peek_at(2)? as char).is_digit(radix).then_some(radix)
+    }
+
+    fn radix_number(&mut self, start: usize) -> Result<(), CalcError> {
+        let radix = self.radix().unwrap_or(10);
+        self.pos += 2;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let value = i64::from_str_radix(&self.input[start + 2..self.pos], radix)
+            .map_err(|_| self.invalid_number(start))?;
+        self.push(TokenKind::Number(Number::Integer(value)), start);
+        Ok(())
+    }
+
+    fn skip_digits(&mut self) -> usize {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self, start: usize) -> Result<(), CalcError> {
+        let mut digits = self.skip_digits();
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            digits += self.skip_digits();
+        }
+        if digits == 0 {
+            return Err(self.invalid_number(start));
+        }
 
-        if memory.contains_key(input) {
-            return Ok(Token::MemoryRef(input.to_string()));
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            let exponent_digits_at = match self.peek_at(1) {
+                Some(b'+' | b'-') => 2,
+                _ => 1,
+            };
+            if matches!(self.peek_at(exponent_digits_at), Some(b'0'..=b'9')) {
+                self.pos += exponent_digits_at;
+                self.skip_digits();
+            }
         }
 
-        match input {
-            "+" => Ok(Token::Plus),
-            "-" => Ok(Token::Minus),
-            "*" => Ok(Token::Asterisk),
-            "/" => Ok(Token::Slash),
-            "(" => Ok(Token::LParen),
-            ")" => Ok(Token::RParen),
-            _ => Err(format!("Unknow token: {}", input)),
+        let value = parse_literal(&self.input[start..self.pos])
+            .ok_or_else(|| self.invalid_number(start))?;
+        self.push(TokenKind::Number(Number::Rational(value)), start);
+        Ok(())
+    }
+
+    fn invalid_number(&self, start: usize) -> CalcError {
+        CalcError::InvalidNumber {
+            text: self.input[start..self.pos].to_string(),
+            span: Span::new(start, self.pos),
         }
     }
+
+    // A memory operator only counts when it ends the word, otherwise `memo+1`
+    // would be swallowed as an update of slot "o" and `memo==1` as a store.
+    // Swapping names the other slot directly: `memA<>B`.
+    fn memory_op(&mut self) -> Option<MemoryOp> {
+        if self.input[self.pos..].starts_with("<>") {
+            let start = self.pos + 2;
+            let len = self.input[start..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(self.input.len() - start);
+            if !is_slot_name(&self.input[start..start + len]) {
+                return None;
+            }
+            self.pos = start + len;
+            return Some(MemoryOp::Swap(self.input[start..self.pos].to_string()));
+        }
+
+        let op = match self.peek()? {
+            b'+' => MemoryOp::Add,
+            b'-' => MemoryOp::Subtract,
+            b'*' => MemoryOp::Multiply,
+            b'=' => MemoryOp::Store,
+            b'?' => MemoryOp::Recall,
+            b'!' => MemoryOp::Clear,
+            _ => return None,
+        };
+        if self.peek_at(1).is_some_and(|c| !c.is_ascii_whitespace()) {
+            return None;
+        }
+        self.pos += 1;
+        Some(op)
+    }
+
+    fn history(&mut self, start: usize) -> Result<(), CalcError> {
+        self.pos += 1;
+        self.skip_digits();
+        let number = self.input[start + 1..self.pos]
+            .parse()
+            .map_err(|_| self.invalid_number(start))?;
+        self.push(TokenKind::History(number), start);
+        Ok(())
+    }
+
+    // Text runs to the next double quote; there are no escapes, so it cannot
+    // contain one.
+    fn text(&mut self, start: usize) -> Result<(), CalcError> {
+        let Some(len) = self.input[start + 1..].find('"') else {
+            return Err(CalcError::UnterminatedText {
+                span: Span::new(start, self.input.len()),
+            });
+        };
+        self.pos = start + 1 + len + 1;
+        let text = self.input[start + 1..start + 1 + len].to_string();
+        self.push(TokenKind::Text(text), start);
+        Ok(())
+    }
+
+    fn identifier(&mut self, start: usize) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == b'_') {
+            self.pos += 1;
+        }
+        let word = &self.input[start..self.pos];
+
+        if let Some(name) = word.strip_prefix("mem").filter(|name| is_slot_name(name)) {
+            if let Some(op) = self.memory_op() {
+                let name = name.to_string();
+                self.push(TokenKind::Memory { name, op }, start);
+                return;
+            }
+        }
+
+        self.push(TokenKind::Ident(word.to_string()), start);
+    }
+}
+
+// A slot has to be named like any other variable, otherwise `let` could not
+// read it back from a memory file: `mem1+` is not an update of slot "1".
+fn is_slot_name(name: &str) -> bool {
+    name.bytes().next().is_some_and(|c| !c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &str) -> TokenKind {
+        TokenKind::Number(Number::Rational(parse_literal(text).unwrap()))
+    }
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_adjacent_operators_and_parens() {
+        assert_eq!(
+            kinds("(1+2)*3"),
+            vec![
+                TokenKind::LParen,
+                number("1"),
+                TokenKind::Plus,
+                number("2"),
+                TokenKind::RParen,
+                TokenKind::Asterisk,
+                number("3"),
+            ]
+        );
+    }
+
+    // Signs are left to the parser's unary operators so that `-3^2` can
+    // bind as `-(3^2)`; the lexer only has to keep them apart from digits.
+    #[test]
+    fn test_signs_are_separate_tokens() {
+        assert_eq!(
+            kinds("3-2"),
+            vec![number("3"), TokenKind::Minus, number("2")]
+        );
+        assert_eq!(
+            kinds("3*-2"),
+            vec![
+                number("3"),
+                TokenKind::Asterisk,
+                TokenKind::Minus,
+                number("2")
+            ]
+        );
+        assert_eq!(
+            kinds("(-.5)"),
+            vec![
+                TokenKind::LParen,
+                TokenKind::Minus,
+                number("0.5"),
+                TokenKind::RParen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_power_and_remainder() {
+        assert_eq!(
+            kinds("2^10%7"),
+            vec![
+                number("2"),
+                TokenKind::Caret,
+                number("10"),
+                TokenKind::Percent,
+                number("7")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_radix_literals_and_bitwise_operators() {
+        assert_eq!(
+            kinds("0xff & ~0b1010 | 0o17"),
+            vec![
+                TokenKind::Number(Number::Integer(255)),
+                TokenKind::Ampersand,
+                TokenKind::Tilde,
+                TokenKind::Number(Number::Integer(10)),
+                TokenKind::Pipe,
+                TokenKind::Number(Number::Integer(15)),
+            ]
+        );
+        assert_eq!(
+            kinds("1<<4>>2"),
+            vec![
+                number("1"),
+                TokenKind::ShiftLeft,
+                number("4"),
+                TokenKind::ShiftRight,
+                number("2"),
+            ]
+        );
+        assert_eq!(
+            kinds("0b"),
+            vec![number("0"), TokenKind::Ident("b".to_string())]
+        );
+        assert_eq!(
+            tokenize("0x1g"),
+            Err(CalcError::InvalidNumber {
+                text: "0x1g".to_string(),
+                span: Span::new(0, 4)
+            })
+        );
+        assert!(tokenize("0x10000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        assert_eq!(
+            kinds("1<=2==3!=4>=5<6>7"),
+            vec![
+                number("1"),
+                TokenKind::LessEquals,
+                number("2"),
+                TokenKind::DoubleEquals,
+                number("3"),
+                TokenKind::BangEquals,
+                number("4"),
+                TokenKind::GreaterEquals,
+                number("5"),
+                TokenKind::Less,
+                number("6"),
+                TokenKind::Greater,
+                number("7"),
+            ]
+        );
+        assert_eq!(
+            kinds("memA==1"),
+            vec![
+                TokenKind::Ident("memA".to_string()),
+                TokenKind::DoubleEquals,
+                number("1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        assert_eq!(kinds("1e3"), vec![number("1000")]);
+        assert_eq!(kinds("2.5E-2"), vec![number("0.025")]);
+        assert_eq!(kinds("1e+2"), vec![number("100")]);
+    }
+
+    #[test]
+    fn test_memory_tokens() {
+        let memory = |name: &str, op: MemoryOp| TokenKind::Memory {
+            name: name.to_string(),
+            op,
+        };
+        assert_eq!(kinds("memA+"), vec![memory("A", MemoryOp::Add)]);
+        assert_eq!(
+            kinds(" memTotal- "),
+            vec![memory("Total", MemoryOp::Subtract)]
+        );
+        assert_eq!(
+            kinds("memA* 3*4"),
+            vec![
+                memory("A", MemoryOp::Multiply),
+                number("3"),
+                TokenKind::Asterisk,
+                number("4"),
+            ]
+        );
+        assert_eq!(
+            kinds("memA= 2"),
+            vec![memory("A", MemoryOp::Store), number("2")]
+        );
+        assert_eq!(kinds("memA?"), vec![memory("A", MemoryOp::Recall)]);
+        assert_eq!(kinds("memA!"), vec![memory("A", MemoryOp::Clear)]);
+        assert_eq!(
+            kinds("memA<>B"),
+            vec![memory("A", MemoryOp::Swap("B".to_string()))]
+        );
+        assert_eq!(
+            kinds("mem1+"),
+            vec![TokenKind::Ident("mem1".to_string()), TokenKind::Plus]
+        );
+        assert_eq!(
+            kinds("memo+1"),
+            vec![
+                TokenKind::Ident("memo".to_string()),
+                TokenKind::Plus,
+                number("1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_tokens() {
+        assert_eq!(
+            kinds("$12*ans"),
+            vec![
+                TokenKind::History(12),
+                TokenKind::Asterisk,
+                TokenKind::Ident("ans".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("$ 1"),
+            Err(CalcError::UnknownCharacter {
+                ch: '$',
+                span: Span::new(0, 1)
+            })
+        );
+    }
+
+    #[test]
+    fn test_text_tokens() {
+        assert_eq!(
+            kinds("load(\"data 1.csv\", \"\")"),
+            vec![
+                TokenKind::Ident("load".to_string()),
+                TokenKind::LParen,
+                TokenKind::Text("data 1.csv".to_string()),
+                TokenKind::Comma,
+                TokenKind::Text(String::new()),
+                TokenKind::RParen,
+            ]
+        );
+        assert_eq!(
+            tokenize("1 + \"abc"),
+            Err(CalcError::UnterminatedText {
+                span: Span::new(4, 8)
+            })
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = tokenize("12 + abc").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span).collect();
+        assert_eq!(
+            spans,
+            vec![Span::new(0, 2), Span::new(3, 4), Span::new(5, 8)]
+        );
+    }
+
+    #[test]
+    fn test_unknown_character() {
+        assert_eq!(
+            tokenize("1 # 2"),
+            Err(CalcError::UnknownCharacter {
+                ch: '#',
+                span: Span::new(2, 3)
+            })
+        );
+        assert_eq!(
+            tokenize("1 + ."),
+            Err(CalcError::InvalidNumber {
+                text: ".".to_string(),
+                span: Span::new(4, 5)
+            })
+        );
+    }
 }
@@ -0,0 +1,390 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::One;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BaseDimension {
+    Length,
+    Time,
+    Data,
+}
+
+impl BaseDimension {
+    const ALL: [BaseDimension; 3] = [
+        BaseDimension::Length,
+        BaseDimension::Time,
+        BaseDimension::Data,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BaseDimension::Length => "length",
+            BaseDimension::Time => "time",
+            BaseDimension::Data => "data",
+        }
+    }
+}
+
+/// The exponent of each base dimension, e.g. data/time for a transfer rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dimension([i32; 3]);
+
+impl Dimension {
+    pub const NONE: Dimension = Dimension([0; 3]);
+
+    pub fn is_none(self) -> bool {
+        self == Dimension::NONE
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<(&str, i32)> = BaseDimension::ALL
+            .iter()
+            .map(|base| (base.name(), self.0[*base as usize]))
+            .filter(|(_, exponent)| *exponent != 0)
+            .collect();
+        if terms.is_empty() {
+            return write!(f, "dimensionless");
+        }
+        write_terms(f, &terms)
+    }
+}
+
+// Writes `a*b^2/c` when there is something to put above the line and
+// `c^-1` otherwise, which is also how units are read back in.
+fn write_terms<T: fmt::Display>(f: &mut fmt::Formatter, terms: &[(T, i32)]) -> fmt::Result {
+    let write_term = |f: &mut fmt::Formatter, name: &T, exponent: i32| {
+        if exponent == 1 {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "{}^{}", name, exponent)
+        }
+    };
+    let numerator: Vec<_> = terms.iter().filter(|(_, exponent)| *exponent > 0).collect();
+    if numerator.is_empty() {
+        for (i, (name, exponent)) in terms.iter().enumerate() {
+            if i > 0 {
+                write!(f, "*")?;
+            }
+            write_term(f, name, *exponent)?;
+        }
+        return Ok(());
+    }
+    for (i, (name, exponent)) in numerator.iter().enumerate() {
+        if i > 0 {
+            write!(f, "*")?;
+        }
+        write_term(f, name, *exponent)?;
+    }
+    for (name, exponent) in terms.iter().filter(|(_, exponent)| *exponent < 0) {
+        write!(f, "/")?;
+        write_term(f, name, -exponent)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Prefix {
+    pub symbol: &'static str,
+    numer: u64,
+    denom: u64,
+}
+
+const fn prefix(symbol: &'static str, numer: u64, denom: u64) -> Prefix {
+    Prefix {
+        symbol,
+        numer,
+        denom,
+    }
+}
+
+pub const SI_PREFIXES: &[Prefix] = &[
+    prefix("E", 1_000_000_000_000_000_000, 1),
+    prefix("P", 1_000_000_000_000_000, 1),
+    prefix("T", 1_000_000_000_000, 1),
+    prefix("G", 1_000_000_000, 1),
+    prefix("M", 1_000_000, 1),
+    prefix("k", 1_000, 1),
+    prefix("c", 1, 100),
+    prefix("m", 1, 1_000),
+    prefix("u", 1, 1_000_000),
+    prefix("n", 1, 1_000_000_000),
+    prefix("p", 1, 1_000_000_000_000),
+];
+
+pub const BINARY_PREFIXES: &[Prefix] = &[
+    prefix("Ki", 1 << 10, 1),
+    prefix("Mi", 1 << 20, 1),
+    prefix("Gi", 1 << 30, 1),
+    prefix("Ti", 1 << 40, 1),
+    prefix("Pi", 1 << 50, 1),
+    prefix("Ei", 1 << 60, 1),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Prefixes {
+    None,
+    Si,
+    SiAndBinary,
+}
+
+/// A named unit, sized relative to the base unit of its dimension: the
+/// metre, the second and the byte.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BaseUnit {
+    pub name: &'static str,
+    pub dimension: BaseDimension,
+    numer: u64,
+    denom: u64,
+    prefixes: Prefixes,
+}
+
+const fn base_unit(
+    name: &'static str,
+    dimension: BaseDimension,
+    numer: u64,
+    denom: u64,
+    prefixes: Prefixes,
+) -> BaseUnit {
+    BaseUnit {
+        name,
+        dimension,
+        numer,
+        denom,
+        prefixes,
+    }
+}
+
+pub const BASE_UNITS: &[BaseUnit] = &[
+    base_unit("m", BaseDimension::Length, 1, 1, Prefixes::Si),
+    base_unit("inch", BaseDimension::Length, 254, 10_000, Prefixes::None),
+    base_unit("ft", BaseDimension::Length, 3_048, 10_000, Prefixes::None),
+    base_unit(
+        "mi",
+        BaseDimension::Length,
+        1_609_344,
+        1_000,
+        Prefixes::None,
+    ),
+    base_unit("s", BaseDimension::Time, 1, 1, Prefixes::Si),
+    base_unit("min", BaseDimension::Time, 60, 1, Prefixes::None),
+    base_unit("h", BaseDimension::Time, 3_600, 1, Prefixes::None),
+    base_unit("d", BaseDimension::Time, 86_400, 1, Prefixes::None),
+    base_unit("week", BaseDimension::Time, 604_800, 1, Prefixes::None),
+    base_unit("B", BaseDimension::Data, 1, 1, Prefixes::SiAndBinary),
+    base_unit("bit", BaseDimension::Data, 1, 8, Prefixes::SiAndBinary),
+];
+
+/// A base unit with an optional prefix, such as `km` or `GiB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitDef {
+    prefix: Option<&'static Prefix>,
+    base: &'static BaseUnit,
+}
+
+impl UnitDef {
+    pub fn lookup(name: &str) -> Option<UnitDef> {
+        if let Some(base) = BASE_UNITS.iter().find(|base| base.name == name) {
+            return Some(UnitDef { prefix: None, base });
+        }
+        BASE_UNITS.iter().find_map(|base| {
+            let symbol = name.strip_suffix(base.name)?;
+            let prefixes: &[&[Prefix]] = match base.prefixes {
+                Prefixes::None => return None,
+                Prefixes::Si => &[SI_PREFIXES],
+                Prefixes::SiAndBinary => &[SI_PREFIXES, BINARY_PREFIXES],
+            };
+            let prefix = prefixes
+                .iter()
+                .flat_map(|prefixes| prefixes.iter())
+                .find(|prefix| prefix.symbol == symbol)?;
+            Some(UnitDef {
+                prefix: Some(prefix),
+                base,
+            })
+        })
+    }
+
+    pub fn dimension(self) -> BaseDimension {
+        self.base.dimension
+    }
+
+    /// How many base units (metres, seconds, bytes) one of this unit is.
+    pub fn factor(self) -> BigRational {
+        let ratio =
+            |numer: u64, denom: u64| BigRational::new(BigInt::from(numer), BigInt::from(denom));
+        let base = ratio(self.base.numer, self.base.denom);
+        match self.prefix {
+            Some(prefix) => base * ratio(prefix.numer, prefix.denom),
+            None => base,
+        }
+    }
+}
+
+impl fmt::Display for UnitDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(prefix) = self.prefix {
+            write!(f, "{}", prefix.symbol)?;
+        }
+        write!(f, "{}", self.base.name)
+    }
+}
+
+/// The largest power of a unit a quantity can carry. Past it `m^2000` and
+/// the like are refused rather than worked out by `factor`, which would
+/// otherwise build ratios with millions of digits.
+pub const MAX_EXPONENT: i32 = 1000;
+
+fn bounded(exponent: Option<i32>) -> Option<i32> {
+    exponent.filter(|exponent| (-MAX_EXPONENT..=MAX_EXPONENT).contains(exponent))
+}
+
+/// A product of powers of units, such as `MB/s` or `m^2`. Each base
+/// dimension appears at most once, so `km*m` cannot arise: multiplying
+/// quantities converts the right-hand unit into the left-hand one.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Unit {
+    terms: Vec<(UnitDef, i32)>,
+}
+
+impl Unit {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn lookup(name: &str) -> Option<Unit> {
+        UnitDef::lookup(name).map(|def| Unit {
+            terms: vec![(def, 1)],
+        })
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        let mut dimension = Dimension::NONE;
+        for (def, exponent) in &self.terms {
+            dimension.0[def.dimension() as usize] += exponent;
+        }
+        dimension
+    }
+
+    /// How many base units one of this unit is, e.g. 1000 for `km` and
+    /// 1/3600 for `1/h`.
+    pub fn factor(&self) -> BigRational {
+        self.terms
+            .iter()
+            .fold(BigRational::one(), |factor, (def, exponent)| {
+                factor * def.factor().pow(*exponent)
+            })
+    }
+
+    /// Raises the unit to the power `n`, or returns `None` when an exponent
+    /// would go past `MAX_EXPONENT`.
+    pub fn powi(&self, n: i32) -> Option<Unit> {
+        if n == 0 {
+            return Some(Unit::none());
+        }
+        let terms = self
+            .terms
+            .iter()
+            .map(|(def, exponent)| Some((*def, bounded(exponent.checked_mul(n))?)))
+            .collect::<Option<_>>()?;
+        Some(Unit { terms })
+    }
+
+    /// Multiplies two units. Where `other` measures a dimension that `self`
+    /// already has a unit for, it is converted into that unit; the returned
+    /// factor is what a value in `other` has to be scaled by for that.
+    /// Returns `None` when an exponent would go past `MAX_EXPONENT`.
+    pub fn mul(&self, other: &Unit) -> Option<(Unit, BigRational)> {
+        let mut terms = self.terms.clone();
+        let mut scale = BigRational::one();
+        for (def, exponent) in &other.terms {
+            match terms
+                .iter_mut()
+                .find(|(existing, _)| existing.dimension() == def.dimension())
+            {
+                Some((existing, total)) => {
+                    *total = bounded(total.checked_add(*exponent))?;
+                    scale *= (def.factor() / existing.factor()).pow(*exponent);
+                }
+                None => terms.push((*def, *exponent)),
+            }
+        }
+        terms.retain(|(_, exponent)| *exponent != 0);
+        terms.sort_by_key(|(def, _)| def.dimension());
+        Some((Unit { terms }, scale))
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_terms(f, &self.terms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_traits::FromPrimitive;
+
+    fn unit(name: &str) -> Unit {
+        Unit::lookup(name).unwrap()
+    }
+
+    fn ratio(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(BigInt::from(numer), BigInt::from(denom))
+    }
+
+    #[test]
+    fn test_lookup_prefixes() {
+        assert_eq!(unit("km").factor(), ratio(1000, 1));
+        assert_eq!(unit("mm").factor(), ratio(1, 1000));
+        assert_eq!(
+            unit("GiB").factor(),
+            BigRational::from_u64(1 << 30).unwrap()
+        );
+        assert_eq!(unit("Mbit").factor(), ratio(125_000, 1));
+        assert_eq!(unit("min").factor(), ratio(60, 1));
+        assert_eq!(unit("min").to_string(), "min");
+        assert!(Unit::lookup("Kis").is_none());
+        assert!(Unit::lookup("kh").is_none());
+        assert!(Unit::lookup("x").is_none());
+    }
+
+    #[test]
+    fn test_mul_converts_shared_dimensions() {
+        let (rate, scale) = unit("MB").mul(&unit("s").powi(-1).unwrap()).unwrap();
+        assert_eq!(rate.to_string(), "MB/s");
+        assert_eq!(scale, ratio(1, 1));
+        assert_eq!(rate.dimension().to_string(), "data/time");
+
+        let (ratio_unit, scale) = unit("km").mul(&unit("m").powi(-1).unwrap()).unwrap();
+        assert!(ratio_unit.is_none());
+        assert_eq!(scale, ratio(1000, 1));
+
+        let (area, scale) = unit("m").mul(&unit("cm")).unwrap();
+        assert_eq!(area.to_string(), "m^2");
+        assert_eq!(scale, ratio(1, 100));
+        assert_eq!(unit("s").powi(-1).unwrap().to_string(), "s^-1");
+    }
+
+    #[test]
+    fn test_exponents_are_bounded() {
+        let square = unit("m").powi(2).unwrap();
+        assert_eq!(square.powi(500).unwrap().to_string(), "m^1000");
+        assert_eq!(square.powi(501), None);
+        assert_eq!(square.powi(2_000_000_000), None);
+        assert_eq!(square.powi(0), Some(Unit::none()));
+
+        let largest = unit("m").powi(MAX_EXPONENT).unwrap();
+        assert_eq!(largest.mul(&unit("cm")), None);
+        let (back, scale) = largest.mul(&unit("km").powi(-1).unwrap()).unwrap();
+        assert_eq!(back.to_string(), "m^999");
+        assert_eq!(scale, ratio(1, 1000));
+    }
+}
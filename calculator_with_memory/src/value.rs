@@ -0,0 +1,548 @@
+use crate::expression::Expr;
+use crate::number::{Number, NumberError};
+use crate::units::{Dimension, Unit};
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Why an operation on values failed; the evaluator attaches the span.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    Incompatible(Dimension, Dimension),
+    Domain(&'static str),
+    Overflow,
+    /// The kind of value that was expected, and the kind that was found.
+    Mismatch(&'static str, &'static str),
+    /// Two lists that were combined element by element differ in shape.
+    Shape(Shape, Shape),
+    /// An element of a list divisor is zero.
+    DivisionByZero,
+}
+
+/// The lengths along each axis of a value: none for a single value, one
+/// for a list and two, rows then columns, for a matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shape(pub Vec<usize>);
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lengths: Vec<String> = self.0.iter().map(usize::to_string).collect();
+        match self.0.len() {
+            0 => write!(f, "single value"),
+            1 => write!(f, "list of {}", self.0[0]),
+            2 => write!(f, "{} matrix", lengths.join("x")),
+            _ => write!(f, "{} array", lengths.join("x")),
+        }
+    }
+}
+
+impl From<NumberError> for ValueError {
+    fn from(error: NumberError) -> Self {
+        match error {
+            NumberError::Overflow => ValueError::Overflow,
+            NumberError::Domain(message) => ValueError::Domain(message),
+        }
+    }
+}
+
+/// What an expression evaluates to: a plain number, a number measured in
+/// some unit, the outcome of a comparison, an expression produced by
+/// `diff` or `simplify`, a list of values, or a piece of text such as a
+/// file name. A quantity whose units cancel
+/// out becomes a plain number. A matrix is a list of rows, each a list of
+/// the same length.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(Number),
+    Quantity { value: Number, unit: Unit },
+    Bool(bool),
+    Expr(Box<Expr>),
+    List(Vec<Value>),
+    Text(String),
+}
+
+impl From<Number> for Value {
+    fn from(number: Number) -> Self {
+        Value::Number(number)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(Number::Float(value))
+    }
+}
+
+impl Value {
+    pub fn quantity(value: Number, unit: Unit) -> Value {
+        if unit.is_none() {
+            Value::Number(value)
+        } else {
+            Value::Quantity { value, unit }
+        }
+    }
+
+    /// The magnitude, in whatever unit the value carries.
+    pub fn magnitude(&self) -> Result<&Number, ValueError> {
+        match self {
+            Value::Number(value) | Value::Quantity { value, .. } => Ok(value),
+            _ => Err(ValueError::Mismatch("number", self.kind())),
+        }
+    }
+
+    pub fn as_number(&self) -> Option<&Number> {
+        match self {
+            Value::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The number itself, for operations that take neither units nor
+    /// booleans, such as bitwise operators and built-in functions.
+    pub fn plain_number(&self) -> Result<&Number, ValueError> {
+        match self {
+            Value::Number(value) => Ok(value),
+            Value::Quantity { .. } => {
+                Err(ValueError::Incompatible(self.dimension(), Dimension::NONE))
+            }
+            _ => Err(ValueError::Mismatch("number", self.kind())),
+        }
+    }
+
+    pub fn as_text(&self) -> Result<&str, ValueError> {
+        match self {
+            Value::Text(text) => Ok(text),
+            _ => Err(ValueError::Mismatch("string", self.kind())),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, ValueError> {
+        match self {
+            Value::Bool(value) => Ok(*value),
+            _ => Err(ValueError::Mismatch("boolean", self.kind())),
+        }
+    }
+
+    /// What sort of value this is, as named in error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Quantity { .. } => "quantity",
+            Value::Bool(_) => "boolean",
+            Value::Expr(_) => "expression",
+            Value::List(_) if self.shape().0.len() == 2 => "matrix",
+            Value::List(_) => "list",
+            Value::Text(_) => "string",
+        }
+    }
+
+    /// The shape of a list, taken from its first element; list literals
+    /// and element-wise operations keep every element the same shape.
+    pub fn shape(&self) -> Shape {
+        let mut lengths = Vec::new();
+        let mut value = self;
+        while let Value::List(items) = value {
+            lengths.push(items.len());
+            match items.first() {
+                Some(first) => value = first,
+                None => break,
+            }
+        }
+        Shape(lengths)
+    }
+
+    /// Applies `f` to each element of a list, keeping its shape, or to the
+    /// value itself when it is not a list.
+    pub fn map_elements<E>(
+        &self,
+        f: &mut impl FnMut(&Value) -> Result<Value, E>,
+    ) -> Result<Value, E> {
+        match self {
+            Value::List(items) => items
+                .iter()
+                .map(|item| item.map_elements(f))
+                .collect::<Result<_, _>>()
+                .map(Value::List),
+            _ => f(self),
+        }
+    }
+
+    // Lists combine element by element and must have the same shape; a
+    // single value combines with every element of a list.
+    fn zip_with(
+        &self,
+        other: &Value,
+        op: &dyn Fn(&Value, &Value) -> Result<Value, ValueError>,
+    ) -> Result<Value, ValueError> {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                if self.shape() != other.shape() {
+                    return Err(ValueError::Shape(self.shape(), other.shape()));
+                }
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| a.zip_with(b, op))
+                    .collect::<Result<_, _>>()
+                    .map(Value::List)
+            }
+            (Value::List(_), _) => self.map_elements(&mut |a| op(a, other)),
+            (_, Value::List(_)) => other.map_elements(&mut |b| op(self, b)),
+            _ => op(self, other),
+        }
+    }
+
+    pub fn unit(&self) -> Unit {
+        match self {
+            Value::Quantity { unit, .. } => unit.clone(),
+            _ => Unit::none(),
+        }
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        match self {
+            Value::Quantity { unit, .. } => unit.dimension(),
+            _ => Dimension::NONE,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude().is_ok_and(Number::is_zero)
+    }
+
+    /// Whether the value can be written out and read back, which NaN and
+    /// the infinities cannot.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Value::List(items) => items.iter().all(Value::is_finite),
+            _ => self.magnitude().map_or(true, Number::is_finite),
+        }
+    }
+
+    fn map_number(
+        &self,
+        f: impl FnOnce(&Number) -> Result<Number, NumberError>,
+    ) -> Result<Value, ValueError> {
+        Ok(Value::quantity(f(self.magnitude()?)?, self.unit()))
+    }
+
+    /// `other`'s magnitude expressed in this value's unit, for operations
+    /// that need both sides to measure the same thing.
+    fn aligned(&self, other: &Value) -> Result<Number, ValueError> {
+        self.magnitude()?;
+        let magnitude = other.magnitude()?;
+        if self.dimension() != other.dimension() {
+            return Err(ValueError::Incompatible(
+                self.dimension(),
+                other.dimension(),
+            ));
+        }
+        if self.unit() == other.unit() {
+            return Ok(magnitude.clone());
+        }
+        let scale = other.unit().factor() / self.unit().factor();
+        Ok(magnitude.mul(&Number::Rational(scale))?)
+    }
+
+    pub fn add(&self, other: &Value) -> Result<Value, ValueError> {
+        self.zip_with(other, &|a, b| {
+            let b = a.aligned(b)?;
+            a.map_number(|value| value.add(&b))
+        })
+    }
+
+    pub fn sub(&self, other: &Value) -> Result<Value, ValueError> {
+        self.zip_with(other, &|a, b| {
+            let b = a.aligned(b)?;
+            a.map_number(|value| value.sub(&b))
+        })
+    }
+
+    pub fn rem(&self, other: &Value) -> Result<Value, ValueError> {
+        self.zip_with(other, &|a, b| {
+            let b = a.aligned(b)?;
+            if b.is_zero() {
+                return Err(ValueError::DivisionByZero);
+            }
+            a.map_number(|value| value.rem(&b))
+        })
+    }
+
+    pub fn mul(&self, other: &Value) -> Result<Value, ValueError> {
+        self.zip_with(other, &|a, b| {
+            let (unit, scale) = a.unit().mul(&b.unit()).ok_or(UNIT_EXPONENT)?;
+            let value = a.magnitude()?.mul(b.magnitude()?)?;
+            Ok(Value::quantity(scaled(value, scale)?, unit))
+        })
+    }
+
+    pub fn div(&self, other: &Value) -> Result<Value, ValueError> {
+        self.zip_with(other, &|a, b| {
+            if b.is_zero() {
+                return Err(ValueError::DivisionByZero);
+            }
+            let (unit, scale) = b
+                .unit()
+                .powi(-1)
+                .and_then(|inverse| a.unit().mul(&inverse))
+                .ok_or(UNIT_EXPONENT)?;
+            let value = a.magnitude()?.div(b.magnitude()?)?;
+            Ok(Value::quantity(scaled(value, scale)?, unit))
+        })
+    }
+
+    pub fn neg(&self) -> Result<Value, ValueError> {
+        self.map_elements(&mut |value| value.map_number(Number::neg))
+    }
+
+    /// Applies a bitwise operation, which only makes sense on plain numbers.
+    pub fn bitwise(
+        &self,
+        other: &Value,
+        op: impl Fn(i64, i64) -> Result<i64, NumberError>,
+    ) -> Result<Value, ValueError> {
+        self.zip_with(other, &|a, b| {
+            let (a, b) = (a.plain_number()?, b.plain_number()?);
+            Ok(Value::Number(a.bitwise(b, &op)?))
+        })
+    }
+
+    pub fn bit_not(&self) -> Result<Value, ValueError> {
+        self.map_elements(&mut |value| Ok(Value::Number(value.plain_number()?.bit_not()?)))
+    }
+
+    /// Orders two values that measure the same thing; `None` if either is
+    /// NaN, which makes every comparison with it false.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, ValueError> {
+        let other = self.aligned(other)?;
+        Ok(self.magnitude()?.partial_cmp(&other))
+    }
+
+    pub fn equals(&self, other: &Value) -> Result<bool, ValueError> {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+            (Value::Text(a), Value::Text(b)) => Ok(a == b),
+            (Value::List(a), Value::List(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (a, b) in a.iter().zip(b) {
+                    if !a.equals(b)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(self.compare(other)? == Some(Ordering::Equal)),
+        }
+    }
+
+    /// Quantities can only be raised to whole numbers, and nothing can be
+    /// raised to a quantity.
+    pub fn pow(&self, exponent: &Value) -> Result<Value, ValueError> {
+        self.zip_with(exponent, &Value::pow_single)
+    }
+
+    fn pow_single(&self, exponent: &Value) -> Result<Value, ValueError> {
+        let power = exponent.plain_number()?;
+        match self {
+            Value::Quantity { unit, .. } => {
+                let exact = power.to_rational();
+                let n = exact
+                    .is_integer()
+                    .then(|| exact.to_integer().to_i32())
+                    .flatten()
+                    .ok_or(ValueError::Domain("a quantity needs a whole exponent"))?;
+                // The unit is checked first so that a huge exponent is turned
+                // down before the magnitude is raised to it.
+                let unit = unit.powi(n).ok_or(UNIT_EXPONENT)?;
+                Ok(Value::quantity(self.magnitude()?.pow(power)?, unit))
+            }
+            _ => Ok(Value::Number(self.magnitude()?.pow(power)?)),
+        }
+    }
+
+    /// Expresses the value in `target`, which must measure the same thing.
+    pub fn convert(&self, target: &Unit) -> Result<Value, ValueError> {
+        if let Value::List(_) = self {
+            return self.map_elements(&mut |value| value.convert(target));
+        }
+        if self.dimension() != target.dimension() {
+            return Err(ValueError::Incompatible(
+                self.dimension(),
+                target.dimension(),
+            ));
+        }
+        let target = Value::quantity(Number::Rational(BigRational::one()), target.clone());
+        let value = target.aligned(self)?;
+        Ok(Value::quantity(value, target.unit()))
+    }
+}
+
+const UNIT_EXPONENT: ValueError = ValueError::Domain("the unit exponent is too large");
+
+// Unit conversions leave an integer alone when the factor is one, so that
+// integer mode does not turn plain arithmetic into rationals.
+
+fn scaled(value: Number, scale: BigRational) -> Result<Number, NumberError> {
+    if scale.is_one() {
+        Ok(value)
+    } else {
+        value.mul(&Number::Rational(scale))
+    }
+}
+
+// A magnitude printed as a fraction is parenthesized so that the text reads
+// back as the same quantity: `(1/3) km` rather than `1 / (3 km)`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Expr(expr) => write!(f, "{}", expr),
+            Value::Text(text) => write!(f, "\"{}\"", text),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Quantity { value, unit } => {
+                let text = value.to_string();
+                if text.contains('/') {
+                    write!(f, "({}) {}", text, unit)
+                } else {
+                    write!(f, "{} {}", text, unit)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantity(value: f64, unit: &str) -> Value {
+        Value::quantity(Number::Float(value), Unit::lookup(unit).unwrap())
+    }
+
+    #[test]
+    fn test_add_converts_to_left_unit() {
+        let sum = quantity(5.0, "km").add(&quantity(300.0, "m")).unwrap();
+        assert_eq!(sum.to_string(), "5.3 km");
+        assert_eq!(
+            quantity(5.0, "km").add(&quantity(1.0, "s")),
+            Err(ValueError::Incompatible(
+                Unit::lookup("km").unwrap().dimension(),
+                Unit::lookup("s").unwrap().dimension()
+            ))
+        );
+        assert!(quantity(5.0, "km").add(&Value::from(1.0)).is_err());
+    }
+
+    #[test]
+    fn test_mul_div_and_convert() {
+        let rate = quantity(200.0, "MB").div(&quantity(1.0, "s")).unwrap();
+        assert_eq!(rate.to_string(), "200 MB/s");
+        let time = quantity(3.0, "GiB").div(&rate).unwrap();
+        assert_eq!(time.to_string(), "16.10612736 s");
+        assert_eq!(
+            quantity(1.0, "km").div(&quantity(1.0, "m")),
+            Ok(Value::from(1000.0))
+        );
+        let area = quantity(2.0, "m").pow(&Value::from(2.0)).unwrap();
+        assert_eq!(area.to_string(), "4 m^2");
+        assert!(quantity(2.0, "m").pow(&Value::from(0.5)).is_err());
+        let minutes = time.convert(&Unit::lookup("min").unwrap()).unwrap();
+        assert!((minutes.magnitude().unwrap().to_f64() - 0.268_435_456).abs() < 1e-12);
+        assert!(time.convert(&Unit::lookup("m").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_unit_exponents_are_bounded() {
+        let area = quantity(1.0, "m").pow(&Value::from(2.0)).unwrap();
+        assert_eq!(area.pow(&Value::from(2e9)), Err(UNIT_EXPONENT));
+        assert!(area.pow(&Value::from(1e10)).is_err());
+        let largest = quantity(1.0, "m").pow(&Value::from(1000.0)).unwrap();
+        assert_eq!(largest.to_string(), "1 m^1000");
+        assert_eq!(largest.mul(&quantity(1.0, "m")), Err(UNIT_EXPONENT));
+        let smaller = largest.div(&quantity(1.0, "km")).unwrap();
+        assert_eq!(smaller.to_string(), "0.001 m^999");
+    }
+
+    #[test]
+    fn test_lists_combine_element_by_element() {
+        let list = |items: &[f64]| Value::List(items.iter().map(|&x| Value::from(x)).collect());
+        let matrix = Value::List(vec![list(&[1.0, 2.0]), list(&[3.0, 4.0])]);
+        assert_eq!(matrix.shape(), Shape(vec![2, 2]));
+        assert_eq!(matrix.kind(), "matrix");
+        assert_eq!(list(&[1.0]).kind(), "list");
+        assert_eq!(
+            list(&[1.0, 2.0])
+                .add(&list(&[10.0, 20.0]))
+                .unwrap()
+                .to_string(),
+            "[11, 22]"
+        );
+        assert_eq!(
+            matrix.mul(&Value::from(2.0)).unwrap().to_string(),
+            "[[2, 4], [6, 8]]"
+        );
+        assert_eq!(
+            Value::from(1.0)
+                .div(&list(&[2.0, 4.0]))
+                .unwrap()
+                .to_string(),
+            "[0.5, 0.25]"
+        );
+        assert_eq!(
+            list(&[2.0, 3.0])
+                .pow(&Value::from(2.0))
+                .unwrap()
+                .to_string(),
+            "[4, 9]"
+        );
+        assert_eq!(matrix.neg().unwrap().to_string(), "[[-1, -2], [-3, -4]]");
+        assert_eq!(
+            list(&[1.0, 2.0]).add(&matrix),
+            Err(ValueError::Shape(Shape(vec![2]), Shape(vec![2, 2])))
+        );
+        assert_eq!(
+            list(&[1.0, 2.0]).div(&list(&[1.0, 0.0])),
+            Err(ValueError::DivisionByZero)
+        );
+        assert_eq!(list(&[1.0, 2.0]).equals(&list(&[1.0, 2.0])), Ok(true));
+        assert_eq!(list(&[1.0, 2.0]).equals(&list(&[1.0])), Ok(false));
+        let lengths = quantity(1.0, "km").mul(&list(&[1.0, 2.5])).unwrap();
+        let lengths = lengths.convert(&Unit::lookup("m").unwrap()).unwrap();
+        assert_eq!(lengths.to_string(), "[1000 m, 2500 m]");
+        assert_eq!(Shape(vec![2, 3]).to_string(), "2x3 matrix");
+        assert_eq!(Shape(vec![]).to_string(), "single value");
+    }
+
+    #[test]
+    fn test_compare_and_booleans() {
+        let km = quantity(1.0, "km");
+        assert_eq!(
+            km.compare(&quantity(999.0, "m")),
+            Ok(Some(Ordering::Greater))
+        );
+        assert_eq!(km.equals(&quantity(1000.0, "m")), Ok(true));
+        assert!(km.compare(&quantity(1.0, "s")).is_err());
+        assert_eq!(Value::from(1.0).compare(&Value::from(f64::NAN)), Ok(None));
+        assert_eq!(Value::Bool(true).equals(&Value::Bool(true)), Ok(true));
+        assert_eq!(
+            Value::Bool(true).add(&Value::from(1.0)),
+            Err(ValueError::Mismatch("number", "boolean"))
+        );
+        assert_eq!(
+            Value::from(1.0).as_bool(),
+            Err(ValueError::Mismatch("boolean", "number"))
+        );
+    }
+}
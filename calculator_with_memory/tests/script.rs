@@ -0,0 +1,118 @@
+//! Runs the calculator binary on script files, the way `--file` is used from
+//! the shell.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+struct Script {
+    path: PathBuf,
+}
+
+impl Script {
+    // Each test writes its own file so the tests can run side by side.
+    fn new(name: &str, lines: &str) -> Self {
+        let file = format!("calculator_with_memory_{}_{}.calc", process::id(), name);
+        let path = env::temp_dir().join(file);
+        fs::write(&path, lines).unwrap();
+        Script { path }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_calculator_with_memory"))
+            .arg("--file")
+            .arg(&self.path)
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn source(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn test_skips_blank_lines_and_comments() {
+    let script = Script::new(
+        "comments",
+        "# totals\n1 + 2\n\n   # indented\n  \nx = 4\nx * 2\n",
+    );
+    let output = script.run(&[]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), " => 3\n => 4\n => 8\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn test_errors_name_the_script_and_line() {
+    let script = Script::new("errors", "# divide\n\n1 / 0\n2 + 2\nnope + 1\n");
+    let output = script.run(&[]);
+    let errors = stderr(&output);
+    let prefixes: Vec<&str> = errors
+        .lines()
+        .filter(|line| line.starts_with(&script.source()))
+        .collect();
+    assert_eq!(
+        prefixes,
+        [
+            format!("{}:3: Error: Division by zero", script.source()),
+            format!("{}:5: Error: Unknown memory slot 'nope'", script.source()),
+        ]
+    );
+}
+
+#[test]
+fn test_continues_after_an_error() {
+    let script = Script::new("continue", "1 / 0\n2 + 2\n");
+    let output = script.run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), " => 4\n");
+}
+
+#[test]
+fn test_fail_fast_stops_at_the_first_error() {
+    let script = Script::new("fail_fast", "1 + 1\n1 / 0\n2 + 2\n3 / 0\n");
+    let output = script.run(&["--fail-fast"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), " => 2\n");
+    assert_eq!(stderr(&output).matches("Division by zero").count(), 1);
+}
+
+#[test]
+fn test_exit_status() {
+    let clean = Script::new("clean", "1 + 1\n");
+    assert!(clean.run(&[]).status.success());
+    assert!(clean.run(&["--fail-fast"]).status.success());
+    let missing = Script::new("missing", "");
+    fs::remove_file(&missing.path).unwrap();
+    let output = missing.run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with(&format!("Error reading {}", missing.source())));
+}
+
+#[test]
+fn test_failed_memory_load_leaves_the_file_alone() {
+    let saved = "let A = 5\nlet B = 2 +\nlet C = 7\n";
+    let memory = Script::new("memory", saved);
+    let script = Script::new("memory_script", "A\nx = 1\n");
+    let output = script.run(&["--memory-file", &memory.source()]);
+    assert_eq!(stdout(&output), " => 5\n => 1\n");
+    assert!(stderr(&output).starts_with(&format!("Error loading {}", memory.source())));
+    assert_eq!(fs::read_to_string(&memory.path).unwrap(), saved);
+}